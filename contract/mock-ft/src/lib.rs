@@ -0,0 +1,165 @@
+//! NEP-141 token for testing the stream contract's transfer callbacks. It can
+//! be set to fail every `ft_transfer`, burn extra gas on it or keep a fee from
+//! it, so the rollback paths of the resolve callbacks can be exercised. Never
+//! deploy it for real tokens: anyone can mint and change its behaviour.
+
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+use near_contract_standards::fungible_token::FungibleToken;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{
+    assert_one_yocto, env, log, near_bindgen, require, AccountId, Balance, PanicOnDefault,
+    PromiseOrValue,
+};
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    token: FungibleToken,
+    fail_transfers: bool,
+    extra_gas: u64, // burned by every `ft_transfer`
+    fee_bps: u32,   // kept by the token from every `ft_transfer`
+}
+
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new() -> Self {
+        Self {
+            token: FungibleToken::new(b"t".to_vec()),
+            fail_transfers: false,
+            extra_gas: 0,
+            fee_bps: 0,
+        }
+    }
+
+    pub fn mint(&mut self, account_id: AccountId, amount: U128) {
+        self.internal_register(&account_id);
+        self.token.internal_deposit(&account_id, amount.0);
+    }
+
+    pub fn set_behaviour(&mut self, fail_transfers: bool, extra_gas: U64, fee_bps: u32) {
+        require!(fee_bps <= 10_000, "Invalid fee bps");
+        self.fail_transfers = fail_transfers;
+        self.extra_gas = extra_gas.0;
+        self.fee_bps = fee_bps;
+        log!(
+            "Behaviour set: {} {} {}",
+            fail_transfers,
+            extra_gas.0,
+            fee_bps
+        );
+    }
+
+    pub fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        assert_one_yocto();
+        require!(!self.fail_transfers, "Transfer failed");
+        self.internal_burn_gas();
+
+        let sender_id = env::predecessor_account_id();
+        let fee = amount.0 * Balance::from(self.fee_bps) / 10_000;
+        self.internal_register(&receiver_id);
+        self.token
+            .internal_transfer(&sender_id, &receiver_id, amount.0 - fee, memo);
+        if fee > 0 {
+            self.token.internal_withdraw(&sender_id, fee);
+        }
+    }
+
+    // not affected by the behaviour, the stream contract only receives with it
+    pub fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.internal_register(&receiver_id);
+        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
+    }
+
+    #[private]
+    pub fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        self.token
+            .ft_resolve_transfer(sender_id, receiver_id, amount)
+    }
+
+    pub fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
+    }
+
+    pub fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
+    }
+}
+
+impl Contract {
+    // accounts are registered on first use, there is no storage deposit
+    fn internal_register(&mut self, account_id: &AccountId) {
+        if self.token.accounts.get(account_id).is_none() {
+            self.token.internal_register_account(account_id);
+        }
+    }
+
+    fn internal_burn_gas(&self) {
+        let target = env::used_gas().0 + self.extra_gas;
+        let mut hash = env::sha256(b"burn");
+        while env::used_gas().0 < target {
+            hash = env::sha256(&hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(1);
+        testing_env!(builder.build());
+    }
+
+    fn minted() -> Contract {
+        set_context(accounts(0));
+        let mut contract = Contract::new();
+        contract.mint(accounts(0), U128(1_000));
+        contract
+    }
+
+    #[test]
+    fn transfer_with_fee() {
+        let mut contract = minted();
+        contract.set_behaviour(false, U64(0), 100);
+        contract.ft_transfer(accounts(1), U128(500), None);
+        assert_eq!(contract.ft_balance_of(accounts(0)), U128(500));
+        assert_eq!(contract.ft_balance_of(accounts(1)), U128(495));
+        assert_eq!(contract.ft_total_supply(), U128(995));
+    }
+
+    #[test]
+    fn transfer_burns_gas() {
+        let mut contract = minted();
+        contract.set_behaviour(false, U64(5_000_000_000_000), 0);
+        let before = env::used_gas().0;
+        contract.ft_transfer(accounts(1), U128(500), None);
+        assert!(env::used_gas().0 - before >= 5_000_000_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Transfer failed")]
+    fn transfer_fails() {
+        let mut contract = minted();
+        contract.set_behaviour(true, U64(0), 0);
+        contract.ft_transfer(accounts(1), U128(500), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::*;
+
+/// Account that can withdraw a stream's tokens for itself once the receiver
+/// has not withdrawn for `inactivity_period` seconds.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Backup {
+    account_id: AccountId,
+    inactivity_period: u32,
+    last_active: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Lets `operator` act for the caller on the stream: withdraw for the
+    // receiver, pause and resume for the sender. The storage is paid from the
+    // attached deposit and the rest is refunded.
+    #[payable]
+    pub fn approve(&mut self, stream_id: U64, operator: AccountId) {
+        let stream = self.internal_get_stream(stream_id.0);
+        let approver = env::predecessor_account_id();
+        require!(
+            approver == stream.sender || approver == stream.receiver,
+            "Only the sender or the receiver can approve operators"
+        );
+        require!(approver != operator, "Cannot approve yourself");
+
+        let initial_storage = env::storage_usage();
+        self.approvals
+            .insert(&(stream_id.0, approver.clone(), operator.clone()));
+        log_event!(
+            "Operator approved: {} {} {}",
+            stream_id.0,
+            approver,
+            operator
+        );
+
+        let cost = Balance::from(env::storage_usage().saturating_sub(initial_storage))
+            * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() >= cost,
+            "The amount provided is not enough for the approval storage"
+        );
+        if env::attached_deposit() > cost {
+            Promise::new(approver).transfer(env::attached_deposit() - cost);
+        }
+    }
+
+    // Removes an operator approved by the caller and refunds the storage freed
+    pub fn revoke(&mut self, stream_id: U64, operator: AccountId) {
+        let approver = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        require!(
+            self.approvals
+                .remove(&(stream_id.0, approver.clone(), operator.clone())),
+            "Operator is not approved"
+        );
+        log_event!(
+            "Operator revoked: {} {} {}",
+            stream_id.0,
+            approver,
+            operator
+        );
+
+        let refund = Balance::from(initial_storage.saturating_sub(env::storage_usage()))
+            * env::storage_byte_cost();
+        if refund > 0 {
+            Promise::new(approver).transfer(refund);
+        }
+    }
+
+    // Designates a backup beneficiary for the caller's stream, `None` removes it
+    pub fn set_backup(
+        &mut self,
+        stream_id: U64,
+        account_id: Option<AccountId>,
+        inactivity_period: U64,
+    ) {
+        let stream = self.internal_get_stream(stream_id.0);
+        require!(
+            env::predecessor_account_id() == stream.receiver,
+            "Only the receiver can set a backup"
+        );
+
+        match account_id {
+            Some(account_id) => {
+                require!(
+                    account_id != stream.receiver,
+                    "Backup cannot be the receiver"
+                );
+                require!(inactivity_period.0 > 0, "Inactivity period cannot be zero");
+                require!(
+                    !self.withdraw_callbacks.contains(&stream_id.0),
+                    "Cannot back up a stream with a withdraw callback"
+                );
+                self.backups.insert(
+                    &stream_id.0,
+                    &Backup {
+                        account_id,
+                        inactivity_period: compact_timestamp(inactivity_period.0),
+                        last_active: compact_timestamp(current_time()),
+                    },
+                );
+                log_event!("Backup set: {}", stream_id.0);
+            }
+            None => {
+                self.backups.remove(&stream_id.0);
+                log_event!("Backup removed: {}", stream_id.0);
+            }
+        }
+    }
+
+    pub fn is_approved(&self, stream_id: U64, approver: AccountId, operator: AccountId) -> bool {
+        self.approvals.contains(&(stream_id.0, approver, operator))
+    }
+}
+
+impl Contract {
+    /// Whether `account_id` is `approver`, an operator approved by it on the stream
+    /// or an operator of the approver's profile the stream was created from.
+    pub(crate) fn internal_acts_for(
+        &self,
+        stream_id: u64,
+        approver: &AccountId,
+        account_id: &AccountId,
+    ) -> bool {
+        account_id == approver
+            || self
+                .approvals
+                .contains(&(stream_id, approver.clone(), account_id.clone()))
+            || self.internal_is_profile_operator(stream_id, approver, account_id)
+    }
+
+    /// Whether `account_id` is the stream's backup and the receiver has been inactive for long enough.
+    pub(crate) fn internal_is_active_backup(&self, stream_id: u64, account_id: &AccountId) -> bool {
+        self.backups.get(&stream_id).is_some_and(|backup| {
+            &backup.account_id == account_id
+                && current_time()
+                    >= u64::from(backup.last_active) + u64::from(backup.inactivity_period)
+        })
+    }
+
+    /// Restarts the inactivity period of the stream's backup.
+    pub(crate) fn internal_touch_backup(&mut self, stream_id: u64) {
+        if let Some(mut backup) = self.backups.get(&stream_id) {
+            backup.last_active = compact_timestamp(current_time());
+            self.backups.insert(&stream_id, &backup);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn operators_act_for_approver() {
+        let mut contract = Contract::new();
+
+        // alice -> bob, charlie operates for both
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(0), NEAR, 0);
+        contract.approve(U64(1), accounts(2));
+        set_context(accounts(1), NEAR, 0);
+        contract.approve(U64(1), accounts(2));
+
+        // charlie pauses and withdraws, the tokens go to bob
+        set_context(accounts(2), 0, 5);
+        contract.pause(U64(1), None);
+        contract.withdraw(U64(1), None);
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.balance, 6);
+        assert!(stream.is_paused);
+
+        set_context(accounts(1), 0, 6);
+        contract.revoke(U64(1), accounts(2));
+        assert!(!contract.is_approved(U64(1), accounts(1), accounts(2)));
+        assert!(contract.is_approved(U64(1), accounts(0), accounts(2)));
+    }
+
+    #[test]
+    fn backup_withdraws_after_inactivity() {
+        let mut contract = Contract::new();
+
+        // alice -> bob, dave is bob's backup after 3 seconds
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), 0, 0);
+        contract.set_backup(U64(1), Some(accounts(3)), U64(3));
+
+        // bob withdraws at 2, dave takes over at 5
+        set_context(accounts(1), 0, 2);
+        contract.withdraw(U64(1), None);
+        set_context(accounts(3), 0, 5);
+        contract.withdraw(U64(1), None);
+        assert_eq!(contract.internal_get_stream(1).balance, 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "You dont have permissions to withdraw")]
+    fn backup_waits_for_inactivity() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), 0, 0);
+        contract.set_backup(U64(1), Some(accounts(3)), U64(3));
+
+        set_context(accounts(1), 0, 2);
+        contract.withdraw(U64(1), None);
+        set_context(accounts(3), 0, 4);
+        contract.withdraw(U64(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "You dont have permissions to withdraw")]
+    fn revoked_operator_cannot_withdraw() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), NEAR, 0);
+        contract.approve(U64(1), accounts(2));
+        contract.revoke(U64(1), accounts(2));
+
+        set_context(accounts(2), 0, 5);
+        contract.withdraw(U64(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the approval storage")]
+    fn approval_needs_storage_deposit() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), 0, 0);
+        contract.approve(U64(1), accounts(2));
+    }
+}
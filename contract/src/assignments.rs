@@ -0,0 +1,210 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    // Proposes to assign the rest of the stream, accrued but not withdrawn
+    // tokens included, to `assignee`. Takes effect when the sender approves it
+    // and replaces any previous proposal.
+    pub fn propose_assignment(&mut self, stream_id: U64, assignee: AccountId) {
+        let id: u64 = stream_id.0;
+        let stream = self.internal_get_stream(id);
+
+        require!(
+            env::predecessor_account_id() == stream.receiver,
+            "Only the receiver can assign the stream"
+        );
+        require!(assignee != stream.receiver, "Cannot assign to yourself");
+        require!(assignee != stream.sender, "Cannot assign to the sender");
+        self.internal_check_assignable(&stream);
+
+        self.receivable_assignments.insert(&id, &assignee);
+        log_event!("Assignment proposed: {} {}", id, assignee);
+    }
+
+    pub fn cancel_assignment(&mut self, stream_id: U64) {
+        let stream = self.internal_get_stream(stream_id.0);
+        require!(
+            env::predecessor_account_id() == stream.receiver,
+            "Only the receiver can assign the stream"
+        );
+        require!(
+            self.receivable_assignments.remove(&stream_id.0).is_some(),
+            "No pending assignment"
+        );
+        log_event!("Assignment cancelled: {}", stream_id.0);
+    }
+
+    // Approves the pending assignment of the stream, `assignee` must match the
+    // proposal so that it cannot be changed in the meantime.
+    pub fn approve_assignment(&mut self, stream_id: U64, assignee: AccountId) {
+        let id: u64 = stream_id.0;
+        let mut stream = self.internal_get_stream(id);
+
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "Only the sender can approve the assignment"
+        );
+        require!(
+            self.receivable_assignments.get(&id) == Some(assignee.clone()),
+            "Assignment does not match the proposal"
+        );
+        self.internal_check_assignable(&stream);
+        self.internal_check_sender(&assignee, &stream.sender);
+
+        // move the stream from the receiver's index to the assignee's
+        if let Some(mut receiver_ids) = self.receiver_streams.get(&stream.receiver) {
+            receiver_ids.remove(&id);
+            self.receiver_streams
+                .insert(&stream.receiver, &receiver_ids);
+        }
+        let receiver = std::mem::replace(&mut stream.receiver, assignee);
+        self.internal_save_stream(&stream);
+        self.internal_index_received_stream(&stream);
+        self.accounts.insert(&stream.receiver);
+
+        // the receiver's settings of the stream do not carry over
+        self.receivable_assignments.remove(&id);
+        self.backups.remove(&id);
+        self.withdraw_callbacks.remove(&id);
+
+        log_event!("Stream assigned: {} {} {}", id, receiver, stream.receiver);
+    }
+
+    pub fn get_pending_assignment(&self, stream_id: U64) -> Option<AccountId> {
+        self.receivable_assignments.get(&stream_id.0)
+    }
+}
+
+impl Contract {
+    /// Checks the stream still has tokens to receive and is not paired, netted,
+    /// withheld from or funding a child stream, all of which were set up for
+    /// the current receiver.
+    fn internal_check_assignable(&self, stream: &Stream) {
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.withdraw_time < stream.end_time,
+            "Stream has already been withdrawn"
+        );
+        require!(
+            self.stream_pairs.get(&stream.id).is_none(),
+            "Cannot assign a paired stream"
+        );
+        require!(
+            !self.netting_streams.contains(&stream.id),
+            "Cannot assign a netted stream"
+        );
+        require!(
+            self.withholdings.get(&stream.id).is_none(),
+            "Cannot assign a stream with withholding"
+        );
+        require!(
+            self.child_streams.get(&stream.id).is_none(),
+            "Cannot assign a stream with a child stream"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::views::StreamRole;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn assignment_approved_by_sender() {
+        let mut contract = Contract::new();
+
+        // alice -> bob, bob assigns to charlie at 5
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), 0, 5);
+        contract.propose_assignment(stream_id, accounts(2));
+        assert_eq!(
+            contract.get_pending_assignment(stream_id),
+            Some(accounts(2))
+        );
+        assert_eq!(contract.get_stream(stream_id).receiver, accounts(1));
+
+        set_context(accounts(0), 0, 5);
+        contract.approve_assignment(stream_id, accounts(2));
+        assert_eq!(contract.get_stream(stream_id).receiver, accounts(2));
+        assert_eq!(contract.get_pending_assignment(stream_id), None);
+        let streams = |account_id| {
+            contract.get_streams_by_filter(
+                account_id,
+                None,
+                None,
+                Some(StreamRole::Receiver),
+                None,
+                None,
+            )
+        };
+        assert!(streams(accounts(1)).is_empty());
+        assert_eq!(streams(accounts(2)).len(), 1);
+
+        // charlie withdraws what accrued before the assignment as well
+        set_context(accounts(2), 0, 7);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.get_stream(stream_id).balance, 4);
+    }
+
+    #[test]
+    fn payments_keep_receiver_after_assignment() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), 0, 3);
+        contract.withdraw(stream_id, None);
+        contract.propose_assignment(stream_id, accounts(2));
+        set_context(accounts(0), 0, 5);
+        contract.approve_assignment(stream_id, accounts(2));
+        set_context(accounts(2), 0, 7);
+        contract.withdraw(stream_id, None);
+
+        let payments = contract.get_payments(accounts(0), U64(0), U64(u64::MAX), None, None);
+        assert_eq!(payments.len(), 2);
+        assert_eq!(payments[0].receiver, accounts(1));
+        assert_eq!(payments[1].receiver, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot assign a stream with a child stream")]
+    fn assignment_with_child_stream() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 100, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(10), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), 0, 2);
+        contract.create_child_stream(stream_id, accounts(3), 5_000);
+        contract.propose_assignment(stream_id, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Assignment does not match the proposal")]
+    fn assignment_must_match_proposal() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), 0, 5);
+        contract.propose_assignment(stream_id, accounts(2));
+        contract.propose_assignment(stream_id, accounts(3));
+
+        set_context(accounts(0), 0, 5);
+        contract.approve_assignment(stream_id, accounts(2));
+    }
+}
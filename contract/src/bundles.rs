@@ -0,0 +1,392 @@
+use crate::calls::{ft_decimals, ft_max_rate};
+use crate::views::BundleStreamView;
+use crate::*;
+
+/// Streams to one receiver, in NEAR and/or whitelisted tokens, that are
+/// created together once every token has been deposited.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bundle {
+    sender: AccountId,
+    receiver: AccountId,
+    streams: Vec<BundleStream>,
+    stream_ids: Vec<u64>, // filled in when the bundle is complete
+    is_cancelled: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleStream {
+    token: Option<AccountId>, // None for native NEAR
+    rate: Balance,
+    start_time: u64,
+    end_time: u64,
+    can_cancel: bool,
+    can_update: bool,
+    is_funded: bool,
+}
+
+impl BundleStream {
+    fn amount(&self) -> Balance {
+        u128::from(self.end_time - self.start_time) * self.rate
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // Creates a pending bundle of streams to `receiver`. The attached deposit
+    // funds the native streams and the bundle storage, the rest is refunded.
+    // Each ft stream is funded with `ft_transfer_call` and the msg
+    // `{"method_name":"fund_bundle","bundle_id":"<id>"}`. The streams are
+    // created when the last one is funded.
+    #[payable]
+    pub fn create_bundle(&mut self, receiver: AccountId, streams: Vec<BundleStreamView>) -> U64 {
+        let sender = env::predecessor_account_id();
+        let current_timestamp: u64 = current_time();
+        require!(sender != receiver, "Sender and receiver cannot be the same");
+        self.internal_check_sender(&receiver, &sender);
+        require!(!streams.is_empty(), "Bundle has no streams");
+
+        let mut native_amount: Balance = 0;
+        let streams: Vec<BundleStream> = streams
+            .into_iter()
+            .map(|view| {
+                let stream = BundleStream {
+                    token: view.token,
+                    rate: view.stream_rate.0,
+                    start_time: view.start.0,
+                    end_time: view.end.0,
+                    can_cancel: view.can_cancel,
+                    can_update: view.can_update,
+                    is_funded: false,
+                };
+
+                // same checks as `create_stream` and `ft_create_stream`
+                require!(
+                    stream.start_time >= current_timestamp,
+                    "Start time cannot be in the past"
+                );
+                require!(
+                    stream.end_time >= stream.start_time,
+                    "Start time cannot be in the past"
+                );
+                require!(stream.rate > 0, "Rate cannot be zero");
+                let max_rate = match &stream.token {
+                    None => MAX_RATE,
+                    Some(token) => {
+                        ft_max_rate(ft_decimals(token).expect("Token is not whitelisted"))
+                    }
+                };
+                require!(stream.rate < max_rate, "Rate is too high");
+                require!(
+                    u128::from(stream.end_time - stream.start_time)
+                        .checked_mul(stream.rate)
+                        .is_some(),
+                    "Stream amount is too large"
+                );
+
+                if stream.token.is_none() {
+                    native_amount += stream.amount();
+                }
+                BundleStream {
+                    is_funded: stream.token.is_none(),
+                    ..stream
+                }
+            })
+            .collect();
+
+        require!(
+            env::attached_deposit() >= native_amount,
+            "The amount provided doesn't matches the native streams"
+        );
+
+        let initial_storage = env::storage_usage();
+        let bundle_id = self.bundles.len();
+        self.bundles.push(&Bundle {
+            sender: sender.clone(),
+            receiver,
+            streams,
+            stream_ids: vec![],
+            is_cancelled: false,
+        });
+        log_event!("Bundle created: {}", bundle_id);
+
+        let cost = Balance::from(env::storage_usage().saturating_sub(initial_storage))
+            * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() - native_amount >= cost,
+            "The amount provided is not enough for the bundle storage"
+        );
+        if env::attached_deposit() - native_amount > cost {
+            Promise::new(sender).transfer(env::attached_deposit() - native_amount - cost);
+        }
+
+        self.internal_complete_bundle(bundle_id);
+        U64(bundle_id)
+    }
+
+    // Refunds everything deposited for a bundle that is not complete yet, a
+    // token refund that fails is put in escrow for the sender
+    pub fn cancel_bundle(&mut self, bundle_id: U64) {
+        let mut bundle = self.internal_get_bundle(bundle_id.0);
+        require!(
+            env::predecessor_account_id() == bundle.sender,
+            "Only the sender can cancel the bundle"
+        );
+
+        bundle.is_cancelled = true;
+        self.bundles.replace(bundle_id.0, &bundle);
+        log_event!("Bundle cancelled: {}", bundle_id.0);
+
+        let mut native_amount: Balance = 0;
+        for stream in bundle.streams.iter().filter(|stream| stream.is_funded) {
+            match &stream.token {
+                None => native_amount += stream.amount(),
+                Some(token) => {
+                    ext_ft_transfer::ext(token.clone())
+                        .with_attached_deposit(1)
+                        .ft_transfer(bundle.sender.clone(), stream.amount().into(), None)
+                        .then(
+                            Self::ext(env::current_account_id()).internal_resolve_escrow_claim(
+                                bundle.sender.clone(),
+                                token.clone(),
+                                stream.amount().into(),
+                            ),
+                        );
+                }
+            }
+        }
+        if native_amount > 0 {
+            Promise::new(bundle.sender).transfer(native_amount);
+        }
+    }
+}
+
+impl Contract {
+    fn internal_get_bundle(&self, bundle_id: u64) -> Bundle {
+        let bundle = self.bundles.get(bundle_id).expect("Bundle does not exist");
+        require!(!bundle.is_cancelled, "Bundle has been cancelled");
+        require!(bundle.stream_ids.is_empty(), "Bundle is already complete");
+        bundle
+    }
+
+    /// Funds the first unfunded stream of the bundle in `token` for `amount`
+    /// and creates the streams if it was the last one.
+    pub(crate) fn internal_fund_bundle(
+        &mut self,
+        bundle_id: u64,
+        sender: AccountId,
+        token: AccountId,
+        amount: Balance,
+    ) {
+        let mut bundle = self.internal_get_bundle(bundle_id);
+        require!(
+            sender == bundle.sender,
+            "Only the sender can fund the bundle"
+        );
+
+        let stream = bundle
+            .streams
+            .iter_mut()
+            .find(|stream| {
+                !stream.is_funded
+                    && stream.token.as_ref() == Some(&token)
+                    && stream.amount() == amount
+            })
+            .expect("No stream of the bundle matches the deposit");
+        stream.is_funded = true;
+        self.bundles.replace(bundle_id, &bundle);
+        log_event!("Bundle funded: {} {} {}", bundle_id, token, amount);
+
+        self.internal_complete_bundle(bundle_id);
+    }
+
+    // creates all the streams of the bundle once every stream is funded
+    fn internal_complete_bundle(&mut self, bundle_id: u64) {
+        let mut bundle = self.bundles.get(bundle_id).unwrap();
+        if !bundle.streams.iter().all(|stream| stream.is_funded) {
+            return;
+        }
+
+        let current_timestamp: u64 = current_time();
+        for stream in bundle.streams.iter() {
+            require!(
+                stream.start_time >= current_timestamp,
+                "Start time cannot be in the past"
+            );
+
+            let id = self.current_id;
+            let is_native = stream.token.is_none();
+            self.internal_add_stream(&Stream {
+                id,
+                sender: bundle.sender.clone(),
+                receiver: bundle.receiver.clone(),
+                rate: stream.rate,
+                is_paused: false,
+                is_cancelled: false,
+                balance: stream.amount(),
+                created: current_timestamp,
+                start_time: stream.start_time,
+                end_time: stream.end_time,
+                withdraw_time: stream.start_time,
+                paused_time: if is_native { 0 } else { stream.start_time },
+                // ignored for native streams
+                contract_id: stream
+                    .token
+                    .clone()
+                    .unwrap_or_else(|| "near.testnet".parse().unwrap()),
+                can_cancel: stream.can_cancel,
+                can_update: stream.can_update,
+                is_native,
+            });
+            self.current_id += 1;
+            bundle.stream_ids.push(id);
+        }
+
+        self.bundles.replace(bundle_id, &bundle);
+        log_event!("Bundle complete: {} {:?}", bundle_id, bundle.stream_ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+    use std::collections::HashMap;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    fn bundle_stream(token: Option<&str>, rate: u128) -> BundleStreamView {
+        BundleStreamView {
+            token: token.map(|token| token.parse().unwrap()),
+            stream_rate: U128(rate),
+            start: U64(0),
+            end: U64(10),
+            can_cancel: true,
+            can_update: false,
+        }
+    }
+
+    #[test]
+    fn create_bundle_when_funded() {
+        let mut contract = Contract::new();
+        let usn = 10u128.pow(18);
+
+        // alice pays bob in NEAR and USN, the rest of the deposit is refunded
+        set_context_with_balance(accounts(0), 11 * NEAR);
+        let bundle_id = contract.create_bundle(
+            accounts(1),
+            vec![
+                bundle_stream(None, NEAR),
+                bundle_stream(Some("usdn.testnet"), usn),
+            ],
+        );
+        assert!(contract
+            .get_bundle(bundle_id)
+            .unwrap()
+            .stream_ids
+            .is_empty());
+
+        // the USN deposit completes the bundle
+        contract.internal_fund_bundle(
+            bundle_id.0,
+            accounts(0),
+            "usdn.testnet".parse().unwrap(),
+            10 * usn,
+        );
+        assert_eq!(
+            contract.get_bundle(bundle_id).unwrap().stream_ids,
+            vec![1, 2]
+        );
+        assert!(contract.internal_get_stream(1).is_native);
+        let stream = contract.internal_get_stream(2);
+        assert_eq!(stream.balance, 10 * usn);
+        assert_eq!(stream.receiver, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "No stream of the bundle matches the deposit")]
+    fn fund_bundle_wrong_amount() {
+        let mut contract = Contract::new();
+        let usn = 10u128.pow(18);
+
+        set_context_with_balance(accounts(0), NEAR);
+        let bundle_id =
+            contract.create_bundle(accounts(1), vec![bundle_stream(Some("usdn.testnet"), usn)]);
+        contract.internal_fund_bundle(
+            bundle_id.0,
+            accounts(0),
+            "usdn.testnet".parse().unwrap(),
+            usn,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the bundle storage")]
+    fn ft_bundle_pays_storage() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 0);
+        contract.create_bundle(accounts(1), vec![bundle_stream(Some("usdn.testnet"), 1)]);
+    }
+
+    #[test]
+    fn cancel_bundle_escrows_failed_refund() {
+        let mut contract = Contract::new();
+        let usn = 10u128.pow(18);
+
+        set_context_with_balance(accounts(0), NEAR);
+        let bundle_id = contract.create_bundle(
+            accounts(1),
+            vec![
+                bundle_stream(Some("usdn.testnet"), usn),
+                bundle_stream(Some("usdn.testnet"), 2 * usn),
+            ],
+        );
+        contract.internal_fund_bundle(
+            bundle_id.0,
+            accounts(0),
+            "usdn.testnet".parse().unwrap(),
+            10 * usn,
+        );
+        contract.cancel_bundle(bundle_id);
+
+        // the refund is resolved by a callback
+        let callback = get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| receipt.actions)
+            .any(|action| {
+                matches!(action, VmAction::FunctionCall { function_name, .. }
+                    if function_name == "internal_resolve_escrow_claim")
+            });
+        assert!(callback);
+
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(env::current_account_id())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            HashMap::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.internal_resolve_escrow_claim(
+            accounts(0),
+            "usdn.testnet".parse().unwrap(),
+            U128(10 * usn),
+        );
+        assert_eq!(
+            contract.get_escrow(accounts(0), Some("usdn.testnet".parse().unwrap())),
+            U128(10 * usn)
+        );
+    }
+}
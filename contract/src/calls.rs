@@ -8,7 +8,7 @@ pub use crate::views::*;
 #[near_bindgen]
 impl Contract {
     #[private]
-    fn ft_create_stream(
+    pub(crate) fn ft_create_stream(
         &mut self,
         stream_rate: U128,
         start_time: U64,
@@ -19,16 +19,20 @@ impl Contract {
         contract_id: AccountId,
         can_cancel: bool,
         can_update: bool,
+        cliff_time: Option<U64>,
+        remainder: Balance,
+        nonce: Option<u64>,
     ) -> bool {
         // check that the receiver and sender are not the same
         assert!(sender != receiver, "Sender and receiver cannot be the same");
+        self.internal_check_sender(&receiver, &sender);
 
         // convert id to native u128
         let rate: u128 = stream_rate.0;
         let start_time: u64 = start_time.0;
         let end_time: u64 = end_time.0;
 
-        let current_timestamp: u64 = env::block_timestamp_ms() / 1000;
+        let current_timestamp: u64 = current_time();
         // Check the start and end timestamp is valid
         require!(
             start_time >= current_timestamp,
@@ -36,13 +40,18 @@ impl Contract {
         );
         require!(end_time >= start_time, "Start time cannot be in the past");
 
-        // check the rate is valid
+        // check the rate is valid for the token's decimals
+        let decimals = ft_decimals(&contract_id).unwrap();
         require!(rate > 0, "Rate cannot be zero");
-        require!(rate < MAX_RATE, "Rate is too high");
+        require!(rate < ft_max_rate(decimals), "Rate is too high");
 
         // calculate the balance is enough
         let stream_duration = end_time - start_time;
-        let stream_amount = u128::from(stream_duration) * rate;
+        let stream_amount = u128::from(stream_duration)
+            .checked_mul(rate)
+            .and_then(|amount| amount.checked_add(remainder));
+        require!(stream_amount.is_some(), "Stream amount is too large");
+        let stream_amount = stream_amount.unwrap();
 
         // check the amount send to the stream
         require!(
@@ -50,7 +59,18 @@ impl Contract {
             "The amount provided doesn't matches the stream"
         );
 
-        let params_key = self.current_id;
+        // same ids as `create_stream_with_nonce`
+        let params_key = match nonce {
+            Some(nonce) => {
+                let id = nonce_stream_id(&sender, nonce);
+                require!(
+                    !self.streams.contains_key(&id),
+                    "Stream with this nonce already exists"
+                );
+                id
+            }
+            None => self.current_id,
+        };
 
         let stream_params = Stream {
             id: params_key,
@@ -71,25 +91,44 @@ impl Contract {
             is_native: false,
         };
 
-        self.streams.insert(&params_key, &stream_params);
-        self.current_id += 1;
-        log!("Saving streams {}", stream_params.id);
+        self.internal_set_cliff(&stream_params, cliff_time);
+        if remainder > 0 {
+            self.stream_remainders.insert(&params_key, &remainder);
+        }
+        self.internal_add_stream(&stream_params);
+        if nonce.is_none() {
+            self.current_id += 1;
+        }
+        log_event!("Saving streams {}", stream_params.id);
         return true;
     }
 
     pub fn valid_ft_sender(account: AccountId) -> bool {
         // can only be called by stablecoin contract
-        // @todo add valid stablecoins (from mainnet) address here later
-        let accounts: [AccountId; 2] = [
-            "usdn.testnet".parse().unwrap(),
-            "wrap.testnet".parse().unwrap(),
-        ];
-        if accounts.contains(&account) {
-            // @todo: check if the accountID is in explicit (".near") or implicit format
-            return true;
-        } else {
-            return false;
-        }
+        // @todo: check if the accountID is in explicit (".near") or implicit format
+        ft_decimals(&account).is_some()
+    }
+}
+
+// decimals of a whitelisted token, None if the token is not whitelisted
+pub(crate) fn ft_decimals(token: &AccountId) -> Option<u8> {
+    FT_TOKENS
+        .iter()
+        .find(|(id, _)| token.as_str() == *id)
+        .map(|(_, decimals)| *decimals)
+}
+
+// the maximum rate (exclusive) of a token in its smallest unit
+pub(crate) fn ft_max_rate(decimals: u8) -> Balance {
+    MAX_TOKENS_PER_SECOND * 10u128.pow(decimals.into())
+}
+
+// the maximum rate (exclusive) of the stream's token
+pub(crate) fn stream_max_rate(stream: &Stream) -> Balance {
+    if stream.is_native {
+        MAX_RATE
+    } else {
+        ft_max_rate(ft_decimals(&stream.contract_id).unwrap())
     }
 }
 
@@ -105,6 +144,85 @@ impl FungibleTokenReceiver for Contract {
         // msg contains the structure of the stream
         let res: Result<StreamView, _> = serde_json::from_str(&msg);
         if res.is_err() {
+            // messages to an existing stream, the unused remainder is returned
+            if let Ok(stream_msg) = serde_json::from_str::<StreamMsgView>(&msg) {
+                let stream_id = stream_msg.stream_id.0;
+                let token = env::predecessor_account_id();
+                match stream_msg.method_name.as_str() {
+                    // applies a pending update of the stream
+                    "apply_update" => {
+                        self.internal_apply_update(stream_id, sender_id, token, amount.0);
+                        return PromiseOrValue::Value(U128(0));
+                    }
+                    "donate" => {
+                        let unused =
+                            self.internal_donate(stream_id, sender_id, amount.0, Some(token));
+                        return PromiseOrValue::Value(U128(unused));
+                    }
+                    // the sender tops up its own stream
+                    "topup" => {
+                        let unused =
+                            self.internal_topup(stream_id, &sender_id, amount.0, Some(token));
+                        return PromiseOrValue::Value(U128(unused));
+                    }
+                    // other methods are refunded below
+                    _ => {}
+                }
+            }
+            // a stream of the transferred amount, see `create_stream_with_amount`
+            if let Ok(stream) = serde_json::from_str::<AmountStreamView>(&msg) {
+                require!(stream.method_name == "create_stream_with_amount");
+                require!(
+                    stream.end.0 > stream.start.0,
+                    "Start time should be less than end time"
+                );
+                let duration = u128::from(stream.end.0 - stream.start.0);
+                require!(
+                    amount.0 >= duration,
+                    "Amount is smaller than the stream duration"
+                );
+                self.ft_create_stream(
+                    U128(amount.0 / duration),
+                    stream.start,
+                    stream.end,
+                    sender_id,
+                    amount,
+                    stream.receiver,
+                    env::predecessor_account_id(),
+                    stream.can_cancel,
+                    stream.can_update,
+                    stream.cliff_time,
+                    amount.0 % duration,
+                    None,
+                );
+                return PromiseOrValue::Value(U128(0));
+            }
+            if let Ok(pool) = serde_json::from_str::<MatchingPoolView>(&msg) {
+                require!(pool.method_name == "fund_matching_pool");
+                self.internal_fund_matching_pool(
+                    pool.pool_id.0,
+                    sender_id,
+                    env::predecessor_account_id(),
+                    amount.0,
+                );
+                return PromiseOrValue::Value(U128(0));
+            }
+            if let Ok(funding) = serde_json::from_str::<BundleFundingView>(&msg) {
+                require!(funding.method_name == "fund_bundle");
+                self.internal_fund_bundle(
+                    funding.bundle_id.0,
+                    sender_id,
+                    env::predecessor_account_id(),
+                    amount.0,
+                );
+                return PromiseOrValue::Value(U128(0));
+            }
+            if let Ok(deposit) = serde_json::from_str::<DepositView>(&msg) {
+                if deposit.method_name == "deposit" {
+                    self.internal_deposit_ft(sender_id, env::predecessor_account_id(), amount.0);
+                    return PromiseOrValue::Value(U128(0));
+                }
+            }
             // if err then return everything back
             return PromiseOrValue::Value(amount);
         }
@@ -120,6 +238,9 @@ impl FungibleTokenReceiver for Contract {
             env::predecessor_account_id(),
             _stream.can_cancel,
             _stream.can_update,
+            _stream.cliff_time,
+            0,
+            _stream.nonce.map(|nonce| nonce.0),
         ) {
             return PromiseOrValue::Value(U128::from(0));
         } else {
@@ -127,3 +248,137 @@ impl FungibleTokenReceiver for Contract {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: &str) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor.parse().unwrap());
+        testing_env!(builder.build());
+    }
+
+    fn create_stream_msg(rate: u128, end: u64) -> String {
+        format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"{}","start":"0","end":"{}","can_update":false,"can_cancel":false}}"#,
+            accounts(1),
+            rate,
+            end
+        )
+    }
+
+    #[test]
+    fn ft_create_stream_with_amount() {
+        let mut contract = Contract::new();
+
+        // 25 over 10 seconds is 2 per second and 5 with the last second
+        set_context("usdn.testnet");
+        let msg = format!(
+            r#"{{"method_name":"create_stream_with_amount","receiver":"{}","start":"0","end":"10","can_update":false,"can_cancel":false}}"#,
+            accounts(1),
+        );
+        let unused = contract.ft_on_transfer(accounts(0), U128(25), msg);
+        assert!(matches!(unused, PromiseOrValue::Value(U128(0))));
+
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.rate, 2);
+        assert_eq!(stream.balance, 25);
+        assert_eq!(contract.internal_streamed_amount(&stream, 0, 10), 25);
+    }
+
+    #[test]
+    fn ft_create_stream_with_nonce() {
+        let mut contract = Contract::new();
+
+        set_context("usdn.testnet");
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"0","end":"10","can_update":false,"can_cancel":false,"nonce":"7"}}"#,
+            accounts(1),
+        );
+        contract.ft_on_transfer(accounts(0), U128(10), msg);
+
+        let stream_id = contract.get_nonce_stream_id(accounts(0), U64(7));
+        assert_eq!(contract.get_stream(stream_id).balance, 10);
+        assert_eq!(contract.current_id, 1);
+    }
+
+    #[test]
+    fn ft_max_rate_per_token() {
+        assert_eq!(ft_decimals(&"usdn.testnet".parse().unwrap()), Some(18));
+        assert_eq!(ft_decimals(&"wrap.testnet".parse().unwrap()), Some(24));
+        assert_eq!(ft_decimals(&accounts(0)), None);
+        assert_eq!(ft_max_rate(24), MAX_RATE);
+        assert_eq!(ft_max_rate(6), 100_000_000);
+    }
+
+    #[test]
+    fn ft_create_stream_high_decimals() {
+        let mut contract = Contract::new();
+        // 50 wNEAR per second is fine for a 24 decimals token
+        let rate = 50 * 10u128.pow(24);
+
+        set_context("wrap.testnet");
+        contract.ft_on_transfer(accounts(0), U128(10 * rate), create_stream_msg(rate, 10));
+
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.rate, rate);
+        assert_eq!(stream.contract_id, "wrap.testnet".parse().unwrap());
+        assert!(!stream.is_native);
+    }
+
+    #[test]
+    fn ft_donate() {
+        let mut contract = Contract::new();
+        let rate = 10u128.pow(18);
+
+        set_context("usdn.testnet");
+        contract.ft_on_transfer(accounts(0), U128(10 * rate), create_stream_msg(rate, 10));
+
+        // 2.5 seconds worth of tokens, the half second is returned
+        let msg = r#"{"method_name":"donate","stream_id":"1"}"#.to_string();
+        let unused = contract.ft_on_transfer(accounts(2), U128(5 * rate / 2), msg);
+        assert!(matches!(unused, PromiseOrValue::Value(U128(amount)) if amount == rate / 2));
+
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.end_time, 12);
+        assert_eq!(stream.balance, 12 * rate);
+    }
+
+    #[test]
+    fn ft_unknown_stream_method_refunded() {
+        let mut contract = Contract::new();
+
+        set_context("usdn.testnet");
+        let msg = r#"{"method_name":"withdraw","stream_id":"1"}"#.to_string();
+        let unused = contract.ft_on_transfer(accounts(2), U128(5), msg);
+        assert!(matches!(unused, PromiseOrValue::Value(U128(5))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Token does not match the stream")]
+    fn ft_donate_other_token() {
+        let mut contract = Contract::new();
+        let rate = 10u128.pow(18);
+
+        set_context("usdn.testnet");
+        contract.ft_on_transfer(accounts(0), U128(10 * rate), create_stream_msg(rate, 10));
+
+        set_context("wrap.testnet");
+        let msg = r#"{"method_name":"donate","stream_id":"1"}"#.to_string();
+        contract.ft_on_transfer(accounts(2), U128(rate), msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "Rate is too high")]
+    fn ft_create_stream_rate_too_high_for_decimals() {
+        let mut contract = Contract::new();
+        // the same rate is 5 * 10^7 USN per second
+        let rate = 50 * 10u128.pow(24);
+
+        set_context("usdn.testnet");
+        contract.ft_on_transfer(accounts(0), U128(10 * rate), create_stream_msg(rate, 10));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::*;
+
+/// Outgoing stream of a receiver funded by `bps` of every payout of the
+/// parent stream, see `create_child_stream`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChildStream {
+    stream_id: u64,
+    bps: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Streams `bps` of the caller's incoming stream on to `receiver` until the
+    // parent ends. The child is funded from the parent's payouts as they are
+    // made and only pays out what was funded, so it can't outrun the parent.
+    pub fn create_child_stream(&mut self, parent_id: U64, receiver: AccountId, bps: u32) -> U64 {
+        let current_timestamp: u64 = current_time();
+        let parent = self.internal_get_stream(parent_id.0);
+        let sender = env::predecessor_account_id();
+
+        require!(
+            sender == parent.receiver,
+            "Only the receiver can create a child stream"
+        );
+        require!(!parent.is_cancelled, "Stream is cancelled");
+        require!(
+            !self.internal_has_ended(&parent, current_timestamp),
+            "Stream already ended"
+        );
+        require!(bps > 0 && bps <= 10_000, "Invalid child bps");
+        require!(
+            self.child_streams.get(&parent_id.0).is_none(),
+            "Stream already has a child stream"
+        );
+        require!(
+            self.withholdings.get(&parent_id.0).is_none(),
+            "Cannot fund a child stream from a stream with a withholding"
+        );
+        require!(receiver != sender, "Sender and receiver cannot be the same");
+        self.internal_check_sender(&receiver, &sender);
+
+        let rate = parent.rate * u128::from(bps) / 10_000;
+        require!(rate > 0, "Rate cannot be zero");
+
+        let start_time = current_timestamp.max(parent.start_time);
+        let id = self.current_id;
+        self.internal_add_stream(&Stream {
+            id,
+            sender,
+            receiver,
+            rate,
+            is_paused: false,
+            is_cancelled: false,
+            balance: 0,
+            created: current_timestamp,
+            start_time,
+            end_time: parent.end_time,
+            withdraw_time: start_time,
+            paused_time: 0,
+            contract_id: parent.contract_id,
+            can_cancel: false,
+            can_update: false,
+            is_native: parent.is_native,
+        });
+        self.current_id += 1;
+
+        self.child_streams
+            .insert(&parent_id.0, &ChildStream { stream_id: id, bps });
+        self.parent_streams.insert(&id, &parent_id.0);
+        log_event!("Child stream created: {} {}", parent_id.0, id);
+        U64(id)
+    }
+
+    pub fn get_child_stream(&self, parent_id: U64) -> Option<ChildStream> {
+        self.child_streams.get(&parent_id.0)
+    }
+}
+
+impl Contract {
+    /// Part of a payout of the stream that funds its child, None without one.
+    pub(crate) fn internal_child_share(&self, stream_id: u64, amount: Balance) -> Option<Balance> {
+        self.child_streams
+            .get(&stream_id)
+            .map(|child| amount * u128::from(child.bps) / 10_000)
+    }
+
+    /// Adds the child's share of a payout of the parent to the child's balance.
+    pub(crate) fn internal_fund_child(&mut self, parent: &Stream, amount: Balance) {
+        let child = match self.child_streams.get(&parent.id) {
+            Some(child) => child,
+            None => return,
+        };
+        let share = amount * u128::from(child.bps) / 10_000;
+        if share == 0 {
+            return;
+        }
+
+        let mut stream = self.internal_get_stream(child.stream_id);
+        stream.balance += share;
+        self.internal_save_stream_state(&stream);
+        log_event!(
+            "Stream payout: {} {} {}",
+            parent.id,
+            parent.receiver,
+            amount - share
+        );
+        log_event!("Child stream funded: {} {} {}", parent.id, stream.id, share);
+    }
+
+    /// Seconds of a child stream its balance pays for, None for other streams.
+    pub(crate) fn internal_funded_time(&self, stream: &Stream) -> Option<u64> {
+        if !self.parent_streams.contains_key(&stream.id) {
+            return None;
+        }
+        Some(u64::try_from(stream.balance / stream.rate).unwrap_or(u64::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn child_funded_by_parent() {
+        let mut contract = Contract::new();
+
+        // alice -> bob 10 per second, bob -> charlie 20% of it
+        set_context(accounts(0), 100, 0);
+        let parent_id =
+            contract.create_stream(accounts(1), U128(10), U64(0), U64(10), true, false, None);
+        set_context(accounts(1), 0, 0);
+        let child_id = contract.create_child_stream(parent_id, accounts(2), 2_000);
+        assert_eq!(contract.get_stream(child_id).rate, 2);
+
+        // charlie can only withdraw once bob was paid
+        set_context(accounts(1), 0, 4);
+        contract.withdraw(parent_id, None);
+        assert_eq!(contract.get_stream(child_id).balance, 8);
+
+        set_context(accounts(2), 0, 6);
+        contract.withdraw(child_id, None);
+        let child = contract.get_stream(child_id);
+        assert_eq!(child.balance, 0);
+        assert_eq!(child.withdraw_time, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the receiver can create a child stream")]
+    fn child_by_sender() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 100, 0);
+        let parent_id =
+            contract.create_stream(accounts(1), U128(10), U64(0), U64(10), true, false, None);
+        contract.create_child_stream(parent_id, accounts(2), 2_000);
+    }
+}
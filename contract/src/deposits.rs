@@ -0,0 +1,211 @@
+use crate::calls::ft_decimals;
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    // Creates an ft stream from the tokens the caller deposited with
+    // `ft_transfer_call` and `{"method_name":"deposit"}`, with the same checks
+    // as creating it from the message. The stream amount is taken from the
+    // deposit and the rest stays there. An optional `nonce` derives the id as in
+    // `create_stream_with_nonce`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_ft_stream(
+        &mut self,
+        token: AccountId,
+        receiver: AccountId,
+        stream_rate: U128,
+        start: U64,
+        end: U64,
+        can_cancel: bool,
+        can_update: bool,
+        cliff_time: Option<U64>,
+        nonce: Option<U64>,
+    ) -> U64 {
+        require!(ft_decimals(&token).is_some(), "Token is not supported");
+        require!(end.0 >= start.0, "Start time cannot be in the past");
+        let amount = u128::from(end.0 - start.0)
+            .checked_mul(stream_rate.0)
+            .expect("Stream amount is too large");
+
+        let sender = env::predecessor_account_id();
+        let key = (sender.clone(), token.clone());
+        let deposit = self.ft_deposits.get(&key).unwrap_or(0);
+        require!(amount <= deposit, "Not enough tokens deposited");
+        if deposit == amount {
+            self.ft_deposits.remove(&key);
+        } else {
+            self.ft_deposits.insert(&key, &(deposit - amount));
+        }
+
+        let nonce = nonce.map(|nonce| nonce.0);
+        let id = nonce.map_or(self.current_id, |nonce| nonce_stream_id(&sender, nonce));
+        self.ft_create_stream(
+            stream_rate,
+            start,
+            end,
+            sender,
+            U128(amount),
+            receiver,
+            token,
+            can_cancel,
+            can_update,
+            cliff_time,
+            0,
+            nonce,
+        );
+        U64(id)
+    }
+
+    // Transfers `amount` of the caller's deposit of `token` back to it,
+    // everything if `None`
+    pub fn withdraw_ft_deposit(&mut self, token: AccountId, amount: Option<U128>) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let key = (account_id.clone(), token.clone());
+        let deposit = self.ft_deposits.get(&key).unwrap_or(0);
+        let amount = amount.map_or(deposit, |amount| amount.0);
+        require!(amount > 0, "Nothing to withdraw");
+        require!(amount <= deposit, "Not enough tokens deposited");
+        if deposit == amount {
+            self.ft_deposits.remove(&key);
+        } else {
+            self.ft_deposits.insert(&key, &(deposit - amount));
+        }
+
+        log_event!("Deposit withdrawn: {} {} {}", account_id, token, amount);
+        ext_ft_transfer::ext(token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(account_id.clone(), amount.into(), None)
+            .then(
+                Self::ext(env::current_account_id()).internal_resolve_deposit_withdraw(
+                    account_id,
+                    token,
+                    amount.into(),
+                ),
+            )
+    }
+
+    // credits the amount back if the transfer failed
+    #[private]
+    pub fn internal_resolve_deposit_withdraw(
+        &mut self,
+        account_id: AccountId,
+        token: AccountId,
+        amount: U128,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !res {
+            self.internal_deposit_ft(account_id, token, amount.0);
+        }
+        res
+    }
+
+    pub fn get_ft_deposit(&self, account_id: AccountId, token: AccountId) -> U128 {
+        U128(self.ft_deposits.get(&(account_id, token)).unwrap_or(0))
+    }
+}
+
+impl Contract {
+    /// Adds tokens to the account's deposit for `create_ft_stream`.
+    pub(crate) fn internal_deposit_ft(
+        &mut self,
+        account_id: AccountId,
+        token: AccountId,
+        amount: Balance,
+    ) {
+        let key = (account_id, token);
+        let deposit = self.ft_deposits.get(&key).unwrap_or(0);
+        self.ft_deposits.insert(&key, &(deposit + amount));
+        log_event!("Deposit: {} {} {}", key.0, key.1, amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn deposit_then_create() {
+        let mut contract = Contract::new();
+        let usn: AccountId = "usdn.testnet".parse().unwrap();
+
+        set_context(usn.clone());
+        let msg = r#"{"method_name":"deposit"}"#.to_string();
+        contract.ft_on_transfer(accounts(0), U128(25), msg);
+        assert_eq!(contract.get_ft_deposit(accounts(0), usn.clone()), U128(25));
+
+        set_context(accounts(0));
+        let stream_id = contract.create_ft_stream(
+            usn.clone(),
+            accounts(1),
+            U128(2),
+            U64(0),
+            U64(10),
+            true,
+            false,
+            None,
+            None,
+        );
+        let stream = contract.get_stream(stream_id);
+        assert_eq!(stream.sender, accounts(0));
+        assert_eq!(stream.contract_id, usn);
+        assert_eq!(stream.balance, 20);
+        assert_eq!(contract.get_ft_deposit(accounts(0), usn), U128(5));
+    }
+
+    #[test]
+    fn deposit_then_create_with_nonce() {
+        let mut contract = Contract::new();
+        let usn: AccountId = "usdn.testnet".parse().unwrap();
+
+        set_context(usn.clone());
+        let msg = r#"{"method_name":"deposit"}"#.to_string();
+        contract.ft_on_transfer(accounts(0), U128(25), msg);
+
+        set_context(accounts(0));
+        let stream_id = contract.create_ft_stream(
+            usn,
+            accounts(1),
+            U128(2),
+            U64(0),
+            U64(10),
+            true,
+            false,
+            None,
+            Some(U64(7)),
+        );
+        assert_eq!(stream_id, contract.get_nonce_stream_id(accounts(0), U64(7)));
+        assert_eq!(contract.get_stream(stream_id).balance, 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough tokens deposited")]
+    fn create_without_deposit() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0));
+        contract.create_ft_stream(
+            "usdn.testnet".parse().unwrap(),
+            accounts(1),
+            U128(2),
+            U64(0),
+            U64(10),
+            true,
+            false,
+            None,
+            None,
+        );
+    }
+}
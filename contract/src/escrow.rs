@@ -0,0 +1,294 @@
+use crate::*;
+
+pub const MAX_PAYOUT_FAILURES: u32 = 3;
+
+#[near_bindgen]
+impl Contract {
+    // Transfers the caller's payouts that could not be delivered in `token`
+    // (None for NEAR)
+    pub fn claim_escrow(&mut self, token: Option<AccountId>) -> PromiseOrValue<bool> {
+        let account_id = env::predecessor_account_id();
+        let amount = self
+            .payout_escrows
+            .remove(&(account_id.clone(), token.clone()))
+            .unwrap_or(0);
+        require!(amount > 0, "Nothing in escrow");
+
+        log_event!("Escrow claimed: {} {}", account_id, amount);
+        match token {
+            None => self
+                .internal_native_payout(
+                    Promise::new(account_id.clone()).transfer(amount),
+                    &account_id,
+                    amount,
+                )
+                .into(),
+            Some(token) => ext_ft_transfer::ext(token.clone())
+                .with_attached_deposit(1)
+                .ft_transfer(account_id.clone(), amount.into(), None)
+                .then(
+                    Self::ext(env::current_account_id()).internal_resolve_escrow_claim(
+                        account_id,
+                        token,
+                        amount.into(),
+                    ),
+                )
+                .into(),
+        }
+    }
+
+    // Puts a native payout back in escrow if its transfer failed, NEAR
+    // refunds the contract when the receiving account doesn't exist
+    #[private]
+    pub fn internal_resolve_native_payout(&mut self, account_id: AccountId, amount: U128) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !res {
+            self.internal_escrow(account_id, None, amount.0);
+        }
+        res
+    }
+
+    // credits the amount back if the transfer of a claim or bundle refund failed
+    #[private]
+    pub fn internal_resolve_escrow_claim(
+        &mut self,
+        account_id: AccountId,
+        token: AccountId,
+        amount: U128,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !res {
+            self.internal_escrow(account_id, Some(token), amount.0);
+        }
+        res
+    }
+
+    // payouts of the account that could not be delivered, token None for NEAR
+    pub fn get_escrow(&self, account_id: AccountId, token: Option<AccountId>) -> U128 {
+        U128(self.payout_escrows.get(&(account_id, token)).unwrap_or(0))
+    }
+}
+
+impl Contract {
+    /// Resolves a native transfer of `amount` to `account_id`, last in `promise`,
+    /// by putting it in escrow if it fails.
+    pub(crate) fn internal_native_payout(
+        &self,
+        promise: Promise,
+        account_id: &AccountId,
+        amount: Balance,
+    ) -> Promise {
+        promise.then(
+            Self::ext(env::current_account_id())
+                .internal_resolve_native_payout(account_id.clone(), U128(amount)),
+        )
+    }
+
+    /// Counts a failed ft payout of the stream, true once it failed
+    /// `MAX_PAYOUT_FAILURES` times in a row and should be escrowed.
+    pub(crate) fn internal_count_payout_failure(&mut self, stream_id: u64) -> bool {
+        let failures = self.payout_failures.get(&stream_id).unwrap_or(0) + 1;
+        if failures >= MAX_PAYOUT_FAILURES {
+            self.payout_failures.remove(&stream_id);
+            true
+        } else {
+            self.payout_failures.insert(&stream_id, &failures);
+            false
+        }
+    }
+
+    /// Adds an undelivered payout to the account's escrow.
+    pub(crate) fn internal_escrow(
+        &mut self,
+        account_id: AccountId,
+        token: Option<AccountId>,
+        amount: Balance,
+    ) {
+        let key = (account_id, token);
+        let balance = self.payout_escrows.get(&key).unwrap_or(0);
+        self.payout_escrows.insert(&key, &(balance + amount));
+        log_event!("Payout escrowed: {} {}", key.0, amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+    use std::collections::HashMap;
+
+    fn set_context(predecessor: AccountId, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    fn set_failed_result() {
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(env::current_account_id())
+                .block_timestamp(4_000_000_000)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            HashMap::default(),
+            vec![PromiseResult::Failed],
+        );
+    }
+
+    #[test]
+    fn repeatedly_failed_ft_payout_escrowed() {
+        let mut contract = Contract::new();
+        let usn: AccountId = "usdn.testnet".parse().unwrap();
+
+        set_context(usn.clone(), 0);
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"0","end":"10","can_update":false,"can_cancel":false}}"#,
+            accounts(1),
+        );
+        contract.ft_on_transfer(accounts(0), U128(10), msg);
+
+        // the first failures roll the withdrawal back
+        let mut stream = contract.internal_get_stream(1);
+        stream.balance = 6;
+        stream.withdraw_time = 4;
+        for _ in 1..MAX_PAYOUT_FAILURES {
+            set_failed_result();
+            contract.internal_resolve_ft_withdraw(
+                stream.clone(),
+                U128(4),
+                U128(4),
+                accounts(1),
+                None,
+                None,
+            );
+            assert_eq!(contract.internal_get_stream(1).balance, 10);
+        }
+
+        // the last one settles it into bob's escrow
+        set_failed_result();
+        contract.internal_resolve_ft_withdraw(stream, U128(4), U128(4), accounts(1), None, None);
+        assert_eq!(contract.internal_get_stream(1).balance, 6);
+        assert_eq!(contract.get_escrow(accounts(1), Some(usn)), U128(4));
+    }
+
+    #[test]
+    fn repeatedly_failed_batch_payout_escrowed() {
+        let mut contract = Contract::new();
+        let usn: AccountId = "usdn.testnet".parse().unwrap();
+
+        set_context(usn.clone(), 0);
+        for _ in 0..2 {
+            let msg = format!(
+                r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"0","end":"10","can_update":false,"can_cancel":false}}"#,
+                accounts(1),
+            );
+            contract.ft_on_transfer(accounts(0), U128(10), msg);
+        }
+
+        let withdrawals = |contract: &Contract| -> Vec<(Stream, U128, Option<WithdrawCap>)> {
+            (1..3)
+                .map(|id| {
+                    let mut stream = contract.internal_get_stream(id);
+                    stream.balance = 6;
+                    stream.withdraw_time = 4;
+                    (stream, U128(4), None)
+                })
+                .collect()
+        };
+        for _ in 1..MAX_PAYOUT_FAILURES {
+            set_failed_result();
+            contract.internal_resolve_batch_withdraw(withdrawals(&contract));
+            assert_eq!(contract.internal_get_stream(1).balance, 10);
+        }
+
+        // the last one settles both streams into bob's escrow
+        set_failed_result();
+        contract.internal_resolve_batch_withdraw(withdrawals(&contract));
+        assert_eq!(contract.internal_get_stream(1).balance, 6);
+        assert_eq!(contract.internal_get_stream(2).balance, 6);
+        assert_eq!(contract.get_escrow(accounts(1), Some(usn)), U128(8));
+    }
+
+    #[test]
+    fn failed_ft_payout_keeps_withdraw_cap() {
+        let mut contract = Contract::new();
+
+        // 1 USN per second from 1 to 11, at most 3 per 5 seconds
+        set_context("usdn.testnet".parse().unwrap(), 0);
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"1","end":"11","can_update":true,"can_cancel":false}}"#,
+            accounts(1),
+        );
+        contract.ft_on_transfer(accounts(0), U128(10), msg);
+        set_context(accounts(0), 0);
+        contract.set_withdraw_cap(U64(1), Some(U128(3)), U64(5));
+
+        // the cap is only used once the transfer succeeds
+        set_context(accounts(1), 6);
+        let mut stream = contract.internal_get_stream(1);
+        let (amount, cap) = contract.internal_withdraw_accrued(&mut stream);
+        assert_eq!(amount, 3);
+        contract.withdraw(U64(1), None);
+        assert_eq!(contract.withdraw_caps.get(&1).unwrap().withdrawn, 0);
+
+        set_failed_result();
+        contract.internal_resolve_ft_withdraw(
+            stream,
+            U128(amount),
+            U128(amount),
+            accounts(1),
+            cap,
+            None,
+        );
+        assert_eq!(contract.withdraw_caps.get(&1).unwrap().withdrawn, 0);
+
+        // so bob can retry in the same period
+        set_context(accounts(1), 6);
+        contract.withdraw(U64(1), None);
+    }
+
+    #[test]
+    fn failed_payout_escrowed() {
+        let mut contract = Contract::new();
+
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(accounts(0));
+        builder.attached_deposit(10);
+        testing_env!(builder.build());
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+
+        // the payout is resolved by the contract
+        set_context(accounts(1), 4);
+        contract.withdraw(U64(1), None);
+        assert_eq!(get_created_receipts().len(), 2);
+
+        // and escrowed when the transfer fails
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(env::current_account_id())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            HashMap::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.internal_resolve_native_payout(accounts(1), U128(4)));
+        assert_eq!(contract.get_escrow(accounts(1), None), U128(4));
+
+        set_context(accounts(1), 5);
+        contract.claim_escrow(None);
+        assert_eq!(contract.get_escrow(accounts(1), None), U128(0));
+    }
+}
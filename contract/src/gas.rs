@@ -0,0 +1,106 @@
+//! Gas regression checks for the main stream calls, run as unit tests on the
+//! mocked blockchain rather than as sandbox integration tests. The mocked
+//! blockchain counts the gas of host functions (storage, logs, promises) but
+//! not of the wasm itself, so the limits catch storage layout regressions.
+
+use crate::*;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+
+const NEAR: u128 = 1000000000000000000000000;
+
+// limits in Ggas, about 20% above the current use
+const CREATE_NATIVE: u64 = 2_400;
+const CREATE_FT: u64 = 2_400;
+const WITHDRAW_NATIVE: u64 = 3_450 + NATIVE_PAYOUT;
+const WITHDRAW_FT: u64 = 24_000;
+const CANCEL_NATIVE: u64 = 16_300 + NATIVE_PAYOUT;
+const CANCEL_FT: u64 = 24_500;
+
+// the callback receipt that escrows a failed native payout, a fixed cost
+const NATIVE_PAYOUT: u64 = 14_300;
+
+fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+    let mut builder = VMContextBuilder::new();
+    builder.predecessor_account_id(predecessor);
+    builder.attached_deposit(amount);
+    builder.block_timestamp(timestamp * 1e9 as u64);
+    testing_env!(builder.build());
+}
+
+// gas used by `call` in Ggas, the context must be set before
+fn measure(name: &str, limit: u64, call: impl FnOnce()) {
+    let before = env::used_gas();
+    call();
+    let used = (env::used_gas() - before).0 / 10u64.pow(9);
+    assert!(
+        used <= limit,
+        "{} used {} Ggas, limit {}",
+        name,
+        used,
+        limit
+    );
+}
+
+fn create_native(contract: &mut Contract) -> U64 {
+    contract.create_stream(accounts(1), U128(NEAR), U64(10), U64(20), true, true, None)
+}
+
+fn create_ft(contract: &mut Contract) -> U64 {
+    let msg = format!(
+        r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"10","end":"20","can_update":true,"can_cancel":true}}"#,
+        accounts(1),
+    );
+    contract.ft_on_transfer(accounts(0), U128(10), msg);
+    U64(contract.current_id - 1)
+}
+
+#[test]
+fn gas_create() {
+    let mut contract = Contract::new();
+    set_context(accounts(0), 10 * NEAR, 0);
+    measure("create native", CREATE_NATIVE, || {
+        create_native(&mut contract);
+    });
+    set_context("usdn.testnet".parse().unwrap(), 0, 0);
+    measure("create ft", CREATE_FT, || {
+        create_ft(&mut contract);
+    });
+}
+
+#[test]
+fn gas_withdraw() {
+    let mut contract = Contract::new();
+    set_context(accounts(0), 10 * NEAR, 0);
+    let native_id = create_native(&mut contract);
+    set_context("usdn.testnet".parse().unwrap(), 0, 0);
+    let ft_id = create_ft(&mut contract);
+
+    set_context(accounts(1), 0, 15);
+    measure("withdraw native", WITHDRAW_NATIVE, || {
+        contract.withdraw(native_id, None);
+    });
+    set_context(accounts(1), 0, 15);
+    measure("withdraw ft", WITHDRAW_FT, || {
+        contract.withdraw(ft_id, None);
+    });
+}
+
+#[test]
+fn gas_cancel() {
+    let mut contract = Contract::new();
+    set_context(accounts(0), 10 * NEAR, 0);
+    let native_id = create_native(&mut contract);
+    set_context("usdn.testnet".parse().unwrap(), 0, 0);
+    let ft_id = create_ft(&mut contract);
+
+    set_context(accounts(0), 1, 15);
+    measure("cancel native", CANCEL_NATIVE, || {
+        contract.cancel(native_id, None);
+    });
+    set_context(accounts(0), 1, 15);
+    measure("cancel ft", CANCEL_FT, || {
+        contract.cancel(ft_id, None);
+    });
+}
@@ -0,0 +1,303 @@
+use crate::*;
+
+pub const GAS_FOR_NOTIFY: Gas = Gas(5_000_000_000_000);
+pub const MAX_METHOD_NAME_LEN: usize = 64;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum StreamEvent {
+    Created,
+    Cancelled,
+    WithdrawalFailed,
+    EndingSoon,
+    PauseEscalated,
+    Paused,
+    Resumed,
+    CancelledBeforeStart,
+    ToppedUp,
+}
+
+/// Method of another contract called with a `StreamNotification` when one of
+/// `events` happens on a stream the account sends or receives.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Subscription {
+    contract_id: AccountId,
+    method_name: String,
+    events: Vec<StreamEvent>,
+}
+
+// arguments of the notification call
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct StreamNotification {
+    api_revision: u32,
+    event: StreamEvent,
+    stream_id: U64,
+    account_id: AccountId,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Replaces the caller's subscription, notifications are sent with
+    // `GAS_FOR_NOTIFY` and their result is ignored. The storage is paid from
+    // the attached deposit and the rest, or the storage freed, is refunded.
+    #[payable]
+    pub fn subscribe(
+        &mut self,
+        contract_id: AccountId,
+        method_name: String,
+        events: Vec<StreamEvent>,
+    ) {
+        require!(!events.is_empty(), "No events to subscribe to");
+        require!(
+            !method_name.is_empty() && method_name.len() <= MAX_METHOD_NAME_LEN,
+            "Invalid method name"
+        );
+
+        // each event once, so the list is at most as long as the enum
+        let mut unique_events: Vec<StreamEvent> = vec![];
+        for event in events {
+            if !unique_events.contains(&event) {
+                unique_events.push(event);
+            }
+        }
+
+        let account_id = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        self.subscriptions.insert(
+            &account_id,
+            &Subscription {
+                contract_id,
+                method_name,
+                events: unique_events,
+            },
+        );
+        log_event!("Subscribed: {}", account_id);
+
+        let final_storage = env::storage_usage();
+        let refund = if final_storage > initial_storage {
+            let cost = Balance::from(final_storage - initial_storage) * env::storage_byte_cost();
+            require!(
+                env::attached_deposit() >= cost,
+                "The amount provided is not enough for the subscription storage"
+            );
+            env::attached_deposit() - cost
+        } else {
+            env::attached_deposit()
+                + Balance::from(initial_storage - final_storage) * env::storage_byte_cost()
+        };
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+    }
+
+    // Calls `on_stream_withdrawn(stream_id, amount)` on the receiver, a contract,
+    // after each payout of the stream has arrived. Set by the receiver.
+    pub fn set_withdraw_callback(&mut self, stream_id: U64, enabled: bool) {
+        let stream = self.internal_get_stream(stream_id.0);
+        require!(
+            env::predecessor_account_id() == stream.receiver,
+            "Only the receiver can set the withdraw callback"
+        );
+
+        if enabled {
+            // a backup would be paid while the receiver is called
+            require!(
+                self.backups.get(&stream_id.0).is_none(),
+                "Cannot call back a stream with a backup"
+            );
+            self.withdraw_callbacks.insert(&stream_id.0);
+            log_event!("Withdraw callback set: {}", stream_id.0);
+        } else {
+            self.withdraw_callbacks.remove(&stream_id.0);
+            log_event!("Withdraw callback removed: {}", stream_id.0);
+        }
+    }
+
+    // Removes the caller's subscription and refunds the storage freed
+    pub fn unsubscribe(&mut self) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        require!(
+            self.subscriptions.remove(&account_id).is_some(),
+            "Not subscribed"
+        );
+        log_event!("Unsubscribed: {}", account_id);
+
+        let refund = Balance::from(initial_storage.saturating_sub(env::storage_usage()))
+            * env::storage_byte_cost();
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+    }
+}
+
+impl Contract {
+    /// Adds the `on_stream_withdrawn` call to a promise to the receiver if the
+    /// receiver asked for it. `amount` is what the receiver got.
+    pub(crate) fn internal_with_withdraw_callback(
+        &self,
+        promise: Promise,
+        stream: &Stream,
+        amount: Balance,
+    ) -> Promise {
+        if amount == 0 || !self.withdraw_callbacks.contains(&stream.id) {
+            return promise;
+        }
+
+        let args = near_sdk::serde_json::json!({
+            "stream_id": U64(stream.id),
+            "amount": U128(amount),
+        });
+        promise.function_call(
+            "on_stream_withdrawn".to_string(),
+            args.to_string().into_bytes(),
+            0,
+            GAS_FOR_NOTIFY,
+        )
+    }
+
+    /// Notifies the sender and the receiver of the stream if they subscribed to `event`.
+    pub(crate) fn internal_notify(&self, stream: &Stream, event: StreamEvent) {
+        for account_id in [&stream.sender, &stream.receiver] {
+            let subscription = match self.subscriptions.get(account_id) {
+                Some(subscription) if subscription.events.contains(&event) => subscription,
+                _ => continue,
+            };
+
+            let args = near_sdk::serde_json::to_vec(&StreamNotification {
+                api_revision: API_REVISION,
+                event,
+                stream_id: U64(stream.id),
+                account_id: account_id.clone(),
+            })
+            .unwrap();
+            Promise::new(subscription.contract_id).function_call(
+                subscription.method_name,
+                args,
+                0,
+                GAS_FOR_NOTIFY,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn call_back_receiver() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+
+        // bob is a contract that wants to know about payouts
+        set_context_with_balance(accounts(1), 0);
+        contract.set_withdraw_callback(U64(1), true);
+
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(accounts(1));
+        builder.block_timestamp(4_000_000_000);
+        testing_env!(builder.build());
+        contract.withdraw(U64(1), None);
+
+        // the transfer and the call are in the same receipt, then the escrow callback
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].actions.len(), 2);
+    }
+
+    #[test]
+    fn notify_subscribed_events() {
+        let mut contract = Contract::new();
+
+        // bob is notified when his streams are cancelled
+        set_context_with_balance(accounts(1), NEAR);
+        contract.subscribe(
+            accounts(3),
+            "on_stream".to_string(),
+            vec![StreamEvent::Cancelled, StreamEvent::Cancelled],
+        );
+        assert_eq!(
+            contract.get_subscription(accounts(1)).unwrap().events.len(),
+            1
+        );
+
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+        assert!(get_created_receipts().is_empty());
+
+        set_context_with_balance(accounts(0), 0);
+        contract.cancel(U64(1), None);
+        let receipts = get_created_receipts();
+        let notification = receipts
+            .iter()
+            .find(|r| r.receiver_id == accounts(3))
+            .unwrap();
+        match &notification.actions[0] {
+            near_sdk::mock::VmAction::FunctionCall { args, .. } => {
+                let args: near_sdk::serde_json::Value =
+                    near_sdk::serde_json::from_slice(args).unwrap();
+                assert_eq!(args["api_revision"], API_REVISION);
+                assert_eq!(args["event"], "cancelled");
+            }
+            _ => panic!("Expected a function call"),
+        }
+    }
+
+    #[test]
+    fn notify_paused() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(1), NEAR);
+        contract.subscribe(
+            accounts(3),
+            "on_stream".to_string(),
+            vec![StreamEvent::Paused],
+        );
+
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(accounts(0));
+        builder.block_timestamp(4_000_000_000);
+        testing_env!(builder.build());
+        contract.pause(U64(1), None);
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, accounts(3));
+
+        // not subscribed to resumes
+        contract.resume(U64(1));
+        assert_eq!(get_created_receipts().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the subscription storage")]
+    fn subscription_needs_storage_deposit() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(1), 0);
+        contract.subscribe(
+            accounts(3),
+            "on_stream".to_string(),
+            vec![StreamEvent::Paused],
+        );
+    }
+}
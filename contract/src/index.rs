@@ -0,0 +1,144 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    // Stops (or restarts) listing new streams to the caller in the receiver
+    // index used by the views, streams already listed stay until trimmed
+    pub fn set_receiver_index(&mut self, enabled: bool) {
+        let receiver = env::predecessor_account_id();
+        if enabled {
+            self.receiver_index_opt_outs.remove(&receiver);
+            log_event!("Receiver index enabled: {}", receiver);
+        } else {
+            self.receiver_index_opt_outs.insert(&receiver);
+            log_event!("Receiver index disabled: {}", receiver);
+        }
+    }
+
+    // Removes streams from the caller's receiver index, the streams themselves
+    // are not changed and can still be read with `get_stream`
+    pub fn trim_receiver_index(&mut self, stream_ids: Vec<U64>) {
+        let receiver = env::predecessor_account_id();
+        let mut receiver_ids = self
+            .receiver_streams
+            .get(&receiver)
+            .expect("No streams to trim");
+        for stream_id in stream_ids {
+            receiver_ids.remove(&stream_id.0);
+        }
+        self.receiver_streams.insert(&receiver, &receiver_ids);
+        log_event!(
+            "Receiver index trimmed: {} {}",
+            receiver,
+            receiver_ids.len()
+        );
+    }
+
+    // Adds the caller's streams back to its receiver index, the storage is
+    // paid from the attached deposit and the rest is refunded
+    #[payable]
+    pub fn restore_receiver_index(&mut self, stream_ids: Vec<U64>) {
+        let receiver = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        for stream_id in stream_ids {
+            let stream = self.internal_get_stream(stream_id.0);
+            require!(
+                stream.receiver == receiver,
+                "Not the receiver of the stream"
+            );
+            self.internal_index_received_stream(&stream);
+        }
+
+        let cost = Balance::from(env::storage_usage().saturating_sub(initial_storage))
+            * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() >= cost,
+            "The amount provided is not enough for the index storage"
+        );
+        if env::attached_deposit() > cost {
+            Promise::new(receiver).transfer(env::attached_deposit() - cost);
+        }
+    }
+
+    pub fn is_receiver_indexed(&self, receiver: AccountId) -> bool {
+        !self.receiver_index_opt_outs.contains(&receiver)
+    }
+}
+
+impl Contract {
+    /// Lists the stream in its receiver's index unless the receiver opted out.
+    pub(crate) fn internal_index_received_stream(&mut self, stream: &Stream) {
+        if self.receiver_index_opt_outs.contains(&stream.receiver) {
+            return;
+        }
+
+        let mut receiver_ids = self
+            .receiver_streams
+            .get(&stream.receiver)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::ReceiverStreamsInner {
+                    account_hash: env::sha256_array(stream.receiver.as_bytes()),
+                })
+            });
+        receiver_ids.insert(&stream.id);
+        self.receiver_streams
+            .insert(&stream.receiver, &receiver_ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::views::StreamRole;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn opt_out_and_trim() {
+        let mut contract = Contract::new();
+        let received = |contract: &Contract| {
+            contract
+                .get_streams_by_filter(
+                    accounts(1),
+                    None,
+                    None,
+                    Some(StreamRole::Receiver),
+                    None,
+                    None,
+                )
+                .len()
+        };
+
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+
+        // bob opts out, the next stream is not listed
+        set_context_with_balance(accounts(1), 0);
+        contract.set_receiver_index(false);
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+        assert_eq!(received(&contract), 1);
+
+        // and trims the first one
+        set_context_with_balance(accounts(1), 0);
+        contract.trim_receiver_index(vec![U64(1)]);
+        assert_eq!(received(&contract), 0);
+        assert_eq!(contract.get_stream(U64(1)).receiver, accounts(1));
+
+        contract.restore_receiver_index(vec![U64(1), U64(2)]);
+        assert_eq!(received(&contract), 0);
+        contract.set_receiver_index(true);
+        set_context_with_balance(accounts(1), NEAR);
+        contract.restore_receiver_index(vec![U64(1), U64(2)]);
+        assert_eq!(received(&contract), 2);
+    }
+}
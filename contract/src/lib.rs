@@ -1,19 +1,85 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
+use near_sdk::collections::{LookupMap, LookupSet, TreeMap, UnorderedSet, Vector};
 use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    env, ext_contract, log, near_bindgen, require, AccountId, Balance, Gas, PanicOnDefault,
-    Promise, PromiseOrValue, PromiseResult, Timestamp,
+    env, ext_contract, near_bindgen, require, AccountId, Balance, BorshStorageKey, CryptoHash, Gas,
+    PanicOnDefault, Promise, PromiseOrValue, PromiseResult, Timestamp,
 };
 
+// logs an event as is, followed by the same event in the NEP-297 format with
+// the API revision it is emitted under as the version
+macro_rules! log_event {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        near_sdk::log!("{}", message);
+        near_sdk::log!(
+            "EVENT_JSON:{}",
+            near_sdk::serde_json::json!({
+                "standard": "zebec",
+                "version": $crate::API_REVISION.to_string(),
+                "event": "log",
+                "data": [{ "message": message }],
+            })
+        );
+    }};
+}
+
+mod approvals;
+mod assignments;
+mod bundles;
 mod calls;
+mod children;
+mod deposits;
+mod escrow;
+#[cfg(test)]
+mod gas;
+mod hooks;
+mod index;
+mod matching;
+mod migrate;
+mod notes;
+mod pauses;
+mod payouts;
+mod policies;
+mod profiles;
+mod renewals;
+mod senders;
+mod simulations;
+mod staking;
+#[cfg(feature = "testing")]
+mod testing;
+mod topups;
+mod updates;
 mod views;
+mod watchlists;
+
+use approvals::Backup;
+use bundles::Bundle;
+use children::ChildStream;
+use hooks::{StreamEvent, Subscription};
+use matching::MatchingPool;
+use pauses::{PauseQuota, PauseRecourse};
+use payouts::PendingPayout;
+use policies::{AccrualPeriod, AccrualPolicy};
+use profiles::Profile;
+use senders::SenderFilter;
+use staking::StakedFunding;
+use updates::PendingUpdate;
 
 pub const CREATE_STREAM_DEPOSIT: Balance = 100_000_000_000_000_000_000_000; // 0.1 NEAR
 pub const ONE_YOCTO: Balance = 1;
 pub const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000; // 1 NEAR
 pub const MAX_RATE: Balance = 100_000_000_000_000_000_000_000_000; // 100 NEAR
+pub const MAX_TOKENS_PER_SECOND: u128 = 100; // ft rate limit in whole tokens
+pub const MAX_ESCALATION_PERIODS: u64 = 1_000;
+pub const MAX_REASON_LEN: usize = 128; // bytes of a pause or cancel reason
+pub const API_REVISION: u32 = 2; // bumped on every change of the methods, views or events
+pub const NONCE_STREAM_ID_FLAG: u64 = 1 << 63; // set in the ids of streams created with a nonce
+
+// whitelisted stablecoin contracts and their decimals
+// @todo add valid stablecoins (from mainnet) address here later
+pub const FT_TOKENS: [(&str, u8); 2] = [("usdn.testnet", 18), ("wrap.testnet", 24)];
 pub const NO_DEPOSIT: u128 = 0; // Attach no deposit.
 
 /// 10T gas for basic operation
@@ -31,7 +97,135 @@ pub const GAS_FOR_FT_TRANSFER: Gas = Gas(20_000_000_000_000);
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
     current_id: u64,
-    streams: UnorderedMap<u64, Stream>,
+    streams: LookupMap<u64, StreamRecord>,
+    stream_states: LookupMap<u64, StreamState>,
+    stream_ids: UnorderedSet<u64>,
+    sender_streams: LookupMap<AccountId, UnorderedSet<u64>>,
+    receiver_streams: LookupMap<AccountId, UnorderedSet<u64>>,
+    payments: Vector<Payment>,
+    account_payments: LookupMap<AccountId, Vector<u64>>,
+    withdraw_caps: LookupMap<u64, WithdrawCap>,
+    bonds: LookupMap<u64, Bond>,
+    stream_ends: TreeMap<(Timestamp, u64), ()>, // (end_time, id) of streams not cancelled
+    monthly_totals: LookupMap<(AccountId, Option<AccountId>, u32), MonthlyTotal>, // (account, token, month)
+    matching_pools: Vector<MatchingPool>,
+    stream_matching_pools: LookupMap<u64, u64>,
+    pair_proposals: LookupMap<u64, u64>, // stream id -> proposed paired stream id
+    stream_pairs: LookupMap<u64, u64>,   // both directions
+    netting_streams: LookupSet<u64>,     // paired streams whose receiver opted in to netting
+    rate_escalations: LookupMap<u64, RateEscalation>,
+    withholdings: LookupMap<u64, Withholding>,
+    withheld_balances: LookupMap<(AccountId, Option<AccountId>), Balance>, // (account, token)
+    bundles: Vector<Bundle>,
+    accounts: UnorderedSet<AccountId>, // every sender and receiver
+    subscriptions: LookupMap<AccountId, Subscription>,
+    approvals: LookupSet<(u64, AccountId, AccountId)>, // (stream, approver, operator)
+    backups: LookupMap<u64, Backup>,
+    rate_changes: LookupMap<u64, RateChange>,
+    stop_reasons: LookupMap<u64, String>, // reason of the last pause or cancel
+    payout_intervals: LookupMap<AccountId, u32>,
+    pending_payouts: LookupMap<(AccountId, Option<AccountId>), PendingPayout>, // (account, token)
+    withdraw_callbacks: LookupSet<u64>,
+    sender_filters: LookupMap<AccountId, SenderFilter>,
+    filtered_senders: LookupSet<(AccountId, AccountId)>, // (receiver, sender)
+    renewal_windows: LookupMap<AccountId, u32>,
+    ending_soon: UnorderedSet<u64>,
+    receiver_statements: LookupMap<(AccountId, Option<AccountId>), ReceiverStatement>, // (receiver, token)
+    pending_updates: LookupMap<u64, PendingUpdate>,
+    stream_notes: LookupMap<(u64, AccountId), String>, // (stream id, sender or receiver)
+    staked_fundings: LookupMap<u64, StakedFunding>,
+    staking_pools: LookupSet<AccountId>, // pools that fund a stream
+    receiver_index_opt_outs: LookupSet<AccountId>,
+    profiles: LookupMap<(AccountId, String), Profile>, // (owner, name)
+    stream_profiles: LookupMap<u64, (AccountId, String)>,
+    profile_streams: LookupMap<(AccountId, String), Vector<u64>>,
+    ft_deposits: LookupMap<(AccountId, AccountId), Balance>, // (account, token)
+    accrual_policies: LookupMap<u64, AccrualPolicy>,
+    withdraw_nonces: LookupMap<(u64, AccountId), u64>, // (stream id, caller) -> last nonce
+    pause_quotas: LookupMap<u64, PauseQuota>,
+    pause_recourses: LookupMap<u64, PauseRecourse>,
+    escalated_pauses: LookupSet<u64>, // paused streams whose receiver escalated
+    child_streams: LookupMap<u64, ChildStream>, // parent id -> child
+    parent_streams: LookupMap<u64, u64>, // child id -> parent id
+    pause_totals: LookupMap<u64, u64>, // seconds paused before the last resume
+    payout_escrows: LookupMap<(AccountId, Option<AccountId>), Balance>, // (account, token)
+    payout_failures: LookupMap<u64, u32>, // failed ft payouts in a row
+    accrual_periods: LookupMap<u64, AccrualPeriod>,
+    receivable_assignments: LookupMap<u64, AccountId>, // stream id -> proposed receiver
+    watchlists: LookupMap<AccountId, UnorderedSet<u64>>,
+    cliff_times: LookupMap<u64, Timestamp>,
+    stream_remainders: LookupMap<u64, Balance>, // of streams created from an amount
+}
+
+// Storage prefixes for the contract collections
+#[derive(BorshStorageKey, BorshSerialize)]
+pub(crate) enum StorageKey {
+    Streams,
+    StreamIds,
+    SenderStreams,
+    SenderStreamsInner { account_hash: CryptoHash },
+    ReceiverStreams,
+    ReceiverStreamsInner { account_hash: CryptoHash },
+    StreamStates,
+    Payments,
+    AccountPayments,
+    AccountPaymentsInner { account_hash: CryptoHash },
+    WithdrawCaps,
+    Bonds,
+    StreamEnds,
+    MonthlyTotals,
+    MatchingPools,
+    StreamMatchingPools,
+    PairProposals,
+    StreamPairs,
+    NettingStreams,
+    RateEscalations,
+    Withholdings,
+    WithheldBalances,
+    Bundles,
+    Accounts,
+    // only used with the `testing` feature, but keeps the keys of later variants
+    #[cfg_attr(not(feature = "testing"), allow(dead_code))]
+    TestTimestamp,
+    Subscriptions,
+    Approvals,
+    Backups,
+    RateChanges,
+    StopReasons,
+    PayoutIntervals,
+    PendingPayouts,
+    WithdrawCallbacks,
+    SenderFilters,
+    FilteredSenders,
+    RenewalWindows,
+    EndingSoon,
+    ReceiverStatements,
+    PendingUpdates,
+    StreamNotes,
+    StakedFundings,
+    StakingPools,
+    ReceiverIndexOptOuts,
+    Profiles,
+    StreamProfiles,
+    ProfileStreams,
+    ProfileStreamsInner { profile_hash: CryptoHash },
+    FtDeposits,
+    AccrualPolicies,
+    WithdrawNonces,
+    PauseQuotas,
+    PauseRecourses,
+    EscalatedPauses,
+    ChildStreams,
+    ParentStreams,
+    PauseTotals,
+    PayoutEscrows,
+    PayoutFailures,
+    AccrualPeriods,
+    ReceivableAssignments,
+    Watchlists,
+    WatchlistsInner { account_hash: CryptoHash },
+    CliffTimes,
+    StreamRemainders,
 }
 // Define the stream structure
 #[near_bindgen]
@@ -56,6 +250,246 @@ pub struct Stream {
     is_native: bool,
 }
 
+// Bits of `StreamRecord::flags`
+const FLAG_CAN_UPDATE: u8 = 1 << 0;
+const FLAG_CAN_CANCEL: u8 = 1 << 1;
+
+// Bits of `StreamState::flags`
+const FLAG_PAUSED: u8 = 1 << 0;
+const FLAG_CANCELLED: u8 = 1 << 1;
+
+/// Creation data of a `Stream`, only rewritten by `update`. The id is the map key,
+/// timestamps are u32 seconds and native streams store no token id.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StreamRecord {
+    sender: AccountId,
+    receiver: AccountId,
+    rate: Balance,
+    created: u32,
+    start_time: u32,
+    end_time: u32,
+    contract_id: Option<AccountId>, // None for native stream
+    flags: u8,
+}
+
+/// Fields of a `Stream` that change on withdraw, pause, resume and cancel.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StreamState {
+    balance: Balance,
+    withdraw_time: u32,
+    paused_time: u32,
+    flags: u8,
+}
+
+// the block timestamp in seconds
+#[cfg(not(feature = "testing"))]
+pub(crate) fn current_time() -> Timestamp {
+    env::block_timestamp_ms() / 1000
+}
+
+// the block timestamp in seconds, unless overridden with `set_test_timestamp`
+#[cfg(feature = "testing")]
+pub(crate) fn current_time() -> Timestamp {
+    testing::test_timestamp().unwrap_or_else(|| env::block_timestamp_ms() / 1000)
+}
+
+fn compact_timestamp(timestamp: Timestamp) -> u32 {
+    require!(timestamp <= u32::MAX.into(), "Timestamp is too large");
+    timestamp as u32
+}
+
+// calendar month of a unix timestamp (seconds) as `year * 12 + month - 1`
+fn calendar_month(timestamp: Timestamp) -> u32 {
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = timestamp / 86_400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // 0 is March
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year * 12 + month - 1) as u32
+}
+
+// id of the stream `sender` creates with `nonce`: the first 8 bytes of
+// sha256(sender || nonce as u64 little endian) read as little endian, with
+// `NONCE_STREAM_ID_FLAG` set so that it is never a counter id
+pub(crate) fn nonce_stream_id(sender: &AccountId, nonce: u64) -> u64 {
+    let mut preimage = sender.as_bytes().to_vec();
+    preimage.extend_from_slice(&nonce.to_le_bytes());
+    let hash = env::sha256_array(&preimage);
+    u64::from_le_bytes(hash[..8].try_into().unwrap()) | NONCE_STREAM_ID_FLAG
+}
+
+// unix timestamp (seconds) of the first day of a month from `calendar_month`
+fn month_start(month: u32) -> Timestamp {
+    // civil date to days, the inverse of the above
+    let (year, month) = (u64::from(month / 12), u64::from(month % 12) + 1);
+    let year = year - u64::from(month <= 2);
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era - 719_468) * 86_400
+}
+
+fn pack_flags(flags: &[(bool, u8)]) -> u8 {
+    flags
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |packed, (_, flag)| packed | flag)
+}
+
+impl From<&Stream> for StreamRecord {
+    fn from(stream: &Stream) -> Self {
+        Self {
+            sender: stream.sender.clone(),
+            receiver: stream.receiver.clone(),
+            rate: stream.rate,
+            created: compact_timestamp(stream.created),
+            start_time: compact_timestamp(stream.start_time),
+            end_time: compact_timestamp(stream.end_time),
+            contract_id: if stream.is_native {
+                None
+            } else {
+                Some(stream.contract_id.clone())
+            },
+            flags: pack_flags(&[
+                (stream.can_update, FLAG_CAN_UPDATE),
+                (stream.can_cancel, FLAG_CAN_CANCEL),
+            ]),
+        }
+    }
+}
+
+impl From<&Stream> for StreamState {
+    fn from(stream: &Stream) -> Self {
+        Self {
+            balance: stream.balance,
+            withdraw_time: compact_timestamp(stream.withdraw_time),
+            paused_time: compact_timestamp(stream.paused_time),
+            flags: pack_flags(&[
+                (stream.is_paused, FLAG_PAUSED),
+                (stream.is_cancelled, FLAG_CANCELLED),
+            ]),
+        }
+    }
+}
+
+impl StreamRecord {
+    fn into_stream(self, id: u64, state: StreamState) -> Stream {
+        Stream {
+            id,
+            sender: self.sender,
+            receiver: self.receiver,
+            balance: state.balance,
+            rate: self.rate,
+            created: self.created.into(),
+            start_time: self.start_time.into(),
+            end_time: self.end_time.into(),
+            withdraw_time: state.withdraw_time.into(),
+            is_paused: state.flags & FLAG_PAUSED != 0,
+            is_cancelled: state.flags & FLAG_CANCELLED != 0,
+            paused_time: state.paused_time.into(),
+            is_native: self.contract_id.is_none(),
+            // this will be ignored for native stream
+            contract_id: self
+                .contract_id
+                .unwrap_or_else(|| "near.testnet".parse().unwrap()),
+            can_update: self.flags & FLAG_CAN_UPDATE != 0,
+            can_cancel: self.flags & FLAG_CAN_CANCEL != 0,
+        }
+    }
+}
+
+/// A payout to the receiver of a stream. The receiver is kept as the stream may
+/// be assigned later, the sender and token are read from the stream.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Payment {
+    stream_id: u64,
+    receiver: AccountId,
+    amount: Balance,
+    timestamp: u32,
+}
+
+/// Limit on the receiver's withdrawals of a stream: at most `amount` per `period`
+/// seconds. The first period begins at the stream start, the next ones at the
+/// first withdrawal after the previous period ended.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawCap {
+    amount: Balance,
+    period: u32,
+    period_start: u32,
+    withdrawn: Balance, // withdrawn since `period_start`
+}
+
+/// NEAR locked by the sender of a stream, forfeited to the receiver if the stream
+/// is cancelled before `commit_end` and returned to the sender otherwise.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bond {
+    amount: Balance,
+    commit_end: u32,
+}
+
+/// Amounts paid out by an account's streams in one token and calendar month.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct MonthlyTotal {
+    streamed_in: Balance,  // as receiver
+    streamed_out: Balance, // as sender
+}
+
+/// Everything paid out to a receiver's streams in one token since they were created.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct ReceiverStatement {
+    received: Balance, // after withholding
+    withheld: Balance,
+    payments: u64,
+}
+
+/// Periodic raise of a stream's rate, see `set_rate_escalation`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateEscalation {
+    bps: u32, // added to the rate every period, 100 is 1%
+    period: u32,
+}
+
+/// Rate of a stream from `effective_at` on, see `set_rate_change`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateChange {
+    rate: Balance,
+    effective_at: u32,
+}
+
+// the caller's nonce of a withdraw waiting for its ft transfer: caller, nonce
+// used and the one before
+type WithdrawNonce = (AccountId, U64, Option<U64>);
+
+// one transfer of `withdraw_batch`: token (None for NEAR), the withdrawals
+// from its streams and the total amount
+type BatchTransfer = (
+    Option<AccountId>,
+    Vec<(Stream, U128, Option<WithdrawCap>)>,
+    Balance,
+);
+
+/// Share of a stream's payouts withheld for another account, see `set_withholding`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Withholding {
+    account_id: AccountId,
+    bps: u32,
+}
+
 #[ext_contract(ext_ft_transfer)]
 trait FungibleTokenCore {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
@@ -74,10 +508,70 @@ impl Contract {
         require!(!env::state_exists(), "Already initialized");
         Self {
             current_id: 1,
-            streams: UnorderedMap::new(b"p"),
+            streams: LookupMap::new(StorageKey::Streams),
+            stream_states: LookupMap::new(StorageKey::StreamStates),
+            stream_ids: UnorderedSet::new(StorageKey::StreamIds),
+            sender_streams: LookupMap::new(StorageKey::SenderStreams),
+            receiver_streams: LookupMap::new(StorageKey::ReceiverStreams),
+            payments: Vector::new(StorageKey::Payments),
+            account_payments: LookupMap::new(StorageKey::AccountPayments),
+            withdraw_caps: LookupMap::new(StorageKey::WithdrawCaps),
+            bonds: LookupMap::new(StorageKey::Bonds),
+            stream_ends: TreeMap::new(StorageKey::StreamEnds),
+            monthly_totals: LookupMap::new(StorageKey::MonthlyTotals),
+            matching_pools: Vector::new(StorageKey::MatchingPools),
+            stream_matching_pools: LookupMap::new(StorageKey::StreamMatchingPools),
+            pair_proposals: LookupMap::new(StorageKey::PairProposals),
+            stream_pairs: LookupMap::new(StorageKey::StreamPairs),
+            netting_streams: LookupSet::new(StorageKey::NettingStreams),
+            rate_escalations: LookupMap::new(StorageKey::RateEscalations),
+            withholdings: LookupMap::new(StorageKey::Withholdings),
+            withheld_balances: LookupMap::new(StorageKey::WithheldBalances),
+            bundles: Vector::new(StorageKey::Bundles),
+            accounts: UnorderedSet::new(StorageKey::Accounts),
+            subscriptions: LookupMap::new(StorageKey::Subscriptions),
+            approvals: LookupSet::new(StorageKey::Approvals),
+            backups: LookupMap::new(StorageKey::Backups),
+            rate_changes: LookupMap::new(StorageKey::RateChanges),
+            stop_reasons: LookupMap::new(StorageKey::StopReasons),
+            payout_intervals: LookupMap::new(StorageKey::PayoutIntervals),
+            pending_payouts: LookupMap::new(StorageKey::PendingPayouts),
+            withdraw_callbacks: LookupSet::new(StorageKey::WithdrawCallbacks),
+            sender_filters: LookupMap::new(StorageKey::SenderFilters),
+            filtered_senders: LookupSet::new(StorageKey::FilteredSenders),
+            renewal_windows: LookupMap::new(StorageKey::RenewalWindows),
+            ending_soon: UnorderedSet::new(StorageKey::EndingSoon),
+            receiver_statements: LookupMap::new(StorageKey::ReceiverStatements),
+            pending_updates: LookupMap::new(StorageKey::PendingUpdates),
+            stream_notes: LookupMap::new(StorageKey::StreamNotes),
+            staked_fundings: LookupMap::new(StorageKey::StakedFundings),
+            staking_pools: LookupSet::new(StorageKey::StakingPools),
+            receiver_index_opt_outs: LookupSet::new(StorageKey::ReceiverIndexOptOuts),
+            profiles: LookupMap::new(StorageKey::Profiles),
+            stream_profiles: LookupMap::new(StorageKey::StreamProfiles),
+            profile_streams: LookupMap::new(StorageKey::ProfileStreams),
+            ft_deposits: LookupMap::new(StorageKey::FtDeposits),
+            accrual_policies: LookupMap::new(StorageKey::AccrualPolicies),
+            withdraw_nonces: LookupMap::new(StorageKey::WithdrawNonces),
+            pause_quotas: LookupMap::new(StorageKey::PauseQuotas),
+            pause_recourses: LookupMap::new(StorageKey::PauseRecourses),
+            escalated_pauses: LookupSet::new(StorageKey::EscalatedPauses),
+            child_streams: LookupMap::new(StorageKey::ChildStreams),
+            parent_streams: LookupMap::new(StorageKey::ParentStreams),
+            pause_totals: LookupMap::new(StorageKey::PauseTotals),
+            payout_escrows: LookupMap::new(StorageKey::PayoutEscrows),
+            payout_failures: LookupMap::new(StorageKey::PayoutFailures),
+            accrual_periods: LookupMap::new(StorageKey::AccrualPeriods),
+            receivable_assignments: LookupMap::new(StorageKey::ReceivableAssignments),
+            watchlists: LookupMap::new(StorageKey::Watchlists),
+            cliff_times: LookupMap::new(StorageKey::CliffTimes),
+            stream_remainders: LookupMap::new(StorageKey::StreamRemainders),
         }
     }
 
+    // `cliff_time` is an optional time between the start and the end before
+    // which the receiver cannot withdraw, what streamed until then unlocks at once
+    #[allow(clippy::too_many_arguments)]
     #[payable]
     pub fn create_stream(
         &mut self,
@@ -87,13 +581,103 @@ impl Contract {
         end: U64,
         can_cancel: bool,
         can_update: bool,
+        cliff_time: Option<U64>,
+    ) -> U64 {
+        self.internal_create_stream(
+            receiver,
+            stream_rate,
+            start,
+            end,
+            can_cancel,
+            can_update,
+            cliff_time,
+            0,
+            None,
+        )
+    }
+
+    // Same as `create_stream` but the id is derived from the caller and `nonce`
+    // instead of the counter, see `get_nonce_stream_id`. A nonce creates at
+    // most one stream, so a retried creation fails instead of creating another.
+    #[allow(clippy::too_many_arguments)]
+    #[payable]
+    pub fn create_stream_with_nonce(
+        &mut self,
+        receiver: AccountId,
+        stream_rate: U128,
+        start: U64,
+        end: U64,
+        can_cancel: bool,
+        can_update: bool,
+        cliff_time: Option<U64>,
+        nonce: U64,
+    ) -> U64 {
+        self.internal_create_stream(
+            receiver,
+            stream_rate,
+            start,
+            end,
+            can_cancel,
+            can_update,
+            cliff_time,
+            0,
+            Some(nonce.0),
+        )
+    }
+
+    // Creates a stream of the attached `total_amount`, the rate is derived from
+    // it and the remainder that doesn't make up a whole second is paid with the
+    // last second, so the receiver gets exactly `total_amount`
+    #[allow(clippy::too_many_arguments)]
+    #[payable]
+    pub fn create_stream_with_amount(
+        &mut self,
+        receiver: AccountId,
+        total_amount: U128,
+        start: U64,
+        end: U64,
+        can_cancel: bool,
+        can_update: bool,
+        cliff_time: Option<U64>,
+    ) -> U64 {
+        require!(end.0 > start.0, "Start time should be less than end time");
+        let duration = u128::from(end.0 - start.0);
+        require!(
+            total_amount.0 >= duration,
+            "Amount is smaller than the stream duration"
+        );
+        self.internal_create_stream(
+            receiver,
+            U128(total_amount.0 / duration),
+            start,
+            end,
+            can_cancel,
+            can_update,
+            cliff_time,
+            total_amount.0 % duration,
+            None,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn internal_create_stream(
+        &mut self,
+        receiver: AccountId,
+        stream_rate: U128,
+        start: U64,
+        end: U64,
+        can_cancel: bool,
+        can_update: bool,
+        cliff_time: Option<U64>,
+        remainder: Balance,
+        nonce: Option<u64>,
     ) -> U64 {
         // convert id to native u128
         let rate: u128 = stream_rate.0;
         let start_time: u64 = start.0;
         let end_time: u64 = end.0;
 
-        let current_timestamp: u64 = env::block_timestamp_ms() / 1000;
+        let current_timestamp: u64 = current_time();
         // Check the start and end timestamp is valid
         require!(
             start_time >= current_timestamp,
@@ -103,6 +687,7 @@ impl Contract {
 
         // Check the receiver and sender are not same
         require!(receiver != env::predecessor_account_id(), "Sender and receiver cannot be Same");
+        self.internal_check_sender(&receiver, &env::predecessor_account_id());
 
         // check the rate is valid
         require!(rate > 0, "Rate cannot be zero");
@@ -110,7 +695,7 @@ impl Contract {
 
         // calculate the balance is enough
         let stream_duration = end_time - start_time;
-        let stream_amount = u128::from(stream_duration) * rate;
+        let stream_amount = u128::from(stream_duration) * rate + remainder;
 
         // check the amount send to the stream
         require!(
@@ -124,7 +709,17 @@ impl Contract {
             "Sender and receiver cannot be the same"
         );
 
-        let params_key = self.current_id;
+        let params_key = match nonce {
+            Some(nonce) => {
+                let id = nonce_stream_id(&env::predecessor_account_id(), nonce);
+                require!(
+                    !self.streams.contains_key(&id),
+                    "Stream with this nonce already exists"
+                );
+                id
+            }
+            None => self.current_id,
+        };
         let near_token_id: AccountId = "near.testnet".parse().unwrap(); // this will be ignored for native stream
 
         let stream_params = Stream {
@@ -147,16 +742,23 @@ impl Contract {
         };
 
         // Save the stream
-        self.streams.insert(&params_key, &stream_params);
+        self.internal_set_cliff(&stream_params, cliff_time);
+        if remainder > 0 {
+            self.stream_remainders.insert(&params_key, &remainder);
+        }
+        self.internal_add_stream(&stream_params);
 
         // Update the global stream count for next stream
-        self.current_id += 1;
+        if nonce.is_none() {
+            self.current_id += 1;
+        }
 
-        log!("Saving streams {}", stream_params.id);
+        log_event!("Saving streams {}", stream_params.id);
 
         U64::from(params_key)
     }
 
+    #[payable]
     pub fn update(
         &mut self,
         stream_id: U64,
@@ -166,10 +768,10 @@ impl Contract {
     ) {
         // convert to native u64
         let id: u64 = stream_id.0;
-        let current_timestamp: u64 = env::block_timestamp_ms() / 1000;
+        let current_timestamp: u64 = current_time();
 
         // get the stream
-        let mut stream = self.streams.get(&id).unwrap();
+        let mut stream = self.internal_get_stream(id);
 
         // check the stream can be udpated
         require!(env::predecessor_account_id() == stream.sender, "You are not authorized to update this stream");
@@ -200,444 +802,2203 @@ impl Contract {
         require!(rate > 0, "Rate cannot be zero");
 
         // check the rate is valid
-        require!(rate < MAX_RATE, "Rate is too high");
+        require!(rate < calls::stream_max_rate(&stream), "Rate is too high");
 
+        let previous_end_time = stream.end_time;
         stream.start_time = start_time;
         stream.withdraw_time = start_time;
         stream.end_time = end_time;
         stream.rate = rate;
+        self.internal_check_cliff(&stream);
+
+        if let Some(escalation) = self.rate_escalations.get(&id) {
+            require!(
+                (end_time - start_time) / u64::from(escalation.period) <= MAX_ESCALATION_PERIODS,
+                "Too many escalation periods"
+            );
+        }
 
         // calculate the balance is enough
-        let stream_duration = stream.end_time - stream.start_time;
-        let stream_amount = u128::from(stream_duration) * rate;
+        let stream_amount =
+            self.internal_streamed_amount(&stream, stream.start_time, stream.end_time);
 
-        if stream_amount > stream.balance {
+        // the attached deposit only funds what a native stream is missing, the
+        // rest is refunded
+        let missing = stream_amount.saturating_sub(stream.balance);
+        let refund = if stream.is_native {
             // check the amount send to the stream
             require!(
-                env::attached_deposit() >= stream_amount - stream.balance,
+                env::attached_deposit() >= missing,
                 "The amount provided is not enough for the stream"
             );
+            stream.balance += missing;
+            env::attached_deposit() - missing
+        } else {
+            env::attached_deposit()
+        };
+        if refund > 0 {
+            Promise::new(stream.sender.clone()).transfer(refund);
+        }
 
-            stream.balance += env::attached_deposit();
+        // ft streams are applied once the tokens arrive with the msg
+        // `{"method_name":"apply_update","stream_id":"<id>"}`
+        if missing > 0 && !stream.is_native {
+            self.internal_add_pending_update(&stream, missing);
+            return;
         }
 
-        self.streams.insert(&id, &stream);
-    }
+        // move the stream in the end time index
+        self.stream_ends.remove(&(previous_end_time, id));
+        self.stream_ends.insert(&(end_time, id), &());
 
-    #[private]
-    pub fn internal_resolve_ft_withdraw(&mut self, stream_id: U64, temp_stream: Stream) -> bool {
-        let res: bool = match env::promise_result(0) {
-            PromiseResult::NotReady => env::abort(),
-            PromiseResult::Successful(_) => true,
-            _ => false,
-        };
-        if res {
-            self.streams.insert(&stream_id.into(), &temp_stream);
-        }
-        return res;
+        self.pending_updates.remove(&id);
+        self.internal_save_stream(&stream);
     }
 
-    #[private]
-    pub fn internal_resolve_ft_claim(&mut self, stream_id: U64, temp_stream: &mut Stream) -> bool {
-        let res: bool = match env::promise_result(0) {
-            PromiseResult::NotReady => env::abort(),
-            PromiseResult::Successful(_) => true,
-            _ => false,
-        };
-        if res {
-            temp_stream.balance = 0;
-            self.streams.insert(&stream_id.into(), &temp_stream);
+    // Limits the receiver's withdrawals to `amount` per `period` seconds, the
+    // rest stays in the stream for later. `None` removes the cap.
+    pub fn set_withdraw_cap(&mut self, stream_id: U64, amount: Option<U128>, period: U64) {
+        // convert to native u64
+        let id: u64 = stream_id.0;
+        let current_timestamp: u64 = current_time();
+
+        let stream = self.internal_get_stream(id);
+
+        // same conditions as `update`
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(stream.can_update, "Stream cannot be updated");
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.start_time > current_timestamp,
+            "Cannot update: stream already started"
+        );
+
+        match amount {
+            Some(amount) => {
+                // at least one second of the stream must be withdrawable per period
+                require!(period.0 > 0, "Period cannot be zero");
+                require!(amount.0 >= stream.rate, "Cap is lower than the stream rate");
+                require!(
+                    self.rate_escalations.get(&id).is_none(),
+                    "Cannot cap a stream with rate escalation"
+                );
+                require!(
+                    self.rate_changes.get(&id).is_none(),
+                    "Cannot cap a stream with a rate change"
+                );
+                self.withdraw_caps.insert(
+                    &id,
+                    &WithdrawCap {
+                        amount: amount.0,
+                        period: compact_timestamp(period.0),
+                        period_start: compact_timestamp(stream.start_time),
+                        withdrawn: 0,
+                    },
+                );
+                log_event!("Withdraw cap set: {}", id);
+            }
+            None => {
+                self.withdraw_caps.remove(&id);
+                log_event!("Withdraw cap removed: {}", id);
+            }
         }
-        return res;
     }
 
+    // Raises the rate by `bps` every `period` seconds from the start time, e.g.
+    // 100 bps monthly. The attached deposit must cover the higher total, 0 bps
+    // removes the escalation. Only for native streams, before they start.
     #[payable]
-    pub fn withdraw(&mut self, stream_id: U64) -> PromiseOrValue<bool> {
-        // convert id to native u64
+    pub fn set_rate_escalation(&mut self, stream_id: U64, bps: u32, period: U64) {
+        // convert to native u64
         let id: u64 = stream_id.0;
+        let current_timestamp: u64 = current_time();
 
-        let current_timestamp: u64 = env::block_timestamp_ms() / 1000;
-
-        // get the stream with id: stream_id
-        let mut temp_stream = self.streams.get(&id).unwrap();
+        let mut stream = self.internal_get_stream(id);
 
-        require!(temp_stream.balance > 0, "No balance to withdraw");
+        // same conditions as `update`
         require!(
-            !temp_stream.is_cancelled,
-            "Stream is cancelled by sender already!"
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
         );
-
-        // assert the stream has started
+        require!(stream.can_update, "Stream cannot be updated");
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
         require!(
-            current_timestamp > temp_stream.start_time,
-            "The stream has not started yet"
+            stream.start_time > current_timestamp,
+            "Cannot update: stream already started"
         );
+        require!(
+            stream.is_native,
+            "Rate escalation is only for native streams"
+        );
+
+        if bps == 0 {
+            self.rate_escalations.remove(&id);
+            log_event!("Rate escalation removed: {}", id);
+            return;
+        }
 
+        require!(period.0 > 0, "Period cannot be zero");
         require!(
-            env::predecessor_account_id() == temp_stream.sender
-                || env::predecessor_account_id() == temp_stream.receiver,
-            "You dont have permissions to withdraw"
+            (stream.end_time - stream.start_time) / period.0 <= MAX_ESCALATION_PERIODS,
+            "Too many escalation periods"
+        );
+        require!(
+            self.withdraw_caps.get(&id).is_none(),
+            "Cannot escalate a stream with a withdraw cap"
+        );
+        require!(
+            self.stream_matching_pools.get(&id).is_none(),
+            "Cannot escalate a stream with a matching pool"
+        );
+        require!(
+            self.rate_changes.get(&id).is_none(),
+            "Cannot escalate a stream with a rate change"
         );
 
-        // Case: sender withdraws excess amount from the stream after it has ended
-        if env::predecessor_account_id() == temp_stream.sender {
+        self.rate_escalations.insert(
+            &id,
+            &RateEscalation {
+                bps,
+                period: compact_timestamp(period.0),
+            },
+        );
+
+        // calculate the balance is enough
+        let stream_amount =
+            self.internal_streamed_amount(&stream, stream.start_time, stream.end_time);
+        if stream_amount > stream.balance {
             require!(
-                current_timestamp > temp_stream.end_time,
-                "Cannot withdraw before the stream has ended"
+                env::attached_deposit() >= stream_amount - stream.balance,
+                "The amount provided is not enough for the stream"
             );
+        }
+        stream.balance += env::attached_deposit();
+        self.internal_save_stream_state(&stream);
 
-            // Amount that has been streamed to the receiver
-            let withdrawal_amount: u128;
-
-            if temp_stream.is_paused {
-                withdrawal_amount = temp_stream.rate
-                    * u128::from(temp_stream.paused_time - temp_stream.withdraw_time);
-            } else {
-                if temp_stream.end_time > temp_stream.withdraw_time {
-                    // receiver has not withdrawn after stream ended
-                    withdrawal_amount = temp_stream.rate
-                        * u128::from(temp_stream.end_time - temp_stream.withdraw_time);
-                } else {
-                    withdrawal_amount = 0;
-                }
-            }
-
-            // Calculate the withdrawl amount
-            let remaining_balance = temp_stream.balance - withdrawal_amount;
-            require!(remaining_balance > 0, "Already withdrawn");
+        log_event!("Rate escalation set: {}", id);
+    }
 
-            // Update stream and save
-            temp_stream.balance -= remaining_balance;
-            // Transfer tokens to the sender
-            let receiver = temp_stream.sender.clone();
+    // Changes the rate of a running or future stream from `effective_at` on, the
+    // end time stays the same. A raise of a native stream is funded by the
+    // attached deposit and the rest is refunded, a lower rate leaves the excess
+    // to the sender at the end. `None` removes a change that has not taken
+    // effect yet.
+    #[payable]
+    pub fn set_rate_change(&mut self, stream_id: U64, rate: Option<U128>, effective_at: U64) {
+        // convert to native u64
+        let id: u64 = stream_id.0;
+        let current_timestamp: u64 = current_time();
 
-            if temp_stream.is_native {
-                self.streams.insert(&stream_id.into(), &temp_stream);
-                Promise::new(receiver).transfer(remaining_balance).into()
-            } else {
-                // NEP141 : ft_transfer()
-                ext_ft_transfer::ext(temp_stream.contract_id.clone())
-                    .with_attached_deposit(1)
-                    .ft_transfer(receiver, remaining_balance.into(), None)
-                    .then(
-                        Self::ext(env::current_account_id())
-                            .internal_resolve_ft_withdraw(stream_id, temp_stream),
-                    )
-                    .into()
-            }
+        let mut stream = self.internal_get_stream(id);
 
-        // Case: Receiver can withdraw the amount fromt the stream
-        } else {
-            let time_elapsed: u64;
-            let withdraw_time: u64;
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(stream.can_update, "Stream cannot be updated");
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
 
-            // Calculate the elapsed time
-            if current_timestamp >= temp_stream.end_time {
+        // a change in effect is folded into the rate once the receiver withdrew past it
+        if let Some(change) = self.rate_changes.get(&id) {
+            if u64::from(change.effective_at) <= current_timestamp {
                 require!(
-                    temp_stream.withdraw_time < temp_stream.end_time,
-                    "Already withdrawn"
+                    stream.withdraw_time >= u64::from(change.effective_at),
+                    "The previous rate change must be withdrawn first"
                 );
-                withdraw_time = current_timestamp;
+                stream.rate = change.rate;
+                self.internal_save_stream(&stream);
+            }
+            self.rate_changes.remove(&id);
+        }
 
-                if temp_stream.is_paused {
-                    time_elapsed = temp_stream.paused_time - temp_stream.withdraw_time;
-                } else {
-                    time_elapsed = temp_stream.end_time - temp_stream.withdraw_time;
+        let rate = match rate {
+            Some(rate) => rate.0,
+            None => {
+                log_event!("Rate change removed: {}", id);
+                if env::attached_deposit() > 0 {
+                    Promise::new(stream.sender).transfer(env::attached_deposit());
                 }
-            } else if temp_stream.is_paused {
-                time_elapsed = temp_stream.paused_time - temp_stream.withdraw_time;
-                withdraw_time = temp_stream.paused_time;
-            } else {
-                time_elapsed = current_timestamp - temp_stream.withdraw_time;
-                withdraw_time = current_timestamp;
+                return;
             }
+        };
 
-            // Calculate the withdrawal amount
-            let withdrawal_amount = temp_stream.rate * u128::from(time_elapsed);
-
-            // Transfer the tokens to the receiver
-            let receiver = temp_stream.receiver.clone();
-            require!(withdrawal_amount > 0, "withdrawal_amount < 0");
-
-            // Update the stream struct and save
-            temp_stream.balance -= withdrawal_amount;
-            temp_stream.withdraw_time = withdraw_time;
-
-            if temp_stream.is_native {
-                self.streams.insert(&stream_id.into(), &temp_stream);
-                Promise::new(receiver).transfer(withdrawal_amount).into()
-            } else {
-                // NEP141 : ft_transfer()
-                // require!(env::prepaid_gas() > GAS_FOR_FT_TRANSFER, "More gas is required");
-                // log!("{:?}", temp_stream);
-                ext_ft_transfer::ext(temp_stream.contract_id.clone())
-                    // .with_static_gas(GAS_FOR_FT_TRANSFER)
-                    .with_attached_deposit(1)
-                    .ft_transfer(receiver, withdrawal_amount.into(), None)
-                    .then(
-                        // ext_self::ext(env::current_account_id())
-                        // .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
-                        // .resolve_ft_withdraw(stream_id, temp_stream),
-                        // ext_self::ft
-                        Self::ext(env::current_account_id())
-                            .internal_resolve_ft_withdraw(stream_id, temp_stream),
-                    )
-                    .into()
-            }
+        require!(
+            effective_at.0 > current_timestamp && effective_at.0 < stream.end_time,
+            "Rate change must take effect before the stream ends"
+        );
+        require!(rate > 0, "Rate cannot be zero");
+        require!(rate < calls::stream_max_rate(&stream), "Rate is too high");
+        require!(
+            self.rate_escalations.get(&id).is_none(),
+            "Cannot change the rate of a stream with rate escalation"
+        );
+        require!(
+            self.withdraw_caps.get(&id).is_none(),
+            "Cannot change the rate of a stream with a withdraw cap"
+        );
+        require!(
+            self.stream_matching_pools.get(&id).is_none(),
+            "Cannot change the rate of a stream with a matching pool"
+        );
+
+        self.rate_changes.insert(
+            &id,
+            &RateChange {
+                rate,
+                effective_at: compact_timestamp(effective_at.0),
+            },
+        );
+
+        // calculate the balance is enough for what is left to withdraw
+        let stream_amount =
+            self.internal_streamed_amount(&stream, stream.withdraw_time, stream.end_time);
+        let missing = stream_amount.saturating_sub(stream.balance);
+        if missing > 0 {
+            require!(stream.is_native, "Cannot raise the rate of an ft stream");
+            require!(
+                env::attached_deposit() >= missing,
+                "The amount provided is not enough for the stream"
+            );
+            stream.balance += missing;
+            self.internal_save_stream_state(&stream);
+        }
+        if env::attached_deposit() > missing {
+            Promise::new(stream.sender.clone()).transfer(env::attached_deposit() - missing);
         }
+
+        log_event!("Rate change set: {} {} {}", id, rate, effective_at.0);
     }
 
-    pub fn pause(&mut self, stream_id: U64) {
-        // convert id to native u64
+    // Sends `bps` of every payout to the receiver to `account_id` instead, which
+    // claims it with `claim_withheld`. `None` removes the withholding.
+    pub fn set_withholding(&mut self, stream_id: U64, account_id: Option<AccountId>, bps: u32) {
+        // convert to native u64
         let id: u64 = stream_id.0;
+        let current_timestamp: u64 = current_time();
 
-        let current_timestamp: u64 = env::block_timestamp_ms() / 1000;
-
-        // get the stream
-        let mut stream = self.streams.get(&id).unwrap();
-
-        // Only the sender can pause the stream
-        require!(env::predecessor_account_id() == stream.sender);
+        let stream = self.internal_get_stream(id);
 
-        // Can only be paused after the stream has started and before it has ended
-        let can_pause =
-            current_timestamp > stream.start_time && current_timestamp < stream.end_time;
+        // same conditions as `update`
         require!(
-            can_pause,
-            "Can only be pause after stream starts and before it has ended"
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(stream.can_update, "Stream cannot be updated");
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.start_time > current_timestamp,
+            "Cannot update: stream already started"
         );
 
-        // assert that the stream is already paused
-        require!(!stream.is_paused, "Cannot pause already paused stream");
-
-        // update the stream state
-        stream.is_paused = true;
-        stream.paused_time = current_timestamp;
-        self.streams.insert(&id, &stream);
+        match account_id {
+            Some(account_id) => {
+                require!(bps > 0 && bps <= 10_000, "Invalid withholding bps");
+                require!(
+                    self.child_streams.get(&id).is_none(),
+                    "Cannot withhold from a stream with a child stream"
+                );
+                self.withholdings
+                    .insert(&id, &Withholding { account_id, bps });
+                log_event!("Withholding set: {}", id);
+            }
+            None => {
+                self.withholdings.remove(&id);
+                log_event!("Withholding removed: {}", id);
+            }
+        }
+    }
 
-        // Log
-        log!("Stream paused: {}", stream.id);
+    // transfers everything withheld for the caller in `token` (None for NEAR)
+    pub fn claim_withheld(&mut self, token: Option<AccountId>) -> PromiseOrValue<bool> {
+        let account_id = env::predecessor_account_id();
+        let key = (account_id.clone(), token.clone());
+        let amount = self.withheld_balances.remove(&key).unwrap_or(0);
+        require!(amount > 0, "Nothing withheld");
+
+        log_event!("Withheld claimed: {} {}", account_id, amount);
+        match token {
+            None => Promise::new(account_id).transfer(amount).into(),
+            Some(token) => ext_ft_transfer::ext(token.clone())
+                .with_attached_deposit(1)
+                .ft_transfer(account_id.clone(), amount.into(), None)
+                .then(
+                    Self::ext(env::current_account_id()).internal_resolve_withheld_claim(
+                        account_id,
+                        token,
+                        amount.into(),
+                    ),
+                )
+                .into(),
+        }
     }
 
-    pub fn resume(&mut self, stream_id: U64) {
-        // convert id to native u64
-        let id: u64 = stream_id.0;
+    // credits the amount back if the transfer to the withholding account failed
+    #[private]
+    pub fn internal_resolve_withheld_claim(
+        &mut self,
+        account_id: AccountId,
+        token: AccountId,
+        amount: U128,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !res {
+            let key = (account_id, Some(token));
+            let balance = self.withheld_balances.get(&key).unwrap_or(0);
+            self.withheld_balances.insert(&key, &(balance + amount.0));
+        }
+        res
+    }
 
-        let current_timestamp: u64 = env::block_timestamp_ms() / 1000;
-        // get the stream
-        let mut stream = self.streams.get(&id).unwrap();
+    // Adds the attached deposit to the stream and extends its end time, anyone
+    // can donate. The part that doesn't make up a whole second is refunded.
+    #[payable]
+    pub fn donate(&mut self, stream_id: U64) {
+        let donor = env::predecessor_account_id();
+        let unused =
+            self.internal_donate(stream_id.0, donor.clone(), env::attached_deposit(), None);
+        if unused > 0 {
+            Promise::new(donor).transfer(unused);
+        }
+    }
 
-        // Only the sender can resume the stream
-        require!(env::predecessor_account_id() == stream.sender);
+    // Links the caller's stream with the counterparty's stream in the other
+    // direction. Once both senders called it, before either stream starts, the
+    // two streams can only be cancelled together. Returns true once paired.
+    pub fn pair_streams(&mut self, stream_id: U64, paired_id: U64) -> bool {
+        let (id, paired_id) = (stream_id.0, paired_id.0);
+        let current_timestamp: u64 = current_time();
 
-        // assert that the stream is already paused
-        let is_paused = self.streams.get(&id).unwrap().is_paused;
-        require!(is_paused, "Cannot resume unpaused stream");
+        let stream = self.internal_get_stream(id);
+        let paired_stream = self.internal_get_stream(paired_id);
 
-        // resume the stream
-        stream.is_paused = false;
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(
+            stream.receiver == paired_stream.sender && paired_stream.receiver == stream.sender,
+            "Streams are not between the same accounts"
+        );
+        require!(
+            stream.can_cancel && paired_stream.can_cancel,
+            "Stream cannot be cancelled"
+        );
+        require!(
+            !stream.is_cancelled && !paired_stream.is_cancelled,
+            "Stream has already been cancelled"
+        );
+        require!(
+            stream.start_time > current_timestamp && paired_stream.start_time > current_timestamp,
+            "Cannot update: stream already started"
+        );
+        require!(
+            self.stream_pairs.get(&id).is_none() && self.stream_pairs.get(&paired_id).is_none(),
+            "Stream is already paired"
+        );
 
-        // Update the withdraw_time so that the receiver will not be
-        // able to withdraw fund for paused time
-        if current_timestamp > stream.end_time {
-            stream.withdraw_time += stream.end_time - stream.paused_time;
+        if self.pair_proposals.get(&paired_id) == Some(id) {
+            self.pair_proposals.remove(&paired_id);
+            self.stream_pairs.insert(&id, &paired_id);
+            self.stream_pairs.insert(&paired_id, &id);
+            log_event!("Streams paired: {} {}", paired_id, id);
+            true
         } else {
-            stream.withdraw_time += current_timestamp - stream.paused_time;
+            self.pair_proposals.insert(&id, &paired_id);
+            log_event!("Stream pair proposed: {} {}", id, paired_id);
+            false
         }
-
-        // Reset the paused_time and save
-        stream.paused_time = 0;
-        self.streams.insert(&id, &stream);
-
-        // Log
-        log!("Stream resumed: {}", stream.id);
     }
 
-    #[payable]
-    pub fn cancel(&mut self, stream_id: U64) -> PromiseOrValue<bool> {
-        //  only tranfsers the tokens to receiver
-        //  sender can claim using ft_claim_sender
-
-        // convert id to native u64
+    // Opts the caller in or out of netting on a paired stream it receives.
+    // Netting applies once both receivers opted in.
+    pub fn set_netting(&mut self, stream_id: U64, enabled: bool) {
         let id: u64 = stream_id.0;
+        let stream = self.internal_get_stream(id);
 
-        let current_timestamp: u64 = env::block_timestamp_ms() / 1000;
-        // Get the stream
-        let mut temp_stream = self.streams.get(&id).unwrap();
-
-        // check that the stream can be cancelled
-        require!(temp_stream.can_cancel, "Stream cannot be cancelled");
-
-        // Only the sender can cancel the stream
-        require!(env::predecessor_account_id() == temp_stream.sender);
-
-        // Stream can only be cancelled if it has not ended
         require!(
-            temp_stream.end_time > current_timestamp,
-            "Stream already ended"
+            env::predecessor_account_id() == stream.receiver,
+            "Only the receiver can set netting"
         );
-        require!(!temp_stream.is_cancelled, "already cancelled!");
-
-        // Amounts to refund to the sender and the receiver
-        let sender_amt: u128;
-        let receiver_amt: u128;
+        require!(self.stream_pairs.get(&id).is_some(), "Stream is not paired");
 
-        // Calculate the amount to refund to the receiver
-        if temp_stream.is_paused {
-            receiver_amt =
-                u128::from(temp_stream.paused_time - temp_stream.withdraw_time) * temp_stream.rate;
+        if enabled {
+            self.netting_streams.insert(&id);
         } else {
-            receiver_amt =
-                u128::from(current_timestamp - temp_stream.withdraw_time) * temp_stream.rate;
+            self.netting_streams.remove(&id);
         }
+        log_event!("Stream netting: {} {}", id, enabled);
+    }
 
-        // Calculate the amoun to refund to the sender
-        sender_amt = temp_stream.balance - receiver_amt;
+    // Withdraws both streams of a netted pair and only transfers the difference
+    // to the account owed more. The smaller side is credited to the balance of
+    // the larger stream, it goes back to that stream's sender with its refund.
+    pub fn withdraw_netted(&mut self, stream_id: U64) -> PromiseOrValue<bool> {
+        let id: u64 = stream_id.0;
+        let paired_id = self.stream_pairs.get(&id).expect("Stream is not paired");
 
-        // Refund the amounts to the sender and the receiver respectively
-        let sender = temp_stream.sender.clone();
-        let receiver = temp_stream.receiver.clone();
+        let mut temp_stream = self.internal_get_stream(id);
+        let mut paired_stream = self.internal_get_stream(paired_id);
 
-        // Update the stream balance and save
-        temp_stream.balance = sender_amt;
-        temp_stream.is_cancelled = true;
-        // self.streams.insert(&id, &temp_stream);
+        require!(
+            env::predecessor_account_id() == temp_stream.sender
+                || env::predecessor_account_id() == temp_stream.receiver,
+            "You dont have permissions to withdraw"
+        );
+        require!(
+            self.netting_streams.contains(&id) && self.netting_streams.contains(&paired_id),
+            "Netting is not enabled on both streams"
+        );
+        require!(
+            temp_stream.is_native == paired_stream.is_native
+                && temp_stream.contract_id == paired_stream.contract_id,
+            "Streams are in different tokens"
+        );
+        require!(
+            !temp_stream.is_cancelled && !paired_stream.is_cancelled,
+            "Stream is cancelled by sender already!"
+        );
+        require!(
+            self.withholdings.get(&id).is_none() && self.withholdings.get(&paired_id).is_none(),
+            "Cannot net a stream with withholding"
+        );
 
-        // log
-        log!("Stream cancelled: {}", temp_stream.id);
+        let (amount, cap) = self.internal_withdraw_accrued(&mut temp_stream);
+        let (paired_amount, paired_cap) = self.internal_withdraw_accrued(&mut paired_stream);
+        require!(amount + paired_amount > 0, "Nothing to withdraw");
 
-        if temp_stream.is_native {
-            temp_stream.balance = 0;
-            self.streams.insert(&id, &temp_stream);
-            Promise::new(sender)
-                .transfer(sender_amt)
-                .then(Promise::new(receiver).transfer(receiver_amt))
-                .into()
+        // the smaller payout stays in the contract as balance of the other stream
+        let (receiver, net_amount) = if amount >= paired_amount {
+            temp_stream.balance += paired_amount;
+            (temp_stream.receiver.clone(), amount - paired_amount)
+        } else {
+            paired_stream.balance += amount;
+            (paired_stream.receiver.clone(), paired_amount - amount)
+        };
+
+        if temp_stream.is_native || net_amount == 0 {
+            self.internal_save_netted_withdraw(&temp_stream, amount, &paired_stream, paired_amount);
+            self.internal_save_withdraw_cap(id, cap);
+            self.internal_save_withdraw_cap(paired_id, paired_cap);
+            if net_amount > 0 {
+                let _ = self.internal_native_payout(
+                    Promise::new(receiver.clone()).transfer(net_amount),
+                    &receiver,
+                    net_amount,
+                );
+            }
+            PromiseOrValue::Value(true)
         } else {
             ext_ft_transfer::ext(temp_stream.contract_id.clone())
                 .with_attached_deposit(1)
-                .ft_transfer(receiver, receiver_amt.into(), None)
+                .ft_transfer(receiver, net_amount.into(), None)
                 .then(
-                    Self::ext(env::current_account_id())
-                        .internal_resolve_ft_withdraw(stream_id, temp_stream),
+                    Self::ext(env::current_account_id()).internal_resolve_netted_withdraw(
+                        temp_stream,
+                        amount.into(),
+                        paired_stream,
+                        paired_amount.into(),
+                        cap,
+                        paired_cap,
+                    ),
                 )
                 .into()
         }
     }
 
-    // allows the sender to withdraw funds if the stream is_cancelled.
-    pub fn ft_claim_sender(&mut self, stream_id: U64) -> PromiseOrValue<bool> {
-        // convert id to native u64
+    #[private]
+    pub fn internal_resolve_netted_withdraw(
+        &mut self,
+        temp_stream: Stream,
+        amount: U128,
+        paired_stream: Stream,
+        paired_amount: U128,
+        cap: Option<WithdrawCap>,
+        paired_cap: Option<WithdrawCap>,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if res {
+            self.internal_save_netted_withdraw(
+                &temp_stream,
+                amount.0,
+                &paired_stream,
+                paired_amount.0,
+            );
+            self.internal_save_withdraw_cap(temp_stream.id, cap);
+            self.internal_save_withdraw_cap(paired_stream.id, paired_cap);
+        }
+        res
+    }
+
+    // Locks the attached deposit as a penalty bond, paid to the receiver if the
+    // sender cancels the started stream before `commit_end`. Works for ft
+    // streams too.
+    #[payable]
+    pub fn post_bond(&mut self, stream_id: U64, commit_end: U64) {
+        // convert to native u64
         let id: u64 = stream_id.0;
+        let current_timestamp: u64 = current_time();
+
+        let stream = self.internal_get_stream(id);
 
-        // Get the stream
-        let mut temp_stream = self.streams.get(&id).unwrap();
         require!(
-            temp_stream.sender == env::predecessor_account_id(),
-            "not sender"
+            env::predecessor_account_id() == stream.sender,
+            "Only the sender can post a bond"
         );
-        require!(temp_stream.is_cancelled, "stream is not cancelled!");
-        ext_ft_transfer::ext(temp_stream.contract_id.clone())
-            .with_attached_deposit(1)
-            .ft_transfer(temp_stream.sender.clone(), temp_stream.balance.into(), None)
-            .then(
-                Self::ext(env::current_account_id())
-                    .internal_resolve_ft_claim(stream_id, &mut temp_stream),
-            )
-            .into()
+        require!(stream.can_cancel, "Stream cannot be cancelled");
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(env::attached_deposit() > 0, "Bond cannot be zero");
+        require!(
+            commit_end.0 > current_timestamp && commit_end.0 <= stream.end_time,
+            "Commitment must end in the future and before the stream"
+        );
+        require!(self.bonds.get(&id).is_none(), "Bond already posted");
+
+        self.bonds.insert(
+            &id,
+            &Bond {
+                amount: env::attached_deposit(),
+                commit_end: compact_timestamp(commit_end.0),
+            },
+        );
+        log_event!("Bond posted: {}", id);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_sdk::test_utils::accounts;
-    use near_sdk::test_utils::VMContextBuilder;
-    use near_sdk::testing_env;
 
-    const NEAR: u128 = 1000000000000000000000000;
+    // returns the bond to the sender once the stream has ended without a cancel
+    pub fn claim_bond(&mut self, stream_id: U64) -> Promise {
+        // convert to native u64
+        let id: u64 = stream_id.0;
+        let current_timestamp: u64 = current_time();
 
-    #[test]
-    fn initializes() {
-        let contract = Contract::new();
-        assert_eq!(contract.current_id, 1);
-        assert_eq!(contract.streams.len(), 0);
-    }
+        let stream = self.internal_get_stream(id);
 
-    #[test]
-    #[should_panic(expected = "The amount provided doesn't matches the stream")]
-    fn create_stream_invalid_amount() {
-        let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 172800);
-        let sender = accounts(0);
-        let receiver = accounts(1);
-        let rate = U128::from(1 * NEAR);
+        require!(env::predecessor_account_id() == stream.sender, "not sender");
+        require!(
+            self.internal_can_reclaim(&stream, current_timestamp),
+            "Cannot claim the bond before the stream has ended"
+        );
+        let bond = self.bonds.remove(&id).expect("No bond posted");
 
-        let mut contract = Contract::new();
+        log_event!("Bond claimed: {}", id);
+        Promise::new(stream.sender).transfer(bond.amount)
+    }
 
-        set_context_with_balance(sender, 200000 * NEAR);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+    // `amount` is what the transfer took out of the stream's balance,
+    // `receiver_amount` the part of it that pays the receiver and `payee` the
+    // account it was sent to, `cap` the withdraw cap after it. After
+    // `MAX_PAYOUT_FAILURES` failed payouts in a row the payout is settled into
+    // the payee's escrow.
+    #[private]
+    pub fn internal_resolve_ft_withdraw(
+        &mut self,
+        temp_stream: Stream,
+        amount: U128,
+        receiver_amount: U128,
+        payee: AccountId,
+        cap: Option<WithdrawCap>,
+        nonce: Option<WithdrawNonce>,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if res {
+            self.payout_failures.remove(&temp_stream.id);
+            if temp_stream.is_cancelled {
+                self.internal_remove_end_indexes(&temp_stream);
+            }
+            self.internal_save_withdrawal(&temp_stream, amount.0);
+            self.internal_save_withdraw_cap(temp_stream.id, cap);
+            if receiver_amount.0 > 0 {
+                self.internal_record_payment(&temp_stream, receiver_amount.0);
+            }
+            if self.withdraw_callbacks.contains(&temp_stream.id) {
+                let net_amount = receiver_amount.0
+                    - self.internal_withheld_amount(&temp_stream, receiver_amount.0);
+                let _ = self.internal_with_withdraw_callback(
+                    Promise::new(temp_stream.receiver.clone()),
+                    &temp_stream,
+                    net_amount,
+                );
+            }
+        } else if receiver_amount.0 > 0 && self.internal_count_payout_failure(temp_stream.id) {
+            if temp_stream.is_cancelled {
+                self.internal_remove_end_indexes(&temp_stream);
+            }
+            self.internal_save_withdrawal(&temp_stream, amount.0);
+            self.internal_save_withdraw_cap(temp_stream.id, cap);
+            self.internal_record_payment(&temp_stream, receiver_amount.0);
+            let net_amount =
+                receiver_amount.0 - self.internal_withheld_amount(&temp_stream, receiver_amount.0);
+            self.internal_escrow(payee, Some(temp_stream.contract_id.clone()), net_amount);
+            self.internal_notify(&temp_stream, StreamEvent::WithdrawalFailed);
+        } else {
+            // nothing was paid, the caller can retry with the same nonce
+            if let Some(nonce) = nonce {
+                self.internal_restore_withdraw_nonce(temp_stream.id, nonce);
+            }
+            self.internal_notify(&temp_stream, StreamEvent::WithdrawalFailed);
+        }
+        return res;
     }
 
-    #[test]
-    #[should_panic(expected = "Sender and receiver cannot be Same")]
-    fn create_stream_invalid_receipient() {
-        let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 172800); // 2 days
-        let sender = &accounts(0); // alice
-        let receiver = &accounts(0); // alice
-        let rate = U128::from(1 * NEAR);
+    #[private]
+    pub fn internal_resolve_ft_claim(&mut self, stream_id: U64, temp_stream: &mut Stream) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if res {
+            temp_stream.balance = 0;
+            self.stream_states
+                .insert(&stream_id.into(), &StreamState::from(&*temp_stream));
+        }
+        return res;
+    }
 
-        let mut contract = Contract::new();
+    // `nonce` is an optional client nonce, a call with a nonce not above the
+    // last one the caller used on the stream fails so retries are not paid twice
+    #[payable]
+    pub fn withdraw(&mut self, stream_id: U64, nonce: Option<U64>) -> PromiseOrValue<bool> {
+        // convert id to native u64
+        let id: u64 = stream_id.0;
 
-        set_context_with_balance(sender.clone(), 172800 * NEAR);
+        let current_timestamp: u64 = current_time();
 
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, true, false);
-    }
+        // get the stream with id: stream_id
+        let mut temp_stream = self.internal_get_stream(id);
+
+        require!(temp_stream.balance > 0, "No balance to withdraw");
+        require!(
+            !temp_stream.is_cancelled,
+            "Stream is cancelled by sender already!"
+        );
+
+        // assert the stream has started
+        require!(
+            current_timestamp > temp_stream.start_time,
+            "The stream has not started yet"
+        );
+
+        // operators approved by the receiver withdraw to the receiver, an active backup to itself
+        require!(
+            env::predecessor_account_id() == temp_stream.sender
+                || self.internal_acts_for(
+                    id,
+                    &temp_stream.receiver,
+                    &env::predecessor_account_id()
+                )
+                || self.internal_is_active_backup(id, &env::predecessor_account_id()),
+            "You dont have permissions to withdraw"
+        );
+
+        // the nonce is given back if an ft transfer fails
+        let mut withdraw_nonce: Option<WithdrawNonce> = None;
+        if let Some(nonce) = nonce {
+            let key = (id, env::predecessor_account_id());
+            let last = self.withdraw_nonces.get(&key);
+            if let Some(last) = last {
+                require!(nonce.0 > last, "Withdrawal already executed");
+            }
+            self.withdraw_nonces.insert(&key, &nonce.0);
+            withdraw_nonce = Some((key.1, nonce, last.map(U64)));
+        }
+
+        // Case: sender withdraws excess amount from the stream after it has ended
+        if env::predecessor_account_id() == temp_stream.sender {
+            require!(
+                self.internal_can_reclaim(&temp_stream, current_timestamp),
+                "Cannot withdraw before the stream has ended"
+            );
+
+            // Calculate the withdrawl amount
+            let remaining_balance = self.internal_sender_reclaim(&temp_stream).1;
+            require!(remaining_balance > 0, "Already withdrawn");
+
+            // Update stream and save
+            temp_stream.balance -= remaining_balance;
+            // Transfer tokens to the sender
+            let receiver = temp_stream.sender.clone();
+
+            if temp_stream.is_native {
+                self.internal_save_stream_state(&temp_stream);
+                Promise::new(receiver).transfer(remaining_balance).into()
+            } else {
+                // NEP141 : ft_transfer()
+                ext_ft_transfer::ext(temp_stream.contract_id.clone())
+                    .with_attached_deposit(1)
+                    .ft_transfer(receiver.clone(), remaining_balance.into(), None)
+                    .then(
+                        Self::ext(env::current_account_id()).internal_resolve_ft_withdraw(
+                            temp_stream,
+                            U128(remaining_balance),
+                            U128(0),
+                            receiver,
+                            None,
+                            withdraw_nonce,
+                        ),
+                    )
+                    .into()
+            }
+
+        // Case: Receiver can withdraw the amount fromt the stream
+        } else {
+            if self.internal_has_ended(&temp_stream, current_timestamp) {
+                require!(
+                    temp_stream.withdraw_time < temp_stream.end_time,
+                    "Already withdrawn"
+                );
+            }
+            require!(
+                !self.internal_before_cliff(id, current_timestamp),
+                "Cliff time has not been reached"
+            );
+
+            // Calculate the withdrawal amount and update the stream struct
+            let (withdrawal_amount, cap) = self.internal_withdraw_accrued(&mut temp_stream);
+
+            // Transfer the tokens to the receiver, or the backup that took over
+            let receiver = if self.internal_acts_for(
+                id,
+                &temp_stream.receiver,
+                &env::predecessor_account_id(),
+            ) {
+                self.internal_touch_backup(id);
+                temp_stream.receiver.clone()
+            } else {
+                env::predecessor_account_id()
+            };
+            require!(withdrawal_amount > 0, "withdrawal_amount < 0");
+
+            // the withheld part stays in the contract for the withholding account
+            let receiver_amount =
+                withdrawal_amount - self.internal_withheld_amount(&temp_stream, withdrawal_amount);
+
+            // receivers on a payout schedule are credited and paid when it is due
+            if self.internal_has_payout_schedule(&receiver) {
+                self.internal_save_stream_state(&temp_stream);
+                self.internal_save_withdraw_cap(id, cap);
+                self.internal_record_payment(&temp_stream, withdrawal_amount);
+                let token = if temp_stream.is_native {
+                    None
+                } else {
+                    Some(temp_stream.contract_id.clone())
+                };
+                return self.internal_credit_payout(receiver, token, receiver_amount);
+            }
+
+            if temp_stream.is_native {
+                self.internal_save_stream_state(&temp_stream);
+                self.internal_save_withdraw_cap(id, cap);
+                self.internal_record_payment(&temp_stream, withdrawal_amount);
+                let transfer = Promise::new(receiver.clone()).transfer(receiver_amount);
+                let transfer =
+                    self.internal_with_withdraw_callback(transfer, &temp_stream, receiver_amount);
+                self.internal_native_payout(transfer, &receiver, receiver_amount)
+                    .into()
+            } else {
+                // NEP141 : ft_transfer()
+                // require!(env::prepaid_gas() > GAS_FOR_FT_TRANSFER, "More gas is required");
+                // log!("{:?}", temp_stream);
+                ext_ft_transfer::ext(temp_stream.contract_id.clone())
+                    // .with_static_gas(GAS_FOR_FT_TRANSFER)
+                    .with_attached_deposit(1)
+                    .ft_transfer(receiver.clone(), receiver_amount.into(), None)
+                    .then(
+                        // ext_self::ext(env::current_account_id())
+                        // .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                        // .resolve_ft_withdraw(stream_id, temp_stream),
+                        // ext_self::ft
+                        Self::ext(env::current_account_id()).internal_resolve_ft_withdraw(
+                            temp_stream,
+                            withdrawal_amount.into(),
+                            withdrawal_amount.into(),
+                            receiver,
+                            cap,
+                            withdraw_nonce,
+                        ),
+                    )
+                    .into()
+            }
+        }
+    }
+
+    // Withdraws from several streams of the same receiver with one transfer per
+    // token. Streams with nothing to withdraw yet are skipped.
+    pub fn withdraw_batch(&mut self, stream_ids: Vec<U64>) -> PromiseOrValue<bool> {
+        // an ft stream is only saved in the callback, so it must not be withdrawn twice
+        let mut ids: Vec<u64> = stream_ids.iter().map(|id| id.0).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut receiver: Option<AccountId> = None;
+        // token (None for NEAR), withdrawals from its streams, amount to transfer
+        let mut transfers: Vec<BatchTransfer> = vec![];
+
+        for id in ids {
+            let mut temp_stream = self.internal_get_stream(id);
+            require!(
+                !temp_stream.is_cancelled,
+                "Stream is cancelled by sender already!"
+            );
+            require!(
+                *receiver.get_or_insert_with(|| temp_stream.receiver.clone())
+                    == temp_stream.receiver,
+                "Streams have different receivers"
+            );
+            require!(
+                self.internal_acts_for(id, &temp_stream.receiver, &env::predecessor_account_id()),
+                "You dont have permissions to withdraw"
+            );
+
+            let (withdrawal_amount, cap) = self.internal_withdraw_accrued(&mut temp_stream);
+            if withdrawal_amount == 0 {
+                continue;
+            }
+            self.internal_touch_backup(id);
+
+            // the withheld part stays in the contract for the withholding account
+            let receiver_amount =
+                withdrawal_amount - self.internal_withheld_amount(&temp_stream, withdrawal_amount);
+
+            // ft streams are saved in the callback, unless the receiver is on a payout schedule
+            let cap = if temp_stream.is_native
+                || self.internal_has_payout_schedule(&temp_stream.receiver)
+            {
+                self.internal_save_stream_state(&temp_stream);
+                self.internal_save_withdraw_cap(id, cap);
+                self.internal_record_payment(&temp_stream, withdrawal_amount);
+                None
+            } else {
+                cap
+            };
+            let token = if temp_stream.is_native {
+                None
+            } else {
+                Some(temp_stream.contract_id.clone())
+            };
+
+            let withdrawal = (temp_stream, U128(withdrawal_amount), cap);
+            match transfers.iter_mut().find(|(t, _, _)| *t == token) {
+                Some((_, withdrawals, total)) => {
+                    withdrawals.push(withdrawal);
+                    *total += receiver_amount;
+                }
+                None => transfers.push((token, vec![withdrawal], receiver_amount)),
+            }
+        }
+        require!(!transfers.is_empty(), "Nothing to withdraw");
+        let receiver = receiver.unwrap();
+
+        // receivers on a payout schedule are credited and paid when it is due
+        if self.internal_has_payout_schedule(&receiver) {
+            let payouts: Vec<Promise> = transfers
+                .into_iter()
+                .filter_map(|(token, _, total)| {
+                    match self.internal_credit_payout(receiver.clone(), token, total) {
+                        PromiseOrValue::Promise(promise) => Some(promise),
+                        PromiseOrValue::Value(_) => None,
+                    }
+                })
+                .collect();
+            return match payouts.into_iter().reduce(Promise::and) {
+                Some(promise) => promise.into(),
+                None => PromiseOrValue::Value(true),
+            };
+        }
+
+        transfers
+            .into_iter()
+            .map(|(token, withdrawals, total)| match token {
+                None => {
+                    let transfer = withdrawals.iter().fold(
+                        Promise::new(receiver.clone()).transfer(total),
+                        |transfer, (temp_stream, amount, _)| {
+                            let net_amount =
+                                amount.0 - self.internal_withheld_amount(temp_stream, amount.0);
+                            self.internal_with_withdraw_callback(transfer, temp_stream, net_amount)
+                        },
+                    );
+                    self.internal_native_payout(transfer, &receiver, total)
+                }
+                Some(token) => ext_ft_transfer::ext(token)
+                    .with_attached_deposit(1)
+                    .ft_transfer(receiver.clone(), total.into(), None)
+                    .then(
+                        Self::ext(env::current_account_id())
+                            .internal_resolve_batch_withdraw(withdrawals),
+                    ),
+            })
+            .reduce(Promise::and)
+            .unwrap()
+            .into()
+    }
+
+    // saves every stream of a consolidated ft transfer, and its withdraw cap, once
+    // it succeeded, or once it failed `MAX_PAYOUT_FAILURES` times into escrow
+    #[private]
+    pub fn internal_resolve_batch_withdraw(
+        &mut self,
+        withdrawals: Vec<(Stream, U128, Option<WithdrawCap>)>,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        for (temp_stream, amount, cap) in withdrawals {
+            if res {
+                self.payout_failures.remove(&temp_stream.id);
+                self.internal_save_withdrawal(&temp_stream, amount.0);
+                self.internal_save_withdraw_cap(temp_stream.id, cap);
+                self.internal_record_payment(&temp_stream, amount.0);
+                if self.withdraw_callbacks.contains(&temp_stream.id) {
+                    let net_amount =
+                        amount.0 - self.internal_withheld_amount(&temp_stream, amount.0);
+                    let _ = self.internal_with_withdraw_callback(
+                        Promise::new(temp_stream.receiver.clone()),
+                        &temp_stream,
+                        net_amount,
+                    );
+                }
+            } else if self.internal_count_payout_failure(temp_stream.id) {
+                self.internal_save_withdrawal(&temp_stream, amount.0);
+                self.internal_save_withdraw_cap(temp_stream.id, cap);
+                self.internal_record_payment(&temp_stream, amount.0);
+                let net_amount = amount.0 - self.internal_withheld_amount(&temp_stream, amount.0);
+                self.internal_escrow(
+                    temp_stream.receiver.clone(),
+                    Some(temp_stream.contract_id.clone()),
+                    net_amount,
+                );
+                self.internal_notify(&temp_stream, StreamEvent::WithdrawalFailed);
+            } else {
+                self.internal_notify(&temp_stream, StreamEvent::WithdrawalFailed);
+            }
+        }
+        res
+    }
+
+    #[payable]
+    pub fn pause(&mut self, stream_id: U64, reason: Option<String>) {
+        // convert id to native u64
+        let id: u64 = stream_id.0;
+
+        let current_timestamp: u64 = current_time();
+
+        // get the stream
+        let mut stream = self.internal_get_stream(id);
+
+        // Only the sender or its operators can pause the stream
+        require!(self.internal_acts_for(id, &stream.sender, &env::predecessor_account_id()));
+
+        // Can only be paused after the stream has started and before it has ended
+        let can_pause = current_timestamp > stream.start_time
+            && !self.internal_has_ended(&stream, current_timestamp);
+        require!(
+            can_pause,
+            "Can only be pause after stream starts and before it has ended"
+        );
+
+        // assert that the stream is already paused
+        require!(!stream.is_paused, "Cannot pause already paused stream");
+        self.internal_use_pause(id);
+
+        // update the stream state
+        stream.is_paused = true;
+        stream.paused_time = current_timestamp;
+        self.internal_save_stream_state(&stream);
+        let reason_storage = self.internal_save_stop_reason(id, &reason);
+        self.internal_pay_stop_reason(reason_storage);
+
+        // Log
+        match reason {
+            Some(reason) => log_event!("Stream paused: {} reason: {}", stream.id, reason),
+            None => log_event!("Stream paused: {}", stream.id),
+        }
+        self.internal_notify(&stream, StreamEvent::Paused);
+    }
+
+    pub fn resume(&mut self, stream_id: U64) {
+        // convert id to native u64
+        let id: u64 = stream_id.0;
+
+        // get the stream
+        let stream = self.internal_get_stream(id);
+
+        // Only the sender or its operators can resume the stream
+        require!(self.internal_acts_for(id, &stream.sender, &env::predecessor_account_id()));
+
+        // assert that the stream is already paused
+        require!(stream.is_paused, "Cannot resume unpaused stream");
+
+        self.internal_resume(stream);
+    }
+
+    fn internal_resume(&mut self, mut stream: Stream) {
+        let current_timestamp: u64 = current_time();
+
+        // resume the stream
+        stream.is_paused = false;
+        self.escalated_pauses.remove(&stream.id);
+
+        // Update the withdraw_time so that the receiver will not be
+        // able to withdraw fund for paused time
+        let paused_seconds = if current_timestamp > stream.end_time {
+            stream.end_time - stream.paused_time
+        } else {
+            current_timestamp - stream.paused_time
+        };
+        stream.withdraw_time += paused_seconds;
+        self.internal_add_pause_time(stream.id, paused_seconds);
+
+        // Reset the paused_time and save
+        stream.paused_time = 0;
+        self.internal_save_stream_state(&stream);
+
+        // Log
+        log_event!("Stream resumed: {}", stream.id);
+        self.internal_notify(&stream, StreamEvent::Resumed);
+    }
+
+    #[payable]
+    pub fn cancel(&mut self, stream_id: U64, reason: Option<String>) -> PromiseOrValue<bool> {
+        //  only tranfsers the tokens to receiver
+        //  sender can claim using ft_claim_sender
+
+        // convert id to native u64
+        let id: u64 = stream_id.0;
+
+        let current_timestamp: u64 = current_time();
+        // Get the stream
+        let temp_stream = self.internal_get_stream(id);
+
+        // check that the stream can be cancelled, always before it starts
+        require!(
+            temp_stream.can_cancel || current_timestamp < temp_stream.start_time,
+            "Stream cannot be cancelled"
+        );
+
+        // Only the sender, or an operator of its profile, can cancel the stream
+        require!(
+            env::predecessor_account_id() == temp_stream.sender
+                || self.internal_is_profile_operator(
+                    id,
+                    &temp_stream.sender,
+                    &env::predecessor_account_id()
+                )
+        );
+
+        // Stream can only be cancelled if it has not ended
+        require!(
+            !self.internal_has_ended(&temp_stream, current_timestamp),
+            "Stream already ended"
+        );
+        require!(!temp_stream.is_cancelled, "already cancelled!");
+
+        self.internal_cancel_with_pair(temp_stream, &reason)
+    }
+
+    // paired streams are cancelled together, each with its own transfers
+    fn internal_cancel_with_pair(
+        &mut self,
+        temp_stream: Stream,
+        reason: &Option<String>,
+    ) -> PromiseOrValue<bool> {
+        let current_timestamp: u64 = current_time();
+        let mut reason_storage = 0;
+        if let Some(paired_id) = self.stream_pairs.get(&temp_stream.id) {
+            let paired_stream = self.internal_get_stream(paired_id);
+            if !paired_stream.is_cancelled
+                && !self.internal_has_ended(&paired_stream, current_timestamp)
+            {
+                reason_storage += self.internal_save_stop_reason(paired_id, reason);
+                let _ = self.internal_cancel(paired_stream, reason);
+            }
+        }
+
+        reason_storage += self.internal_save_stop_reason(temp_stream.id, reason);
+        self.internal_pay_stop_reason(reason_storage);
+        self.internal_cancel(temp_stream, reason)
+    }
+
+    fn internal_cancel(
+        &mut self,
+        mut temp_stream: Stream,
+        reason: &Option<String>,
+    ) -> PromiseOrValue<bool> {
+        let id: u64 = temp_stream.id;
+        let current_timestamp: u64 = current_time();
+
+        // Amounts to refund to the sender and the receiver
+        let (receiver_amt, sender_amt) =
+            self.internal_cancel_amounts(&temp_stream, current_timestamp);
+
+        // Refund the amounts to the sender and the receiver respectively
+        let sender = temp_stream.sender.clone();
+        let receiver = temp_stream.receiver.clone();
+
+        // The bond goes to the receiver if cancelled within the commitment
+        if let Some(bond) = self.bonds.remove(&id) {
+            let bond_to = if Self::internal_bond_forfeited(&temp_stream, &bond, current_timestamp) {
+                receiver.clone()
+            } else {
+                sender.clone()
+            };
+            Promise::new(bond_to).transfer(bond.amount);
+        }
+
+        // Update the stream balance and save
+        temp_stream.balance = sender_amt;
+        temp_stream.is_cancelled = true;
+        // an ft stream paying the receiver stays indexed until the transfer resolves
+        if temp_stream.is_native || current_timestamp < temp_stream.start_time {
+            self.internal_remove_end_indexes(&temp_stream);
+        }
+        // self.streams.insert(&id, &temp_stream);
+
+        // Nothing has streamed before the start, the whole balance goes back
+        // to the sender and the receiver is not paid
+        if current_timestamp < temp_stream.start_time {
+            log_event!("Stream cancelled before start: {}", id);
+            self.internal_notify(&temp_stream, StreamEvent::CancelledBeforeStart);
+            if temp_stream.is_native {
+                temp_stream.balance = 0;
+                self.internal_save_stream_state(&temp_stream);
+                return Promise::new(sender).transfer(sender_amt).into();
+            }
+            // the sender claims ft streams with `ft_claim_sender`
+            self.internal_save_stream_state(&temp_stream);
+            return PromiseOrValue::Value(true);
+        }
+
+        // log
+        match reason {
+            Some(reason) => log_event!("Stream cancelled: {} reason: {}", temp_stream.id, reason),
+            None => log_event!("Stream cancelled: {}", temp_stream.id),
+        }
+        self.internal_notify(&temp_stream, StreamEvent::Cancelled);
+
+        // the withheld part stays in the contract for the withholding account
+        let receiver_net_amt =
+            receiver_amt - self.internal_withheld_amount(&temp_stream, receiver_amt);
+
+        if temp_stream.is_native {
+            temp_stream.balance = 0;
+            self.internal_save_stream_state(&temp_stream);
+            if receiver_amt > 0 {
+                self.internal_record_payment(&temp_stream, receiver_amt);
+            }
+            let transfers = Promise::new(sender).transfer(sender_amt).then(
+                self.internal_with_withdraw_callback(
+                    Promise::new(receiver.clone()).transfer(receiver_net_amt),
+                    &temp_stream,
+                    receiver_net_amt,
+                ),
+            );
+            self.internal_native_payout(transfers, &receiver, receiver_net_amt)
+                .into()
+        } else {
+            ext_ft_transfer::ext(temp_stream.contract_id.clone())
+                .with_attached_deposit(1)
+                .ft_transfer(receiver.clone(), receiver_net_amt.into(), None)
+                .then(
+                    Self::ext(env::current_account_id()).internal_resolve_ft_withdraw(
+                        temp_stream,
+                        receiver_amt.into(),
+                        receiver_amt.into(),
+                        receiver,
+                        None,
+                        None,
+                    ),
+                )
+                .into()
+        }
+    }
+
+    // allows the sender to withdraw funds if the stream is_cancelled.
+    pub fn ft_claim_sender(&mut self, stream_id: U64) -> PromiseOrValue<bool> {
+        // convert id to native u64
+        let id: u64 = stream_id.0;
+
+        // Get the stream
+        let mut temp_stream = self.internal_get_stream(id);
+        require!(
+            temp_stream.sender == env::predecessor_account_id(),
+            "not sender"
+        );
+        require!(temp_stream.is_cancelled, "stream is not cancelled!");
+        ext_ft_transfer::ext(temp_stream.contract_id.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(temp_stream.sender.clone(), temp_stream.balance.into(), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .internal_resolve_ft_claim(stream_id, &mut temp_stream),
+            )
+            .into()
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_get_stream(&self, stream_id: u64) -> Stream {
+        let record = self.streams.get(&stream_id).expect("Stream does not exist");
+        let state = self.stream_states.get(&stream_id).unwrap();
+        record.into_stream(stream_id, state)
+    }
+
+    /// Writes both the creation data and the state of the stream.
+    pub(crate) fn internal_save_stream(&mut self, stream: &Stream) {
+        self.streams.insert(&stream.id, &StreamRecord::from(stream));
+        self.internal_save_stream_state(stream);
+    }
+
+    /// Drops a cancelled stream from the end time and ending soon indexes.
+    fn internal_remove_end_indexes(&mut self, stream: &Stream) {
+        self.stream_ends.remove(&(stream.end_time, stream.id));
+        self.ending_soon.remove(&stream.id);
+    }
+
+    /// Keeps the reason of a pause or cancel, a stop without reason clears it.
+    /// Returns the storage it added.
+    fn internal_save_stop_reason(&mut self, stream_id: u64, reason: &Option<String>) -> u64 {
+        let initial_storage = env::storage_usage();
+        match reason {
+            Some(reason) => {
+                require!(reason.len() <= MAX_REASON_LEN, "Reason is too long");
+                self.stop_reasons.insert(&stream_id, reason);
+            }
+            None => {
+                self.stop_reasons.remove(&stream_id);
+            }
+        }
+        env::storage_usage().saturating_sub(initial_storage)
+    }
+
+    /// Takes the storage of stop reasons from the attached deposit and refunds
+    /// the rest to the caller.
+    fn internal_pay_stop_reason(&self, storage: u64) {
+        if storage == 0 {
+            return;
+        }
+        let cost = Balance::from(storage) * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() >= cost,
+            "The amount provided is not enough for the reason storage"
+        );
+        if env::attached_deposit() > cost {
+            Promise::new(env::predecessor_account_id()).transfer(env::attached_deposit() - cost);
+        }
+    }
+
+    /// Writes only the balance, withdraw/pause times and pause/cancel flags.
+    pub(crate) fn internal_save_stream_state(&mut self, stream: &Stream) {
+        self.stream_states
+            .insert(&stream.id, &StreamState::from(stream));
+    }
+
+    /// Saves a withdrawal of `amount` from `temp_stream`, read before its ft
+    /// transfer, on top of the current state of the stream, so that donations
+    /// and top-ups made while the transfer ran are kept.
+    pub(crate) fn internal_save_withdrawal(&mut self, temp_stream: &Stream, amount: Balance) {
+        let mut stream = self.internal_get_stream(temp_stream.id);
+        stream.balance -= amount;
+        stream.withdraw_time = temp_stream.withdraw_time;
+        stream.is_cancelled = temp_stream.is_cancelled;
+        self.internal_save_stream_state(&stream);
+    }
+
+    /// Adds `amount` of `token` (None for NEAR), and its match if any, to the stream and
+    /// extends its end time by the whole seconds paid for. Returns the unused remainder.
+    pub(crate) fn internal_donate(
+        &mut self,
+        stream_id: u64,
+        donor: AccountId,
+        amount: Balance,
+        token: Option<AccountId>,
+    ) -> Balance {
+        let current_timestamp: u64 = current_time();
+
+        let mut stream = self.internal_get_stream(stream_id);
+
+        require!(
+            stream.is_native == token.is_none()
+                && (stream.is_native || Some(&stream.contract_id) == token.as_ref()),
+            "Token does not match the stream"
+        );
+        require!(!stream.is_cancelled, "Stream is cancelled");
+        require!(
+            !self.internal_has_ended(&stream, current_timestamp),
+            "Stream already ended"
+        );
+        require!(
+            self.rate_escalations.get(&stream_id).is_none(),
+            "Cannot donate to a stream with rate escalation"
+        );
+        require!(
+            self.rate_changes.get(&stream_id).is_none(),
+            "Cannot donate to a stream with a rate change"
+        );
+
+        let donated = amount / stream.rate * stream.rate;
+        require!(
+            donated > 0,
+            "Donation is less than one second of the stream"
+        );
+        log_event!("Stream donation: {} {} {}", stream_id, donor, donated);
+
+        // a sponsor may match the donation
+        let matched = self.internal_match_donation(&stream, donated);
+
+        let extension = (donated + matched) / stream.rate;
+        require!(extension <= u32::MAX.into(), "Donation is too large");
+
+        // move the stream in the end time index
+        self.stream_ends.remove(&(stream.end_time, stream_id));
+        stream.end_time += extension as u64;
+        self.stream_ends.insert(&(stream.end_time, stream_id), &());
+
+        stream.balance += donated + matched;
+        self.internal_save_stream(&stream);
+
+        amount - donated
+    }
+
+    /// Amount the stream pays out between `from` and `to`. With a rate escalation the
+    /// rate grows every period, counted in block time from the start time.
+    pub(crate) fn internal_streamed_amount(
+        &self,
+        stream: &Stream,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Balance {
+        // the remainder of a stream created from an amount comes with its last second
+        let remainder = match self.stream_remainders.get(&stream.id) {
+            Some(remainder) if from < stream.end_time && to >= stream.end_time => remainder,
+            _ => 0,
+        };
+
+        // the old rate until the change, the new one from then on
+        if let Some(change) = self.rate_changes.get(&stream.id) {
+            let effective_at = u64::from(change.effective_at);
+            let before = effective_at.min(to).saturating_sub(from);
+            let after = to.saturating_sub(effective_at.max(from));
+            return stream.rate * u128::from(before) + change.rate * u128::from(after) + remainder;
+        }
+
+        let escalation = match self.rate_escalations.get(&stream.id) {
+            Some(escalation) => escalation,
+            None => return stream.rate * u128::from(to - from) + remainder,
+        };
+
+        let mut amount = remainder;
+        let mut rate = stream.rate;
+        let mut period_start = stream.start_time;
+        while period_start < to {
+            let period_end = period_start + u64::from(escalation.period);
+            let overlap = period_end.min(to).saturating_sub(period_start.max(from));
+            amount += rate * u128::from(overlap);
+
+            rate = self.internal_escalated_rate(stream.id, rate, escalation.bps);
+            period_start = period_end;
+        }
+        amount
+    }
+
+    /// Takes what the receiver can withdraw now, up to the withdraw cap, out of the
+    /// stream and returns it with the cap after the withdrawal. The caller transfers
+    /// it and saves the stream and the cap once it is paid.
+    pub(crate) fn internal_withdraw_accrued(
+        &mut self,
+        temp_stream: &mut Stream,
+    ) -> (Balance, Option<WithdrawCap>) {
+        // Only the time vested so far under the stream's accrual period
+        let current_timestamp: u64 = self.internal_vested_time(temp_stream, current_time());
+
+        if current_timestamp <= temp_stream.start_time
+            || temp_stream.withdraw_time >= temp_stream.end_time
+            || current_timestamp <= temp_stream.withdraw_time
+        {
+            return (0, None);
+        }
+
+        let time_elapsed: u64;
+        let withdraw_time: u64;
+
+        // Calculate the elapsed time
+        if self.internal_has_ended(temp_stream, current_timestamp) {
+            withdraw_time = current_timestamp;
+
+            if temp_stream.is_paused {
+                time_elapsed = temp_stream.paused_time - temp_stream.withdraw_time;
+            } else {
+                time_elapsed = temp_stream.end_time - temp_stream.withdraw_time;
+            }
+        } else if temp_stream.is_paused {
+            let paused_time = temp_stream.paused_time.min(current_timestamp);
+            time_elapsed = paused_time - temp_stream.withdraw_time;
+            withdraw_time = paused_time;
+        } else {
+            time_elapsed = current_timestamp - temp_stream.withdraw_time;
+            withdraw_time = current_timestamp;
+        }
+
+        // Only withdraw whole seconds up to the cap, the rest stays accrued
+        let mut withdraw_cap = self.withdraw_caps.get(&temp_stream.id);
+        let (time_elapsed, withdraw_time) = match withdraw_cap.as_mut() {
+            Some(cap) => {
+                if current_timestamp >= u64::from(cap.period_start) + u64::from(cap.period) {
+                    cap.period_start = compact_timestamp(current_timestamp);
+                    cap.withdrawn = 0;
+                }
+                let allowed_time = (cap.amount - cap.withdrawn) / temp_stream.rate;
+                require!(allowed_time > 0, "Withdraw cap reached for this period");
+
+                let capped = if u128::from(time_elapsed) > allowed_time {
+                    // allowed_time < time_elapsed, so it fits in u64
+                    let allowed_time = allowed_time as u64;
+                    (allowed_time, temp_stream.withdraw_time + allowed_time)
+                } else {
+                    (time_elapsed, withdraw_time)
+                };
+                cap.withdrawn += temp_stream.rate * u128::from(capped.0);
+                capped
+            }
+            None => (time_elapsed, withdraw_time),
+        };
+
+        // A child stream only pays the whole seconds its parent funded so far
+        let (time_elapsed, withdraw_time) = match self.internal_funded_time(temp_stream) {
+            Some(funded_time) if funded_time < time_elapsed => {
+                require!(funded_time > 0, "Child stream is not funded yet");
+                (funded_time, temp_stream.withdraw_time + funded_time)
+            }
+            _ => (time_elapsed, withdraw_time),
+        };
+
+        // Calculate the withdrawal amount and update the stream struct
+        let withdrawal_amount = self.internal_streamed_amount(
+            temp_stream,
+            temp_stream.withdraw_time,
+            temp_stream.withdraw_time + time_elapsed,
+        );
+        temp_stream.balance -= withdrawal_amount;
+        temp_stream.withdraw_time = withdraw_time;
+        (withdrawal_amount, withdraw_cap)
+    }
+
+    /// Gives the caller back its previous withdraw nonce after a failed ft
+    /// transfer, unless it used a newer one meanwhile.
+    pub(crate) fn internal_restore_withdraw_nonce(
+        &mut self,
+        stream_id: u64,
+        (caller, nonce, previous): WithdrawNonce,
+    ) {
+        let key = (stream_id, caller);
+        if self.withdraw_nonces.get(&key) != Some(nonce.0) {
+            return;
+        }
+        match previous {
+            Some(previous) => self.withdraw_nonces.insert(&key, &previous.0),
+            None => self.withdraw_nonces.remove(&key),
+        };
+    }
+
+    /// Saves the withdraw cap returned by `internal_withdraw_accrued`.
+    pub(crate) fn internal_save_withdraw_cap(&mut self, stream_id: u64, cap: Option<WithdrawCap>) {
+        if let Some(cap) = cap {
+            self.withdraw_caps.insert(&stream_id, &cap);
+        }
+    }
+
+    /// Saves both streams of a netted withdraw and records the payout of each side.
+    pub(crate) fn internal_save_netted_withdraw(
+        &mut self,
+        stream: &Stream,
+        amount: Balance,
+        paired_stream: &Stream,
+        paired_amount: Balance,
+    ) {
+        for (stream, amount) in [(stream, amount), (paired_stream, paired_amount)] {
+            self.internal_save_stream_state(stream);
+            if amount > 0 {
+                self.internal_record_payment(stream, amount);
+            }
+        }
+    }
+
+    /// Part of a payout to the receiver that goes to the stream's withholding account.
+    pub(crate) fn internal_withheld_amount(&self, stream: &Stream, amount: Balance) -> Balance {
+        if let Some(share) = self.internal_child_share(stream.id, amount) {
+            return share;
+        }
+        self.withholdings.get(&stream.id).map_or(0, |withholding| {
+            amount * u128::from(withholding.bps) / 10_000
+        })
+    }
+
+    /// Amounts of an ended stream kept for the receiver, streamed but not
+    /// withdrawn, and left for the sender to reclaim.
+    pub(crate) fn internal_sender_reclaim(&self, stream: &Stream) -> (Balance, Balance) {
+        // Amount that has been streamed to the receiver
+        let withdrawal_amount = if stream.is_paused {
+            self.internal_streamed_amount(stream, stream.withdraw_time, stream.paused_time)
+        } else if stream.end_time > stream.withdraw_time {
+            // receiver has not withdrawn after stream ended
+            self.internal_streamed_amount(stream, stream.withdraw_time, stream.end_time)
+        } else {
+            0
+        };
+
+        // a child stream may hold less than it streamed
+        let withdrawal_amount = withdrawal_amount.min(stream.balance);
+        (withdrawal_amount, stream.balance - withdrawal_amount)
+    }
+
+    /// Amounts that cancelling the stream at `timestamp` pays the receiver, only
+    /// what has vested, and refunds the sender.
+    pub(crate) fn internal_cancel_amounts(
+        &self,
+        stream: &Stream,
+        timestamp: Timestamp,
+    ) -> (Balance, Balance) {
+        let vested_time = self
+            .internal_vested_time(stream, timestamp)
+            .max(stream.withdraw_time);
+        let accrued_until = if stream.is_paused {
+            stream.paused_time.min(vested_time)
+        } else {
+            vested_time
+        };
+        let receiver_amt =
+            self.internal_streamed_amount(stream, stream.withdraw_time, accrued_until);
+        (receiver_amt, stream.balance - receiver_amt)
+    }
+
+    /// Whether a cancel at `timestamp` pays the bond to the receiver: after the
+    /// start and before the end of the commitment.
+    pub(crate) fn internal_bond_forfeited(
+        stream: &Stream,
+        bond: &Bond,
+        timestamp: Timestamp,
+    ) -> bool {
+        timestamp >= stream.start_time && timestamp < u64::from(bond.commit_end)
+    }
+
+    /// Appends a payout to the receiver to the payment history and monthly totals of both
+    /// parties, and credits the withheld part of it to the withholding account.
+    pub(crate) fn internal_record_payment(&mut self, stream: &Stream, amount: Balance) {
+        let payment_id = self.payments.len();
+        self.payments.push(&Payment {
+            stream_id: stream.id,
+            receiver: stream.receiver.clone(),
+            amount,
+            timestamp: compact_timestamp(current_time()),
+        });
+
+        for account_id in [&stream.sender, &stream.receiver] {
+            let mut payment_ids = self.account_payments.get(account_id).unwrap_or_else(|| {
+                Vector::new(StorageKey::AccountPaymentsInner {
+                    account_hash: env::sha256_array(account_id.as_bytes()),
+                })
+            });
+            payment_ids.push(&payment_id);
+            self.account_payments.insert(account_id, &payment_ids);
+        }
+
+        // add the payment to the monthly totals of both parties
+        let token = if stream.is_native {
+            None
+        } else {
+            Some(stream.contract_id.clone())
+        };
+
+        let withheld = self.internal_withheld_amount(stream, amount);
+        // the rest of the withheld part funds a child stream
+        if let Some(withholding) = self.withholdings.get(&stream.id).filter(|_| withheld > 0) {
+            let key = (withholding.account_id.clone(), token.clone());
+            let balance = self.withheld_balances.get(&key).unwrap_or(0);
+            self.withheld_balances.insert(&key, &(balance + withheld));
+
+            log_event!(
+                "Stream payout: {} {} {}",
+                stream.id,
+                stream.receiver,
+                amount - withheld
+            );
+            log_event!(
+                "Stream withholding: {} {} {}",
+                stream.id,
+                withholding.account_id,
+                withheld
+            );
+        }
+        self.internal_fund_child(stream, amount);
+        self.internal_track_ending_soon(stream);
+
+        let month = calendar_month(current_time());
+
+        let sender_key = (stream.sender.clone(), token.clone(), month);
+        let mut sender_total = self.monthly_totals.get(&sender_key).unwrap_or_default();
+        sender_total.streamed_out += amount;
+        self.monthly_totals.insert(&sender_key, &sender_total);
+
+        let receiver_key = (stream.receiver.clone(), token.clone(), month);
+        let mut receiver_total = self.monthly_totals.get(&receiver_key).unwrap_or_default();
+        receiver_total.streamed_in += amount;
+        self.monthly_totals.insert(&receiver_key, &receiver_total);
+
+        let statement_key = (stream.receiver.clone(), token);
+        let mut statement = self
+            .receiver_statements
+            .get(&statement_key)
+            .unwrap_or_default();
+        statement.received += amount - withheld;
+        statement.withheld += withheld;
+        statement.payments += 1;
+        self.receiver_statements.insert(&statement_key, &statement);
+    }
+
+    /// Saves a new stream and adds it to the global, sender, receiver and end time indexes.
+    /// Updates to an existing stream only need `internal_save_stream`.
+    pub(crate) fn internal_add_stream(&mut self, stream: &Stream) {
+        self.internal_save_stream(stream);
+        self.stream_ids.insert(&stream.id);
+        self.stream_ends.insert(&(stream.end_time, stream.id), &());
+
+        let mut sender_ids = self.sender_streams.get(&stream.sender).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::SenderStreamsInner {
+                account_hash: env::sha256_array(stream.sender.as_bytes()),
+            })
+        });
+        sender_ids.insert(&stream.id);
+        self.sender_streams.insert(&stream.sender, &sender_ids);
+
+        self.internal_index_received_stream(stream);
+
+        self.accounts.insert(&stream.sender);
+        self.accounts.insert(&stream.receiver);
+
+        self.internal_notify(stream, StreamEvent::Created);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    #[test]
+    fn initializes() {
+        let contract = Contract::new();
+        assert_eq!(contract.current_id, 1);
+        assert_eq!(contract.stream_ids.len(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided doesn't matches the stream")]
+    fn create_stream_invalid_amount() {
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 172800);
+        let sender = accounts(0);
+        let receiver = accounts(1);
+        let rate = U128::from(1 * NEAR);
+
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender, 200000 * NEAR);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Sender and receiver cannot be Same")]
+    fn create_stream_invalid_receipient() {
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 172800); // 2 days
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(0); // alice
+        let rate = U128::from(1 * NEAR);
+
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender.clone(), 172800 * NEAR);
+
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            true,
+            false,
+            None,
+        );
+    }
+
+    #[test]
+    fn create_stream() {
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 172800); // 2 days
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender.clone(), 172800 * NEAR);
+
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            true,
+            false,
+            None,
+        );
+        assert_eq!(contract.current_id, 2);
+        let params_key = 1;
+        let stream = contract.internal_get_stream(params_key);
+        require!(!stream.is_paused);
+        assert_eq!(stream.id, 1);
+        assert_eq!(stream.sender, sender.clone());
+        assert_eq!(stream.receiver, accounts(1));
+        assert_eq!(stream.balance, 172800 * NEAR);
+        assert_eq!(stream.rate, rate.0);
+
+        let stream_start_time: u64 = start_time.0;
+        let stream_end_time: u64 = end_time.0;
+
+        assert_eq!(stream.start_time, stream_start_time);
+        assert_eq!(stream.end_time, stream_end_time);
+        assert_eq!(stream.withdraw_time, stream_start_time);
+        assert_eq!(stream.paused_time, 0);
+        assert_eq!(stream.can_update, false);
+        assert_eq!(stream.can_cancel, true);
+    }
+
+    #[test]
+    fn stream_record_size() {
+        let start = env::block_timestamp();
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            U64(start),
+            U64(start + 10),
+            true,
+            false,
+            None,
+        );
+
+        // the unpacked stream takes 117 bytes, the stored record 46 and the
+        // state rewritten on withdraw/pause/resume/cancel only 25
+        let stream = contract.internal_get_stream(1);
+        let record = contract.streams.get(&1).unwrap();
+        let state = contract.stream_states.get(&1).unwrap();
+        assert_eq!(stream.try_to_vec().unwrap().len(), 117);
+        assert_eq!(record.try_to_vec().unwrap().len(), 46);
+        assert_eq!(state.try_to_vec().unwrap().len(), 25);
+
+        // flags and timestamps survive the round trip
+        assert!(stream.can_cancel && !stream.can_update && stream.is_native);
+        assert_eq!(stream.end_time, start + 10);
+        assert_eq!(stream.contract_id, "near.testnet".parse().unwrap());
+    }
+
+    #[test]
+    fn calendar_months() {
+        assert_eq!(calendar_month(0), 1970 * 12);
+        assert_eq!(calendar_month(951_782_400), 2000 * 12 + 1); // 2000-02-29
+        assert_eq!(calendar_month(1_704_067_199), 2023 * 12 + 11); // 2023-12-31 23:59:59
+        assert_eq!(calendar_month(1_704_067_200), 2024 * 12); // 2024-01-01
+    }
+
+    #[test]
+    #[should_panic(expected = "Timestamp is too large")]
+    fn create_stream_timestamp_overflow() {
+        let start = u64::from(u32::MAX);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1);
+
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender.clone(), 10);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            U64(start),
+            U64(start + 10),
+            true,
+            false,
+            None,
+        );
+    }
 
     #[test]
-    fn create_stream() {
+    fn withdraw_stream_receiver() {
+        // 1. create_stream contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 172800); // 2 days
+        let end_time: U64 = U64::from(start + 10);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
+
+        let stream_id = U64::from(1);
+
+        // 2. create stream
+        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, start_time.0);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+
+        // 4. assert internal balance
+        // Check the contract balance after stream is created
+        set_context_with_balance_timestamp(env::current_account_id(), 10 * NEAR, start_time.0);
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        require!(internal_balance == 10 * NEAR);
+
+        // 3. call withdraw (action)
+        let stream_start_time: u64 = start_time.0;
+        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 2);
+
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let stream = contract.internal_get_stream(stream_id.0);
+        let internal_balance = stream.balance;
+
+        assert_eq!(internal_balance, 8 * NEAR);
+        assert_eq!(stream.withdraw_time, stream_start_time + 2);
+    }
 
+    #[test]
+    #[should_panic(expected = "Cannot withdraw before the stream has ended")]
+    fn withdraw_stream_sender_before_end() {
+        // 1. create_stream contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
 
-        set_context_with_balance(sender.clone(), 172800 * NEAR);
+        let stream_id = U64::from(1);
 
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, true, false);
-        assert_eq!(contract.current_id, 2);
-        let params_key = 1;
-        let stream = contract.streams.get(&params_key).unwrap();
-        require!(!stream.is_paused);
-        assert_eq!(stream.id, 1);
-        assert_eq!(stream.sender, sender.clone());
-        assert_eq!(stream.receiver, accounts(1));
-        assert_eq!(stream.balance, 172800 * NEAR);
-        assert_eq!(stream.rate, rate.0);
+        // 2. create stream
+        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, start_time.0);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+
+        // 3. call withdraw (action)
+        let stream_start_time: u64 = start_time.0;
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 2);
+        contract.withdraw(stream_id, None);
+    }
+
+    #[test]
+    fn withdraw_stream_sender_after_end() {
+        // 1. create_stream contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10);
+        let sender = &accounts(0); // // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
+
+        let stream_id = U64::from(1);
+
+        let stream_start_time: u64 = start_time.0;
+        // 2. create stream
+        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 2);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
+        contract.resume(stream_id);
+
+        // 3. call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 11);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 8 * NEAR);
+    }
+
+    #[test]
+    fn withdraw_stream_sender_after_end_paused_stream() {
+        // 1. create_stream contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
+
+        let stream_id = U64::from(1);
+
+        let stream_start_time: u64 = start_time.0;
+        // 2. create stream
+        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
+        contract.pause(stream_id, None);
+
+        // 3. call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 11);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 4 * NEAR);
+    }
+
+    #[test]
+    fn withdraw_stream_sender_after_end_multiple_pauses() {
+        // 1. create_stream contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 20);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
+
+        let stream_id = U64::from(1);
+
+        let stream_start_time: u64 = start_time.0;
+
+        // 2. create stream
+        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 6);
+        contract.resume(stream_id);
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
+        contract.resume(stream_id);
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 15);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 17);
+        contract.resume(stream_id);
+
+        // 3. call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 12 * NEAR);
+    }
+
+    #[test]
+    fn withdraw_stream_receiver_after_end_multiple_pauses() {
+        // 1. create_stream contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 20);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
+
+        let stream_id = U64::from(1);
+
+        let stream_start_time: u64 = start_time.0;
+
+        // 2. create stream
+        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 6);
+        contract.resume(stream_id);
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
+        contract.resume(stream_id);
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 15);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 17);
+        contract.resume(stream_id);
+
+        // 3. call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 8 * NEAR);
+    }
+
+    #[test]
+    fn test_sender_withdraws_before_sender() {
+        // 1. create_stream contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 20);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
+
+        let stream_id = U64::from(1);
+
+        let stream_start_time: u64 = start_time.0;
+
+        // 2. create stream
+        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
+        contract.pause(stream_id, None);
 
-        let stream_start_time: u64 = start_time.0;
-        let stream_end_time: u64 = end_time.0;
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
+        contract.resume(stream_id);
 
-        assert_eq!(stream.start_time, stream_start_time);
-        assert_eq!(stream.end_time, stream_end_time);
-        assert_eq!(stream.withdraw_time, stream_start_time);
-        assert_eq!(stream.paused_time, 0);
-        assert_eq!(stream.can_update, false);
-        assert_eq!(stream.can_cancel, true);
+        // 3. sender call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 16 * NEAR);
+
+        // 3. receiver call withdraw
+        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 25);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 0);
     }
 
     #[test]
-    fn withdraw_stream_receiver() {
+    fn test_receiver_withdraws_before_sender() {
         // 1. create_stream contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10);
+        let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
@@ -645,37 +3006,51 @@ mod tests {
 
         let stream_id = U64::from(1);
 
+        let stream_start_time: u64 = start_time.0;
+
         // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, start_time.0);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
 
-        // 4. assert internal balance
-        // Check the contract balance after stream is created
-        set_context_with_balance_timestamp(env::current_account_id(), 10 * NEAR, start_time.0);
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        require!(internal_balance == 10 * NEAR);
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
+        contract.pause(stream_id, None);
 
-        // 3. call withdraw (action)
-        let stream_start_time: u64 = start_time.0;
-        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 2);
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
+        contract.resume(stream_id);
 
-        contract.withdraw(stream_id);
+        // 3. sender call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None);
 
         // 4. assert internal balance
-        let stream = contract.streams.get(&stream_id.0).unwrap();
-        let internal_balance = stream.balance;
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 4 * NEAR);
 
-        assert_eq!(internal_balance, 8 * NEAR);
-        assert_eq!(stream.withdraw_time, stream_start_time + 2);
+        // 3. receiver call withdraw
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 25);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 0);
     }
 
     #[test]
-    #[should_panic(expected = "Cannot withdraw before the stream has ended")]
-    fn withdraw_stream_sender_before_end() {
+    #[should_panic(expected = "Already withdrawn")]
+    fn test_receiver_tries_multiple_withdraw() {
         // 1. create_stream contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10);
+        let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
@@ -683,23 +3058,107 @@ mod tests {
 
         let stream_id = U64::from(1);
 
+        let stream_start_time: u64 = start_time.0;
+
         // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, start_time.0);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
 
-        // 3. call withdraw (action)
-        let stream_start_time: u64 = start_time.0;
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 2);
-        contract.withdraw(stream_id);
+        // pause and resume the stream
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
+        contract.pause(stream_id, None);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
+        contract.resume(stream_id);
+
+        // 3. receiver call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None);
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 4 * NEAR);
+
+        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None); // panics here
     }
 
     #[test]
-    fn withdraw_stream_sender_after_end() {
+    #[should_panic(expected = "Withdrawal already executed")]
+    fn test_withdraw_nonce_retried() {
+        let mut contract = Contract::new();
+        let stream_id = U64(1);
+
+        set_context_with_balance_timestamp(accounts(0), 20 * NEAR, 0);
+        contract.create_stream(accounts(1), U128(NEAR), U64(0), U64(20), false, false, None);
+
+        set_context_with_balance_timestamp(accounts(1), 0, 5);
+        contract.withdraw(stream_id, Some(U64(7)));
+        assert!(contract.is_withdrawal_nonce_used(stream_id, accounts(1), U64(7)));
+        assert!(!contract.is_withdrawal_nonce_used(stream_id, accounts(1), U64(8)));
+        assert_eq!(contract.internal_get_stream(1).balance, 15 * NEAR);
+
+        // the wallet retries the same attempt
+        set_context_with_balance_timestamp(accounts(1), 0, 6);
+        contract.withdraw(stream_id, Some(U64(7)));
+    }
+
+    #[test]
+    fn test_withdraw_nonce_failed_transfer() {
+        let mut contract = Contract::new();
+        let stream_id = U64(1);
+
+        // alice -> bob, 1 USN per second from 0 to 10
+        set_context_with_balance("usdn.testnet".parse().unwrap(), 0);
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"0","end":"10","can_update":false,"can_cancel":true}}"#,
+            accounts(1),
+        );
+        contract.ft_on_transfer(accounts(0), U128(10), msg);
+
+        set_context_with_balance_timestamp(accounts(1), 0, 4);
+        contract.withdraw(stream_id, Some(U64(3)));
+        set_context_with_balance_timestamp(accounts(1), 0, 5);
+        let mut stream = contract.internal_get_stream(1);
+        let (amount, cap) = contract.internal_withdraw_accrued(&mut stream);
+        contract.withdraw(stream_id, Some(U64(7)));
+
+        // the transfer fails, nothing was paid
+        set_promise_result(PromiseResult::Failed);
+        let nonce = (accounts(1), U64(7), Some(U64(3)));
+        contract.internal_resolve_ft_withdraw(
+            stream,
+            U128(amount),
+            U128(amount),
+            accounts(1),
+            cap,
+            Some(nonce),
+        );
+        assert!(!contract.is_withdrawal_nonce_used(stream_id, accounts(1), U64(7)));
+        assert!(contract.is_withdrawal_nonce_used(stream_id, accounts(1), U64(3)));
+
+        // the wallet retries the same attempt
+        set_context_with_balance_timestamp(accounts(1), 0, 6);
+        contract.withdraw(stream_id, Some(U64(7)));
+        assert!(contract.is_withdrawal_nonce_used(stream_id, accounts(1), U64(7)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Already withdrawn")]
+    fn test_sender_tries_multiple_withdraw() {
         // 1. create_stream contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10);
-        let sender = &accounts(0); // // alice
+        let end_time: U64 = U64::from(start + 20);
+        let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
@@ -707,32 +3166,48 @@ mod tests {
         let stream_id = U64::from(1);
 
         let stream_start_time: u64 = start_time.0;
+
         // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
 
         // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 2);
-        contract.pause(stream_id);
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
+        contract.pause(stream_id, None);
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
         contract.resume(stream_id);
 
-        // 3. call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 11);
-        contract.withdraw(stream_id);
+        // 3. sender call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None);
 
         // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 8 * NEAR);
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 16 * NEAR);
+
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None); // panics here
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 16 * NEAR);
     }
 
     #[test]
-    fn withdraw_stream_sender_after_end_paused_stream() {
+    fn test_withdraw_after_end_on_paused() {
         // 1. create_stream contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10);
+        let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
@@ -741,576 +3216,1179 @@ mod tests {
         let stream_id = U64::from(1);
 
         let stream_start_time: u64 = start_time.0;
+
         // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
 
         // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
-        contract.pause(stream_id);
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
+        contract.pause(stream_id, None);
 
-        // 3. call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 11);
-        contract.withdraw(stream_id);
+        // 3. sender call withdraw after stream has ended (action)
+        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
+        contract.withdraw(stream_id, None);
 
         // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 4 * NEAR);
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 9 * NEAR);
+
+        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 25);
+        contract.withdraw(stream_id, None); // panics here
+
+        // 4. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 0);
     }
 
     #[test]
-    fn withdraw_stream_sender_after_end_multiple_pauses() {
-        // 1. create_stream contract
+    fn test_pause() {
+        // 1. Create the contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 20);
+        let end_time: U64 = U64::from(start + 10000);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
 
+        set_context_with_balance(sender.clone(), 10000 * NEAR);
+
+        // 2. create stream
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
         let stream_id = U64::from(1);
 
-        let stream_start_time: u64 = start_time.0;
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 10);
+        // 3. pause
+        contract.pause(stream_id, None);
 
-        // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        // 4. assert
+        require!(contract.internal_get_stream(stream_id.0).is_paused);
+    }
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
-        contract.pause(stream_id);
+    #[test]
+    #[should_panic(expected = "Cannot pause already paused stream")]
+    fn double_pause_panic() {
+        // 1. Create the contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10000);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 6);
-        contract.resume(stream_id);
+        set_context_with_balance(sender.clone(), 10000 * NEAR);
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
-        contract.pause(stream_id);
+        // 2. create stream and pause
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+        let stream_id = U64::from(1);
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 10);
+        contract.pause(stream_id, None);
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
-        contract.resume(stream_id);
+        // 3. pause
+        contract.pause(stream_id, None);
+    }
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 15);
-        contract.pause(stream_id);
+    #[test]
+    fn test_resume() {
+        // 1. Create the contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10000);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 17);
+        set_context_with_balance(sender.clone(), 10000 * NEAR);
+
+        // 2. create stream and pause
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+        let stream_id = U64::from(1);
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
+        contract.pause(stream_id, None);
+
+        // 3. resume
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 4);
         contract.resume(stream_id);
 
-        // 3. call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id);
+        // 4. assert
+        let stream = contract.internal_get_stream(stream_id.0);
+        require!(!stream.is_paused);
+        assert_eq!(stream.withdraw_time, start + 3);
+    }
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 12 * NEAR);
+    #[test]
+    #[should_panic(expected = "Stream cannot be cancelled")]
+    fn test_cancel_with_no_cancel() {
+        // 1. Create the contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10000);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender.clone(), 10000 * NEAR);
+
+        // 2. create stream and pause
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+        let stream_id = U64::from(1);
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
+        contract.cancel(stream_id, None);
     }
 
     #[test]
-    fn withdraw_stream_receiver_after_end_multiple_pauses() {
-        // 1. create_stream contract
+    fn test_cancel() {
+        // 1. Create the contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 20);
+        let end_time: U64 = U64::from(start + 10);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
 
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+
+        // 2. create stream and cancel
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            true,
+            false,
+            None,
+        );
         let stream_id = U64::from(1);
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
+        contract.cancel(stream_id, None);
 
-        let stream_start_time: u64 = start_time.0;
+        // 3. assert internal balance
+        let internal_balance = contract.internal_get_stream(stream_id.0).balance;
+        assert_eq!(internal_balance, 0);
+    }
 
-        // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+    #[test]
+    fn test_create_stream_with_amount() {
+        let mut contract = Contract::new();
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 4);
-        contract.pause(stream_id);
+        // 100 over 30 seconds is 3 per second and 10 with the last second
+        set_context_with_balance_timestamp(accounts(0), 100, 0);
+        let stream_id = contract.create_stream_with_amount(
+            accounts(1),
+            U128(100),
+            U64(10),
+            U64(40),
+            true,
+            false,
+            None,
+        );
+        let stream = contract.get_stream(stream_id);
+        assert_eq!(stream.rate, 3);
+        assert_eq!(stream.balance, 100);
+
+        set_context_with_balance_timestamp(accounts(1), 0, 20);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.get_stream(stream_id).balance, 70);
+
+        // the last withdrawal pays exactly the rest
+        set_context_with_balance_timestamp(accounts(1), 0, 45);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.get_stream(stream_id).balance, 0);
+        let statement = contract.get_receiver_statement(accounts(1), None);
+        assert_eq!(statement.received, U128(100));
+    }
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 6);
-        contract.resume(stream_id);
+    #[test]
+    #[should_panic(expected = "Amount is smaller than the stream duration")]
+    fn test_create_stream_with_small_amount() {
+        let mut contract = Contract::new();
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
-        contract.pause(stream_id);
+        // 20 over 30 seconds would be a zero rate
+        set_context_with_balance_timestamp(accounts(0), 20, 0);
+        contract.create_stream_with_amount(
+            accounts(1),
+            U128(20),
+            U64(10),
+            U64(40),
+            true,
+            false,
+            None,
+        );
+    }
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
-        contract.resume(stream_id);
+    #[test]
+    fn test_create_stream_with_nonce() {
+        let mut contract = Contract::new();
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 15);
-        contract.pause(stream_id);
+        // the id is known before the stream is created and the counter is not used
+        let expected_id = contract.get_nonce_stream_id(accounts(0), U64(7));
+        assert!(expected_id.0 >= NONCE_STREAM_ID_FLAG);
+        set_context_with_balance_timestamp(accounts(0), 10, 0);
+        let stream_id = contract.create_stream_with_nonce(
+            accounts(1),
+            U128(1),
+            U64(10),
+            U64(20),
+            true,
+            false,
+            None,
+            U64(7),
+        );
+        assert_eq!(stream_id, expected_id);
+        assert_eq!(contract.get_stream(stream_id).sender, accounts(0));
+        assert_eq!(contract.current_id, 1);
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 17);
-        contract.resume(stream_id);
+        // the nonce is scoped to the sender
+        assert_ne!(
+            contract.get_nonce_stream_id(accounts(2), U64(7)),
+            expected_id
+        );
+    }
 
-        // 3. call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id);
+    #[test]
+    #[should_panic(expected = "Stream with this nonce already exists")]
+    fn test_create_stream_with_nonce_twice() {
+        let mut contract = Contract::new();
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 8 * NEAR);
+        set_context_with_balance_timestamp(accounts(0), 10, 0);
+        for _ in 0..2 {
+            contract.create_stream_with_nonce(
+                accounts(1),
+                U128(1),
+                U64(10),
+                U64(20),
+                true,
+                false,
+                None,
+                U64(7),
+            );
+        }
     }
 
     #[test]
-    fn test_sender_withdraws_before_sender() {
-        // 1. create_stream contract
+    fn test_cancel_before_start() {
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
 
+        // a stream that cannot be cancelled once it starts
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+        contract.create_stream(
+            receiver.clone(),
+            U128::from(NEAR),
+            U64::from(start + 10),
+            U64::from(start + 20),
+            false,
+            false,
+            None,
+        );
         let stream_id = U64::from(1);
 
-        let stream_start_time: u64 = start_time.0;
-
-        // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
-
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
-        contract.pause(stream_id);
-
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
-        contract.resume(stream_id);
-
-        // 3. sender call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id);
-
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 16 * NEAR);
-
-        // 3. receiver call withdraw
-        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 25);
-        contract.withdraw(stream_id);
-
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 0);
+        // the whole balance goes back to alice, bob is not paid
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 5);
+        contract.cancel(stream_id, None);
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert!(stream.is_cancelled);
+        assert_eq!(stream.balance, 0);
+        assert_eq!(near_sdk::test_utils::get_created_receipts().len(), 1);
+        assert_eq!(
+            contract
+                .get_receiver_statement(receiver.clone(), None)
+                .payments,
+            U64(0)
+        );
     }
 
     #[test]
-    fn test_receiver_withdraws_before_sender() {
-        // 1. create_stream contract
+    #[should_panic(expected = "You are not authorized to update this stream")]
+    fn test_update_unauthorized() {
+        // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
+        let start_time: U64 = U64::from(start + 10);
         let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
 
-        let stream_id = U64::from(1);
-
-        let stream_start_time: u64 = start_time.0;
-
-        // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
-
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
-        contract.pause(stream_id);
-
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
-        contract.resume(stream_id);
-
-        // 3. sender call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id);
+        set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 4 * NEAR);
+        // 2. create stream and cancel
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
+        let stream_id = U64::from(1);
 
-        // 3. receiver call withdraw
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 25);
-        contract.withdraw(stream_id);
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 11);
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 0);
+        contract.update(
+            stream_id,
+            Option::Some(U64::from(start + 12)),
+            Option::Some(U64::from(start + 14)),
+            Option::Some(U128::from(2 * NEAR)),
+        );
     }
 
     #[test]
-    #[should_panic(expected = "Already withdrawn")]
-    fn test_receiver_tries_multiple_withdraw() {
-        // 1. create_stream contract
+    #[should_panic(expected = "Cannot update: stream already started")]
+    fn test_update_after_stream_start() {
+        // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
+        let start_time: U64 = U64::from(start + 10);
         let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
 
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+
+        // 2. create stream and cancel
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
         let stream_id = U64::from(1);
 
-        let stream_start_time: u64 = start_time.0;
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 11);
 
-        // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        contract.update(
+            stream_id,
+            Option::Some(U64::from(start + 12)),
+            Option::Some(U64::from(start + 14)),
+            Option::Some(U128::from(2 * NEAR)),
+        );
+    }
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
-        contract.pause(stream_id);
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the stream")]
+    fn test_update_stream_insufficient_balance_1() {
+        // 1. Create the contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start + 10);
+        let end_time: U64 = U64::from(start + 20);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(1 * NEAR);
+        let mut contract = Contract::new();
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
-        contract.resume(stream_id);
+        set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 3. receiver call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id);
+        // 2. create stream and cancel
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
+        let stream_id = U64::from(1);
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 4 * NEAR);
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
 
-        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id); // panics here
+        contract.update(
+            stream_id,
+            Option::Some(U64::from(start + 12)),
+            Option::Some(U64::from(start + 14)),
+            Option::Some(U128::from(70 * NEAR)), // Rate = 70 NEAR with balance of just 10 Near (should fail)
+        );
     }
 
     #[test]
-    #[should_panic(expected = "Already withdrawn")]
-    fn test_sender_tries_multiple_withdraw() {
-        // 1. create_stream contract
+    fn test_update_stream() {
+        // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
+        let start_time: U64 = U64::from(start + 10);
         let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
         let rate = U128::from(1 * NEAR);
         let mut contract = Contract::new();
 
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+
+        // 2. create stream and cancel
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
         let stream_id = U64::from(1);
 
-        let stream_start_time: u64 = start_time.0;
+        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, start + 1);
 
-        // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        contract.update(
+            stream_id,
+            Option::Some(U64::from(start + 12)),
+            Option::Some(U64::from(start + 14)),
+            Option::Some(U128::from(10 * NEAR)),
+        );
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
-        contract.pause(stream_id);
+        let params_key = 1;
+        let stream = contract.internal_get_stream(params_key);
+        assert!(!stream.is_paused);
+        assert_eq!(stream.id, 1);
+        assert_eq!(stream.sender, sender.clone());
+        assert_eq!(stream.receiver, accounts(1));
+        assert_eq!(stream.balance, 20 * NEAR);
+        assert_eq!(stream.rate, 10 * NEAR);
+        assert_eq!(stream.start_time, start + 12);
+        assert_eq!(stream.end_time, start + 14);
+        assert_eq!(stream.withdraw_time, start + 12);
+        assert_eq!(stream.paused_time, 0);
+        assert_eq!(stream.can_update, true);
+        assert_eq!(stream.can_cancel, false);
+    }
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 13);
-        contract.resume(stream_id);
+    #[test]
+    fn test_withdraw_cap() {
+        // 1. Create the contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(NEAR);
+        let mut contract = Contract::new();
 
-        // 3. sender call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id);
+        set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 16 * NEAR);
+        // 2. create stream and cap withdrawals at 3 NEAR per 5 seconds
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
+        let stream_id = U64::from(1);
+        contract.set_withdraw_cap(stream_id, Some(U128::from(3 * NEAR)), U64::from(5));
+
+        // 3. 4 NEAR accrued, only 3 are withdrawn
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 5);
+        contract.withdraw(stream_id, None);
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(stream.balance, 7 * NEAR);
+        assert_eq!(stream.withdraw_time, start + 4);
+
+        // 4. next period, the 3 NEAR accrued are below the cap
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 7);
+        contract.withdraw(stream_id, None);
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(stream.balance, 4 * NEAR);
+        assert_eq!(stream.withdraw_time, start + 7);
+
+        // 5. after the end the remainder takes two periods
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 13);
+        contract.withdraw(stream_id, None);
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(stream.balance, NEAR);
+        assert_eq!(stream.withdraw_time, start + 10);
+
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 18);
+        contract.withdraw(stream_id, None);
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(stream.balance, 0);
+    }
 
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id); // panics here
+    #[test]
+    #[should_panic(expected = "Withdraw cap reached for this period")]
+    fn test_withdraw_cap_reached() {
+        // 1. Create the contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let rate = U128::from(NEAR);
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+
+        // 2. create stream and cap withdrawals at 3 NEAR per 5 seconds
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
+        let stream_id = U64::from(1);
+        contract.set_withdraw_cap(stream_id, Some(U128::from(3 * NEAR)), U64::from(5));
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 16 * NEAR);
+        // 3. withdraw twice in the same period
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 5);
+        contract.withdraw(stream_id, None);
+        contract.withdraw(stream_id, None);
     }
 
     #[test]
-    fn test_withdraw_after_end_on_paused() {
-        // 1. create_stream contract
+    fn test_donate() {
+        // 1. Create the contract
         let start = env::block_timestamp();
         let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 20);
+        let end_time: U64 = U64::from(start + 10);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
-        let stream_id = U64::from(1);
-
-        let stream_start_time: u64 = start_time.0;
+        set_context_with_balance(sender.clone(), 10 * NEAR);
 
         // 2. create stream
-        set_context_with_balance_timestamp(sender.clone(), 20 * NEAR, stream_start_time);
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
+        let stream_id = U64::from(1);
 
-        // pause and resume the stream
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 9);
-        contract.pause(stream_id);
+        // 3. charlie donates 3.5 NEAR, the stream is extended by 3 seconds
+        set_context_with_balance_timestamp(accounts(2), 7 * NEAR / 2, start + 5);
+        contract.donate(stream_id);
 
-        // 3. sender call withdraw after stream has ended (action)
-        set_context_with_balance_timestamp(sender.clone(), 0, stream_start_time + 21);
-        contract.withdraw(stream_id);
+        // 4. assert
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(stream.balance, 13 * NEAR);
+        assert_eq!(stream.end_time, start + 13);
+        assert_eq!(stream.sender, sender.clone());
+    }
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 9 * NEAR);
+    #[test]
+    fn test_donate_during_ft_withdraw() {
+        let mut contract = Contract::new();
 
-        set_context_with_balance_timestamp(receiver.clone(), 0, stream_start_time + 25);
-        contract.withdraw(stream_id); // panics here
+        // alice -> bob, 1 USN per second from 0 to 10
+        set_context_with_balance("usdn.testnet".parse().unwrap(), 0);
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"0","end":"10","can_update":false,"can_cancel":true}}"#,
+            accounts(1),
+        );
+        contract.ft_on_transfer(accounts(0), U128(10), msg);
+
+        // bob withdraws 4 at 4, the stream is saved when the transfer resolves
+        set_context_with_balance_timestamp(accounts(1), 0, 4);
+        let mut stream = contract.internal_get_stream(1);
+        let (amount, cap) = contract.internal_withdraw_accrued(&mut stream);
+        contract.withdraw(U64(1), None);
+
+        // charlie donates 3 in the meantime
+        set_context_with_balance_timestamp("usdn.testnet".parse().unwrap(), 0, 4);
+        let msg = r#"{"method_name":"donate","stream_id":"1"}"#.to_string();
+        contract.ft_on_transfer(accounts(2), U128(3), msg);
+
+        set_promise_result(PromiseResult::Successful(vec![]));
+        contract.internal_resolve_ft_withdraw(
+            stream,
+            U128(amount),
+            U128(amount),
+            accounts(1),
+            cap,
+            None,
+        );
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.balance, 9);
+        assert_eq!(stream.end_time, 13);
+        assert_eq!(stream.withdraw_time, 4);
+
+        // the donation is paid out with the rest
+        set_context_with_balance_timestamp(accounts(1), 0, 13);
+        contract.withdraw(U64(1), None);
+        let mut stream = contract.internal_get_stream(1);
+        assert_eq!(contract.internal_withdraw_accrued(&mut stream).0, 9);
+    }
 
-        // 4. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 0);
+    #[test]
+    fn test_ft_cancel_unindexed_when_paid() {
+        let mut contract = Contract::new();
+
+        // alice -> bob, 1 USN per second from 0 to 10
+        set_context_with_balance("usdn.testnet".parse().unwrap(), 0);
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"1","start":"0","end":"10","can_update":false,"can_cancel":true}}"#,
+            accounts(1),
+        );
+        contract.ft_on_transfer(accounts(0), U128(10), msg);
+
+        // alice cancels at 4, bob's 4 USN are sent
+        set_context_with_balance_timestamp(accounts(0), 0, 4);
+        contract.cancel(U64(1), None);
+        let mut stream = contract.internal_get_stream(1);
+        stream.balance = 6;
+        stream.is_cancelled = true;
+        assert!(contract.stream_ends.get(&(10, 1)).is_some());
+
+        // the stream stays indexed while the transfer fails
+        set_promise_result(PromiseResult::Failed);
+        let resolve = |contract: &mut Contract, stream: &Stream| {
+            contract.internal_resolve_ft_withdraw(
+                stream.clone(),
+                U128(4),
+                U128(4),
+                accounts(1),
+                None,
+                None,
+            )
+        };
+        assert!(!resolve(&mut contract, &stream));
+        assert!(contract.stream_ends.get(&(10, 1)).is_some());
+        assert!(!contract.internal_get_stream(1).is_cancelled);
+
+        set_promise_result(PromiseResult::Successful(vec![]));
+        assert!(resolve(&mut contract, &stream));
+        assert!(contract.stream_ends.get(&(10, 1)).is_none());
+        assert!(contract.internal_get_stream(1).is_cancelled);
     }
 
     #[test]
-    fn test_pause() {
+    fn test_cancel_paired_streams() {
         // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10000);
-        let sender = &accounts(0); // alice
-        let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
+        let alice = &accounts(0);
+        let bob = &accounts(1);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
-        set_context_with_balance(sender.clone(), 10000 * NEAR);
-
-        // 2. create stream
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
-        let stream_id = U64::from(1);
+        // 2. create alice -> bob and bob -> alice and pair them
+        set_context_with_balance(alice.clone(), 10 * NEAR);
+        contract.create_stream(bob.clone(), rate, start_time, end_time, true, false, None);
+        set_context_with_balance(bob.clone(), 10 * NEAR);
+        contract.create_stream(alice.clone(), rate, start_time, end_time, true, false, None);
+
+        assert!(!contract.pair_streams(U64(2), U64(1)));
+        assert_eq!(contract.get_paired_stream(U64(2)), None);
+        set_context_with_balance(alice.clone(), 0);
+        assert!(contract.pair_streams(U64(1), U64(2)));
+        assert_eq!(contract.get_paired_stream(U64(2)), Some(U64(1)));
+
+        // 3. alice cancels her stream, bob's is cancelled too
+        set_context_with_balance_timestamp(alice.clone(), 0, start + 5);
+        contract.cancel(U64(1), None);
+        assert!(contract.internal_get_stream(1).is_cancelled);
+        assert!(contract.internal_get_stream(2).is_cancelled);
+    }
 
-        set_context_with_balance_timestamp(sender.clone(), 0, start + 10);
-        // 3. pause
-        contract.pause(stream_id);
+    #[test]
+    fn test_withdraw_netted() {
+        // 1. Create the contract
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
+        let alice = &accounts(0);
+        let bob = &accounts(1);
+        let mut contract = Contract::new();
 
-        // 4. assert
-        require!(contract.streams.get(&stream_id.0).unwrap().is_paused);
+        // 2. alice streams 2 NEAR/s to bob, bob 1 NEAR/s to alice, paired and netted
+        set_context_with_balance(alice.clone(), 20 * NEAR);
+        contract.create_stream(
+            bob.clone(),
+            U128(2 * NEAR),
+            start_time,
+            end_time,
+            true,
+            false,
+            None,
+        );
+        set_context_with_balance(bob.clone(), 10 * NEAR);
+        contract.create_stream(
+            alice.clone(),
+            U128(NEAR),
+            start_time,
+            end_time,
+            true,
+            false,
+            None,
+        );
+        contract.pair_streams(U64(2), U64(1));
+        set_context_with_balance(alice.clone(), 0);
+        contract.pair_streams(U64(1), U64(2));
+        contract.set_netting(U64(2), true);
+        set_context_with_balance(bob.clone(), 0);
+        contract.set_netting(U64(1), true);
+
+        // 3. after 4 seconds bob is owed 8 NEAR and alice 4 NEAR
+        set_context_with_balance_timestamp(bob.clone(), 0, start + 5);
+        contract.withdraw_netted(U64(2));
+
+        // 4. bob got 4 NEAR, alice's 4 NEAR went back to her stream
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.balance, 16 * NEAR);
+        assert_eq!(stream.withdraw_time, start + 5);
+        let paired_stream = contract.internal_get_stream(2);
+        assert_eq!(paired_stream.balance, 6 * NEAR);
+        assert_eq!(paired_stream.withdraw_time, start + 5);
+        assert_eq!(contract.payments.len(), 2);
     }
 
     #[test]
-    #[should_panic(expected = "Cannot pause already paused stream")]
-    fn double_pause_panic() {
+    fn test_rate_escalation() {
         // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10000);
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
-        set_context_with_balance(sender.clone(), 10000 * NEAR);
+        set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 2. create stream and pause
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        // 2. create stream and double the rate after 5 seconds
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
         let stream_id = U64::from(1);
-        set_context_with_balance_timestamp(sender.clone(), 0, start + 10);
-        contract.pause(stream_id);
-
-        // 3. pause
-        contract.pause(stream_id);
+        set_context_with_balance(sender.clone(), 5 * NEAR);
+        contract.set_rate_escalation(stream_id, 10_000, U64::from(5));
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 15 * NEAR);
+
+        // 3. 5 seconds at 1 NEAR and 2 at 2 NEAR
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 8);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 6 * NEAR);
+
+        // 4. the rest after the end
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 12);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 0);
     }
 
     #[test]
-    fn test_resume() {
+    fn test_rate_change() {
         // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10000);
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
-        set_context_with_balance(sender.clone(), 10000 * NEAR);
+        set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 2. create stream and pause
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        // 2. create stream, at 3 raise the rate to 2 NEAR from 6 on
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
         let stream_id = U64::from(1);
-        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
-        contract.pause(stream_id);
-
-        // 3. resume
-        set_context_with_balance_timestamp(sender.clone(), 0, start + 4);
-        contract.resume(stream_id);
-
-        // 4. assert
-        let stream = contract.streams.get(&stream_id.0).unwrap();
-        require!(!stream.is_paused);
-        assert_eq!(stream.withdraw_time, start + 3);
+        set_context_with_balance_timestamp(sender.clone(), 6 * NEAR, start + 3);
+        contract.set_rate_change(stream_id, Some(U128(2 * NEAR)), U64(start + 6));
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 15 * NEAR);
+        // the NEAR not needed for the raise is refunded
+        assert!(matches!(
+            get_created_receipts()[0].actions[0],
+            VmAction::Transfer { deposit } if deposit == NEAR
+        ));
+
+        // 3. 5 seconds at 1 NEAR and 2 at 2 NEAR
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 8);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 6 * NEAR);
+
+        // 4. lower the rate to 1 NEAR from 9 on, the 2 NEAR rate is folded in
+        set_context_with_balance_timestamp(sender.clone(), NEAR, start + 8);
+        contract.set_rate_change(stream_id, Some(rate), U64(start + 9));
+        assert_eq!(contract.internal_get_stream(stream_id.0).rate, 2 * NEAR);
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 6 * NEAR);
+        assert!(matches!(
+            get_created_receipts()[0].actions[0],
+            VmAction::Transfer { deposit } if deposit == NEAR
+        ));
+
+        // 5. bob gets 2 + 2 NEAR, alice the 2 NEAR left after the end
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 12);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 2 * NEAR);
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 12);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.internal_get_stream(stream_id.0).balance, 0);
     }
 
     #[test]
-    #[should_panic(expected = "Stream cannot be cancelled")]
-    fn test_cancel_with_no_cancel() {
+    #[should_panic(expected = "The amount provided is not enough for the stream")]
+    fn test_rate_escalation_underfunded() {
         // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10000);
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
-        set_context_with_balance(sender.clone(), 10000 * NEAR);
+        set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 2. create stream and pause
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
-        let stream_id = U64::from(1);
-        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
-        contract.cancel(stream_id);
+        // 2. create stream and raise the rate by 1% every second without a deposit
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            true,
+            None,
+        );
+        set_context_with_balance(sender.clone(), 0);
+        contract.set_rate_escalation(U64::from(1), 100, U64::from(1));
     }
 
     #[test]
-    fn test_cancel() {
-        // 1. Create the contract
+    fn test_withdraw_batch() {
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start);
-        let end_time: U64 = U64::from(start + 10);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
+        // 1. alice and charlie stream to bob
         set_context_with_balance(sender.clone(), 10 * NEAR);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            U64(start + 1),
+            U64(start + 11),
+            true,
+            true,
+            None,
+        );
+        set_context_with_balance(accounts(2), 10 * NEAR);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            U64(start + 1),
+            U64(start + 11),
+            true,
+            true,
+            None,
+        );
 
-        // 2. create stream and cancel
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, true, false);
+        // 2. bob withdraws both with a single transfer
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 5);
+        contract.withdraw_batch(vec![U64(1), U64(2), U64(1)]);
+        // the transfer and its escrow callback
+        assert_eq!(near_sdk::test_utils::get_created_receipts().len(), 2);
+        assert_eq!(contract.internal_get_stream(1).balance, 6 * NEAR);
+        assert_eq!(contract.internal_get_stream(2).balance, 6 * NEAR);
+        assert_eq!(
+            contract
+                .get_payments(receiver.clone(), U64(0), U64(100), None, None)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_stop_reasons() {
+        let start = env::block_timestamp();
+        let sender = &accounts(0); // alice
+        let rate = U128::from(NEAR);
+        let mut contract = Contract::new();
+
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start + 1),
+            U64(start + 11),
+            true,
+            true,
+            None,
+        );
         let stream_id = U64::from(1);
-        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
-        contract.cancel(stream_id);
 
-        // 3. assert internal balance
-        let internal_balance = contract.streams.get(&stream_id.0).unwrap().balance;
-        assert_eq!(internal_balance, 0);
+        set_context_with_balance_timestamp(sender.clone(), NEAR, start + 3);
+        contract.pause(stream_id, Some("Contract under review".to_string()));
+        assert_eq!(
+            contract.get_stop_reason(stream_id),
+            Some("Contract under review".to_string())
+        );
+
+        contract.resume(stream_id);
+        contract.cancel(stream_id, Some("Contract ended".to_string()));
+        assert_eq!(
+            contract.get_stop_reason(stream_id),
+            Some("Contract ended".to_string())
+        );
     }
 
     #[test]
-    #[should_panic(expected = "You are not authorized to update this stream")]
-    fn test_update_unauthorized() {
-        // 1. Create the contract
+    #[should_panic(expected = "Reason is too long")]
+    fn test_stop_reason_too_long() {
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start + 10);
-        let end_time: U64 = U64::from(start + 20);
         let sender = &accounts(0); // alice
-        let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
         set_context_with_balance(sender.clone(), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start + 1),
+            U64(start + 11),
+            true,
+            true,
+            None,
+        );
 
-        // 2. create stream and cancel
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, true);
-        let stream_id = U64::from(1);
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 3);
+        contract.pause(U64(1), Some("x".repeat(MAX_REASON_LEN + 1)));
+    }
 
-        set_context_with_balance_timestamp(receiver.clone(), 0, start + 11);
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the reason storage")]
+    fn test_stop_reason_storage() {
+        let start = env::block_timestamp();
+        let sender = &accounts(0); // alice
+        let rate = U128::from(NEAR);
+        let mut contract = Contract::new();
 
-        contract.update(
-            stream_id,
-            Option::Some(U64::from(start + 12)),
-            Option::Some(U64::from(start + 14)),
-            Option::Some(U128::from(2 * NEAR)),
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start + 1),
+            U64(start + 11),
+            true,
+            true,
+            None,
         );
-    }
 
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 3);
+        contract.cancel(U64(1), Some("Contract ended".to_string()));
+    }
 
     #[test]
-    #[should_panic(expected = "Cannot update: stream already started")]
-    fn test_update_after_stream_start() {
+    fn test_withholding() {
         // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start + 10);
-        let end_time: U64 = U64::from(start + 20);
+        let start_time: U64 = U64::from(start + 1);
+        let end_time: U64 = U64::from(start + 11);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let tax = &accounts(2); // charlie
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
         set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 2. create stream and cancel
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, true);
+        // 2. create stream and withhold 20% for charlie
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            true,
+            true,
+            None,
+        );
         let stream_id = U64::from(1);
+        contract.set_withholding(stream_id, Some(tax.clone()), 2_000);
+
+        // 3. bob withdraws 4 NEAR, 0.8 are withheld
+        set_context_with_balance_timestamp(receiver.clone(), 0, start + 5);
+        contract.withdraw(stream_id, None);
+        assert_eq!(
+            contract.get_withheld_balance(tax.clone(), None),
+            U128(4 * NEAR / 5)
+        );
 
-        set_context_with_balance_timestamp(sender.clone(), 0, start + 11);
-
-        contract.update(
-            stream_id,
-            Option::Some(U64::from(start + 12)),
-            Option::Some(U64::from(start + 14)),
-            Option::Some(U128::from(2 * NEAR)),
+        // 4. alice cancels, 0.4 more of bob's 2 NEAR are withheld
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 7);
+        contract.cancel(stream_id, None);
+        assert_eq!(
+            contract.get_withheld_balance(tax.clone(), None),
+            U128(6 * NEAR / 5)
         );
+
+        // 5. charlie claims
+        set_context_with_balance(tax.clone(), 0);
+        contract.claim_withheld(None);
+        assert_eq!(contract.get_withheld_balance(tax.clone(), None), U128(0));
     }
 
     #[test]
-    #[should_panic(expected = "The amount provided is not enough for the stream")]
-    fn test_update_stream_insufficient_balance_1() {
+    fn test_cancel_forfeits_bond() {
         // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start + 10);
-        let end_time: U64 = U64::from(start + 20);
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
         set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 2. create stream and cancel
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, true);
+        // 2. create stream and post a bond committing to 5 seconds
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            true,
+            false,
+            None,
+        );
         let stream_id = U64::from(1);
+        set_context_with_balance(sender.clone(), 2 * NEAR);
+        contract.post_bond(stream_id, U64::from(start + 5));
+        assert_eq!(contract.bonds.get(&stream_id.0).unwrap().amount, 2 * NEAR);
 
+        // 3. cancel early, the bond is paid out with the stream
         set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
+        contract.cancel(stream_id, None);
+        assert!(contract.bonds.get(&stream_id.0).is_none());
+    }
 
-        contract.update(
-            stream_id,
-            Option::Some(U64::from(start + 12)),
-            Option::Some(U64::from(start + 14)),
-            Option::Some(U128::from(70 * NEAR)), // Rate = 70 NEAR with balance of just 10 Near (should fail)
+    #[test]
+    fn test_cancel_before_start_refunds_bond() {
+        let start = env::block_timestamp();
+        let sender = &accounts(0); // alice
+        let receiver = &accounts(1); // bob
+        let mut contract = Contract::new();
+
+        // 1. create a stream starting in 5 seconds with a bond committing to 8
+        set_context_with_balance(sender.clone(), 10 * NEAR);
+        contract.create_stream(
+            receiver.clone(),
+            U128::from(NEAR),
+            U64::from(start + 5),
+            U64::from(start + 15),
+            true,
+            false,
+            None,
         );
+        let stream_id = U64::from(1);
+        set_context_with_balance(sender.clone(), 2 * NEAR);
+        contract.post_bond(stream_id, U64::from(start + 8));
+
+        // 2. cancel before the start, the bond goes back to alice with the balance
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 1);
+        contract.cancel(stream_id, None);
+        assert!(contract.bonds.get(&stream_id.0).is_none());
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert!(receipts
+            .iter()
+            .all(|receipt| receipt.receiver_id == *sender));
     }
 
     #[test]
-    fn test_update_stream() {
+    #[should_panic(expected = "Cannot claim the bond before the stream has ended")]
+    fn test_claim_bond_before_end() {
         // 1. Create the contract
         let start = env::block_timestamp();
-        let start_time: U64 = U64::from(start + 10);
-        let end_time: U64 = U64::from(start + 20);
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10);
         let sender = &accounts(0); // alice
         let receiver = &accounts(1); // bob
-        let rate = U128::from(1 * NEAR);
+        let rate = U128::from(NEAR);
         let mut contract = Contract::new();
 
         set_context_with_balance(sender.clone(), 10 * NEAR);
 
-        // 2. create stream and cancel
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, true);
-        let stream_id = U64::from(1);
-
-        set_context_with_balance_timestamp(sender.clone(), 10 * NEAR, start + 1);
-
-        contract.update(
-            stream_id,
-            Option::Some(U64::from(start + 12)),
-            Option::Some(U64::from(start + 14)),
-            Option::Some(U128::from(10 * NEAR)),
+        // 2. create stream and post a bond committing to 5 seconds
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            true,
+            false,
+            None,
         );
+        let stream_id = U64::from(1);
+        set_context_with_balance(sender.clone(), 2 * NEAR);
+        contract.post_bond(stream_id, U64::from(start + 5));
 
-        let params_key = 1;
-        let stream = contract.streams.get(&params_key).unwrap();
-        assert!(!stream.is_paused);
-        assert_eq!(stream.id, 1);
-        assert_eq!(stream.sender, sender.clone());
-        assert_eq!(stream.receiver, accounts(1));
-        assert_eq!(stream.balance, 20 * NEAR);
-        assert_eq!(stream.rate, 10 * NEAR);
-        assert_eq!(stream.start_time, start + 12);
-        assert_eq!(stream.end_time, start + 14);
-        assert_eq!(stream.withdraw_time, start + 12);
-        assert_eq!(stream.paused_time, 0);
-        assert_eq!(stream.can_update, true);
-        assert_eq!(stream.can_cancel, false);
+        // 3. the commitment is over, but the stream is still running
+        set_context_with_balance_timestamp(sender.clone(), 0, start + 6);
+        contract.claim_bond(stream_id);
     }
 
     // fn set_context(predecessor: AccountId) {
@@ -1333,4 +4411,17 @@ mod tests {
         builder.block_timestamp(ts * 1e9 as u64);
         testing_env!(builder.build());
     }
+
+    // the context of a callback with the result of its promise
+    fn set_promise_result(result: PromiseResult) {
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(env::current_account_id())
+                .build(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+    }
 }
@@ -0,0 +1,325 @@
+use crate::*;
+
+pub const MAX_MATCH_BPS: u32 = 100_000; // at most 10x the donation
+
+/// Funds of a sponsor that match donations to the designated streams at
+/// `ratio_bps` (10_000 matches 1:1) until the balance runs out.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchingPool {
+    sponsor: AccountId,
+    token: Option<AccountId>, // None for native NEAR
+    balance: Balance,
+    ratio_bps: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Escrows the attached deposit to match NEAR donations to `stream_ids`,
+    // less the storage of the pool which is paid from it
+    #[payable]
+    pub fn create_matching_pool(&mut self, stream_ids: Vec<U64>, ratio_bps: u32) -> U64 {
+        let initial_storage = env::storage_usage();
+        let pool_id = self.internal_create_matching_pool(
+            env::predecessor_account_id(),
+            None,
+            stream_ids,
+            ratio_bps,
+        );
+
+        let cost = Balance::from(env::storage_usage().saturating_sub(initial_storage))
+            * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() > cost,
+            "The amount provided is not enough for the pool storage"
+        );
+        let mut pool = self.matching_pools.get(pool_id).unwrap();
+        pool.balance = env::attached_deposit() - cost;
+        self.matching_pools.replace(pool_id, &pool);
+        U64(pool_id)
+    }
+
+    // Creates an empty pool to match donations in `token` to `stream_ids`, the
+    // sponsor funds it with `ft_transfer_call` and the msg
+    // `{"method_name":"fund_matching_pool","pool_id":"<id>"}`. The storage is
+    // paid from the attached deposit and the rest is refunded.
+    #[payable]
+    pub fn create_ft_matching_pool(
+        &mut self,
+        token: AccountId,
+        stream_ids: Vec<U64>,
+        ratio_bps: u32,
+    ) -> U64 {
+        let sponsor = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        let pool_id =
+            self.internal_create_matching_pool(sponsor.clone(), Some(token), stream_ids, ratio_bps);
+
+        let cost = Balance::from(env::storage_usage().saturating_sub(initial_storage))
+            * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() >= cost,
+            "The amount provided is not enough for the pool storage"
+        );
+        if env::attached_deposit() > cost {
+            Promise::new(sponsor).transfer(env::attached_deposit() - cost);
+        }
+        U64(pool_id)
+    }
+
+    // returns what is left in the pool to the sponsor, matching stops
+    pub fn close_matching_pool(&mut self, pool_id: U64) -> PromiseOrValue<bool> {
+        let mut pool = self
+            .matching_pools
+            .get(pool_id.0)
+            .expect("Pool does not exist");
+        require!(
+            env::predecessor_account_id() == pool.sponsor,
+            "Only the sponsor can close the pool"
+        );
+        require!(pool.balance > 0, "Pool is empty");
+
+        let amount = pool.balance;
+        pool.balance = 0;
+        self.matching_pools.replace(pool_id.0, &pool);
+        log_event!("Matching pool closed: {}", pool_id.0);
+
+        match pool.token {
+            None => Promise::new(pool.sponsor).transfer(amount).into(),
+            Some(token) => ext_ft_transfer::ext(token)
+                .with_attached_deposit(1)
+                .ft_transfer(pool.sponsor, amount.into(), None)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .internal_resolve_pool_close(pool_id, amount.into()),
+                )
+                .into(),
+        }
+    }
+
+    // puts the amount back in the pool if the transfer to the sponsor failed
+    #[private]
+    pub fn internal_resolve_pool_close(&mut self, pool_id: U64, amount: U128) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !res {
+            let mut pool = self.matching_pools.get(pool_id.0).unwrap();
+            pool.balance += amount.0;
+            self.matching_pools.replace(pool_id.0, &pool);
+        }
+        res
+    }
+}
+
+impl Contract {
+    /// Creates an empty pool for the streams, the balance is added by the caller.
+    fn internal_create_matching_pool(
+        &mut self,
+        sponsor: AccountId,
+        token: Option<AccountId>,
+        stream_ids: Vec<U64>,
+        ratio_bps: u32,
+    ) -> u64 {
+        require!(
+            ratio_bps > 0 && ratio_bps <= MAX_MATCH_BPS,
+            "Invalid matching ratio"
+        );
+        require!(!stream_ids.is_empty(), "No streams to match");
+
+        let pool_id = self.matching_pools.len();
+        for stream_id in stream_ids {
+            let stream = self.internal_get_stream(stream_id.0);
+            require!(
+                stream.is_native == token.is_none()
+                    && (stream.is_native || Some(&stream.contract_id) == token.as_ref()),
+                "Token does not match the stream"
+            );
+            require!(
+                self.stream_matching_pools.get(&stream_id.0).is_none(),
+                "Stream is already matched"
+            );
+            require!(
+                self.rate_escalations.get(&stream_id.0).is_none(),
+                "Cannot match a stream with rate escalation"
+            );
+            require!(
+                self.rate_changes.get(&stream_id.0).is_none(),
+                "Cannot match a stream with a rate change"
+            );
+            self.stream_matching_pools.insert(&stream_id.0, &pool_id);
+        }
+
+        self.matching_pools.push(&MatchingPool {
+            sponsor,
+            token,
+            balance: 0,
+            ratio_bps,
+        });
+        log_event!("Matching pool created: {}", pool_id);
+        pool_id
+    }
+
+    /// Adds `amount` of `token` from the sponsor to an ft pool.
+    pub(crate) fn internal_fund_matching_pool(
+        &mut self,
+        pool_id: u64,
+        sponsor: AccountId,
+        token: AccountId,
+        amount: Balance,
+    ) {
+        let mut pool = self
+            .matching_pools
+            .get(pool_id)
+            .expect("Pool does not exist");
+        require!(
+            sponsor == pool.sponsor,
+            "Only the sponsor can fund the pool"
+        );
+        require!(pool.token == Some(token), "Token does not match the pool");
+
+        pool.balance += amount;
+        self.matching_pools.replace(pool_id, &pool);
+        log_event!("Matching pool funded: {} {}", pool_id, amount);
+    }
+
+    /// Matches a donation of `donated` to the stream from its pool, in whole
+    /// seconds of the stream. Returns the amount to add to the stream.
+    pub(crate) fn internal_match_donation(&mut self, stream: &Stream, donated: Balance) -> Balance {
+        let pool_id = match self.stream_matching_pools.get(&stream.id) {
+            Some(pool_id) => pool_id,
+            None => return 0,
+        };
+        let mut pool = self.matching_pools.get(pool_id).unwrap();
+
+        let matched = donated * u128::from(pool.ratio_bps) / 10_000;
+        let matched = matched.min(pool.balance) / stream.rate * stream.rate;
+        if matched == 0 {
+            return 0;
+        }
+
+        pool.balance -= matched;
+        self.matching_pools.replace(pool_id, &pool);
+        log_event!("Donation matched: {} {} {}", stream.id, pool_id, matched);
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn match_donations_until_exhausted() {
+        let mut contract = Contract::new();
+
+        // alice -> bob for 10 seconds
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(accounts(1), U128(NEAR), U64(0), U64(10), false, false, None);
+
+        // charlie matches donations 2:1 with 6 NEAR, less the pool storage
+        set_context_with_balance(accounts(2), 6 * NEAR);
+        let pool_id = contract.create_matching_pool(vec![U64(1)], 20_000);
+        let storage_cost = 6 * NEAR - contract.get_matching_pool(pool_id).unwrap().balance;
+        assert!(storage_cost > 0);
+
+        // dave donates 2 NEAR, 4 NEAR are matched
+        set_context_with_balance(accounts(3), 2 * NEAR);
+        contract.donate(U64(1));
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.end_time, 16);
+        assert_eq!(stream.balance, 16 * NEAR);
+
+        // only 1 NEAR is left to match in whole seconds
+        contract.donate(U64(1));
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.end_time, 19);
+        assert_eq!(
+            contract.get_matching_pool(pool_id).unwrap().balance,
+            NEAR - storage_cost
+        );
+    }
+
+    #[test]
+    fn fund_ft_pool() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 0);
+        contract.ft_create_stream(
+            U128(1),
+            U64(0),
+            U64(10),
+            accounts(0),
+            U128(10),
+            accounts(1),
+            "usdn.testnet".parse().unwrap(),
+            false,
+            false,
+            None,
+            0,
+            None,
+        );
+
+        set_context_with_balance(accounts(2), NEAR);
+        let pool_id =
+            contract.create_ft_matching_pool("usdn.testnet".parse().unwrap(), vec![U64(1)], 10_000);
+        assert_eq!(contract.get_matching_pool(pool_id).unwrap().balance, 0);
+
+        contract.internal_fund_matching_pool(
+            pool_id.0,
+            accounts(2),
+            "usdn.testnet".parse().unwrap(),
+            5,
+        );
+        assert_eq!(contract.get_matching_pool(pool_id).unwrap().balance, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the pool storage")]
+    fn ft_pool_needs_storage_deposit() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 0);
+        contract.ft_create_stream(
+            U128(1),
+            U64(0),
+            U64(10),
+            accounts(0),
+            U128(10),
+            accounts(1),
+            "usdn.testnet".parse().unwrap(),
+            false,
+            false,
+            None,
+            0,
+            None,
+        );
+        contract.create_ft_matching_pool("usdn.testnet".parse().unwrap(), vec![U64(1)], 10_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Stream is already matched")]
+    fn one_pool_per_stream() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(accounts(1), U128(NEAR), U64(0), U64(10), false, false, None);
+
+        set_context_with_balance(accounts(2), 5 * NEAR);
+        contract.create_matching_pool(vec![U64(1)], 10_000);
+        contract.create_matching_pool(vec![U64(1)], 10_000);
+    }
+}
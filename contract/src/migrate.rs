@@ -0,0 +1,144 @@
+use crate::*;
+use near_sdk::collections::UnorderedMap;
+
+// contract state as deployed before the stream indexes were introduced
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldContract {
+    current_id: u64,
+    streams: UnorderedMap<u64, Stream>,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Moves the streams from the old `UnorderedMap` into the `LookupMap` and
+    // builds the global, sender and receiver indexes
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let mut old_state: OldContract = env::state_read().expect("Old state doesn't exist");
+
+        let mut contract = Self {
+            current_id: old_state.current_id,
+            streams: LookupMap::new(StorageKey::Streams),
+            stream_states: LookupMap::new(StorageKey::StreamStates),
+            stream_ids: UnorderedSet::new(StorageKey::StreamIds),
+            sender_streams: LookupMap::new(StorageKey::SenderStreams),
+            receiver_streams: LookupMap::new(StorageKey::ReceiverStreams),
+            payments: Vector::new(StorageKey::Payments),
+            account_payments: LookupMap::new(StorageKey::AccountPayments),
+            withdraw_caps: LookupMap::new(StorageKey::WithdrawCaps),
+            bonds: LookupMap::new(StorageKey::Bonds),
+            stream_ends: TreeMap::new(StorageKey::StreamEnds),
+            monthly_totals: LookupMap::new(StorageKey::MonthlyTotals),
+            matching_pools: Vector::new(StorageKey::MatchingPools),
+            stream_matching_pools: LookupMap::new(StorageKey::StreamMatchingPools),
+            pair_proposals: LookupMap::new(StorageKey::PairProposals),
+            stream_pairs: LookupMap::new(StorageKey::StreamPairs),
+            netting_streams: LookupSet::new(StorageKey::NettingStreams),
+            rate_escalations: LookupMap::new(StorageKey::RateEscalations),
+            withholdings: LookupMap::new(StorageKey::Withholdings),
+            withheld_balances: LookupMap::new(StorageKey::WithheldBalances),
+            bundles: Vector::new(StorageKey::Bundles),
+            accounts: UnorderedSet::new(StorageKey::Accounts),
+            subscriptions: LookupMap::new(StorageKey::Subscriptions),
+            approvals: LookupSet::new(StorageKey::Approvals),
+            backups: LookupMap::new(StorageKey::Backups),
+            rate_changes: LookupMap::new(StorageKey::RateChanges),
+            stop_reasons: LookupMap::new(StorageKey::StopReasons),
+            payout_intervals: LookupMap::new(StorageKey::PayoutIntervals),
+            pending_payouts: LookupMap::new(StorageKey::PendingPayouts),
+            withdraw_callbacks: LookupSet::new(StorageKey::WithdrawCallbacks),
+            sender_filters: LookupMap::new(StorageKey::SenderFilters),
+            filtered_senders: LookupSet::new(StorageKey::FilteredSenders),
+            renewal_windows: LookupMap::new(StorageKey::RenewalWindows),
+            ending_soon: UnorderedSet::new(StorageKey::EndingSoon),
+            receiver_statements: LookupMap::new(StorageKey::ReceiverStatements),
+            pending_updates: LookupMap::new(StorageKey::PendingUpdates),
+            stream_notes: LookupMap::new(StorageKey::StreamNotes),
+            staked_fundings: LookupMap::new(StorageKey::StakedFundings),
+            staking_pools: LookupSet::new(StorageKey::StakingPools),
+            receiver_index_opt_outs: LookupSet::new(StorageKey::ReceiverIndexOptOuts),
+            profiles: LookupMap::new(StorageKey::Profiles),
+            stream_profiles: LookupMap::new(StorageKey::StreamProfiles),
+            profile_streams: LookupMap::new(StorageKey::ProfileStreams),
+            ft_deposits: LookupMap::new(StorageKey::FtDeposits),
+            accrual_policies: LookupMap::new(StorageKey::AccrualPolicies),
+            withdraw_nonces: LookupMap::new(StorageKey::WithdrawNonces),
+            pause_quotas: LookupMap::new(StorageKey::PauseQuotas),
+            pause_recourses: LookupMap::new(StorageKey::PauseRecourses),
+            escalated_pauses: LookupSet::new(StorageKey::EscalatedPauses),
+            child_streams: LookupMap::new(StorageKey::ChildStreams),
+            parent_streams: LookupMap::new(StorageKey::ParentStreams),
+            pause_totals: LookupMap::new(StorageKey::PauseTotals),
+            payout_escrows: LookupMap::new(StorageKey::PayoutEscrows),
+            payout_failures: LookupMap::new(StorageKey::PayoutFailures),
+            accrual_periods: LookupMap::new(StorageKey::AccrualPeriods),
+            receivable_assignments: LookupMap::new(StorageKey::ReceivableAssignments),
+            watchlists: LookupMap::new(StorageKey::Watchlists),
+            cliff_times: LookupMap::new(StorageKey::CliffTimes),
+            stream_remainders: LookupMap::new(StorageKey::StreamRemainders),
+        };
+
+        for (_, stream) in old_state.streams.iter() {
+            contract.internal_add_stream(&stream);
+        }
+
+        // remove the old records and key vector
+        old_state.streams.clear();
+
+        log_event!("Migrated {} streams", contract.stream_ids.len());
+        contract
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::accounts;
+
+    #[test]
+    fn migrate_from_unordered_map() {
+        let mut streams: UnorderedMap<u64, Stream> = UnorderedMap::new(b"p");
+        for id in 1..3 {
+            streams.insert(
+                &id,
+                &Stream {
+                    id,
+                    sender: accounts(0),
+                    receiver: accounts(id as usize),
+                    balance: 10,
+                    rate: 1,
+                    created: 0,
+                    start_time: 0,
+                    end_time: 10,
+                    withdraw_time: 0,
+                    is_paused: false,
+                    is_cancelled: false,
+                    paused_time: 0,
+                    contract_id: "near.testnet".parse().unwrap(),
+                    can_update: false,
+                    can_cancel: false,
+                    is_native: true,
+                },
+            );
+        }
+        env::state_write(&OldContract {
+            current_id: 3,
+            streams,
+        });
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.current_id, 3);
+        assert_eq!(contract.stream_ids.to_vec(), vec![1, 2]);
+        assert_eq!(contract.internal_get_stream(2).receiver, accounts(2));
+        assert_eq!(contract.sender_streams.get(&accounts(0)).unwrap().len(), 2);
+        assert_eq!(
+            contract.receiver_streams.get(&accounts(1)).unwrap().len(),
+            1
+        );
+        // the old value vector is gone
+        let mut old_value_key = b"pv".to_vec();
+        old_value_key.extend_from_slice(&0u64.to_le_bytes());
+        assert!(!env::storage_has_key(&old_value_key));
+    }
+}
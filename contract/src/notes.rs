@@ -0,0 +1,114 @@
+use crate::*;
+
+pub const MAX_NOTE_LEN: usize = 256;
+
+#[near_bindgen]
+impl Contract {
+    // Sets the caller's note on the stream, `None` removes it. The storage is
+    // paid from the attached deposit and the rest, or the storage freed, is
+    // refunded to the caller.
+    #[payable]
+    pub fn set_stream_note(&mut self, stream_id: U64, note: Option<String>) {
+        let stream = self.internal_get_stream(stream_id.0);
+        let account_id = env::predecessor_account_id();
+        require!(
+            account_id == stream.sender || account_id == stream.receiver,
+            "Only the sender or the receiver can set a note"
+        );
+
+        let key = (stream_id.0, account_id.clone());
+        let initial_storage = env::storage_usage();
+        match note {
+            Some(note) => {
+                require!(
+                    !note.is_empty() && note.len() <= MAX_NOTE_LEN,
+                    "Invalid note"
+                );
+                self.stream_notes.insert(&key, &note);
+                log_event!("Stream note set: {} {}", stream_id.0, account_id);
+            }
+            None => {
+                self.stream_notes.remove(&key);
+                log_event!("Stream note removed: {} {}", stream_id.0, account_id);
+            }
+        }
+
+        let final_storage = env::storage_usage();
+        let refund = if final_storage > initial_storage {
+            let cost = Balance::from(final_storage - initial_storage) * env::storage_byte_cost();
+            require!(
+                env::attached_deposit() >= cost,
+                "The amount provided is not enough for the note storage"
+            );
+            env::attached_deposit() - cost
+        } else {
+            env::attached_deposit()
+                + Balance::from(initial_storage - final_storage) * env::storage_byte_cost()
+        };
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+    }
+
+    pub fn get_stream_note(&self, stream_id: U64, account_id: AccountId) -> Option<String> {
+        self.stream_notes.get(&(stream_id.0, account_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn notes_per_party() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+
+        // both parties attach a note, the unused deposit is refunded
+        set_context_with_balance(accounts(0), NEAR);
+        contract.set_stream_note(U64(1), Some("invoice 42".to_string()));
+        assert_eq!(get_created_receipts().len(), 1);
+        set_context_with_balance(accounts(1), NEAR);
+        contract.set_stream_note(U64(1), Some("disputed".to_string()));
+
+        assert_eq!(
+            contract.get_stream_note(U64(1), accounts(0)),
+            Some("invoice 42".to_string())
+        );
+        assert_eq!(
+            contract.get_stream_note(U64(1), accounts(1)),
+            Some("disputed".to_string())
+        );
+
+        // removing a note refunds its storage
+        set_context_with_balance(accounts(1), 0);
+        contract.set_stream_note(U64(1), None);
+        assert_eq!(contract.get_stream_note(U64(1), accounts(1)), None);
+        assert_eq!(get_created_receipts().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the note storage")]
+    fn note_storage_not_paid() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+
+        set_context_with_balance(accounts(0), 0);
+        contract.set_stream_note(U64(1), Some("invoice 42".to_string()));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::*;
+
+pub const PAUSE_GRACE_PERIOD: u64 = 604_800; // 7 days
+
+/// Limit on how many times a stream can be paused, fixed once it starts.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseQuota {
+    max: u32,
+    used: u32,
+}
+
+/// What happens when the receiver escalates a pause, see `escalate_pause`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum PauseAction {
+    Resume,
+    Cancel,
+}
+
+/// Recourse of the receiver against a long pause, fixed once the stream starts.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseRecourse {
+    grace_period: u32,
+    action: Option<PauseAction>,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Limits the pauses of the stream to `max_pauses`, `None` removes the
+    // limit. Only before the stream starts, so the receiver can rely on it.
+    pub fn set_pause_quota(&mut self, stream_id: U64, max_pauses: Option<u32>) {
+        let id: u64 = stream_id.0;
+        let stream = self.internal_get_stream(id);
+
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.start_time > current_time(),
+            "Cannot update: stream already started"
+        );
+
+        match max_pauses {
+            Some(max) => {
+                self.pause_quotas.insert(&id, &PauseQuota { max, used: 0 });
+                log_event!("Pause quota set: {} {}", id, max);
+            }
+            None => {
+                self.pause_quotas.remove(&id);
+                log_event!("Pause quota removed: {}", id);
+            }
+        }
+    }
+
+    // Sets how long the stream can stay paused before its receiver can
+    // escalate and what the escalation does, `None` only flags the stream.
+    // Only before the stream starts, a cancel needs a cancellable stream.
+    pub fn set_pause_recourse(
+        &mut self,
+        stream_id: U64,
+        grace_period: U64,
+        action: Option<PauseAction>,
+    ) {
+        let id: u64 = stream_id.0;
+        let stream = self.internal_get_stream(id);
+
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.start_time > current_time(),
+            "Cannot update: stream already started"
+        );
+        require!(
+            action != Some(PauseAction::Cancel) || stream.can_cancel,
+            "Stream cannot be cancelled"
+        );
+
+        self.pause_recourses.insert(
+            &id,
+            &PauseRecourse {
+                grace_period: compact_timestamp(grace_period.0),
+                action,
+            },
+        );
+        log_event!("Pause recourse set: {} {}", id, grace_period.0);
+    }
+
+    // Lets the receiver of a stream paused longer than its grace period flag
+    // it, with the `pause_escalated` event, then resumes or cancels it if the
+    // sender set that up with `set_pause_recourse`
+    pub fn escalate_pause(&mut self, stream_id: U64) -> PromiseOrValue<bool> {
+        let id: u64 = stream_id.0;
+        let stream = self.internal_get_stream(id);
+
+        require!(
+            env::predecessor_account_id() == stream.receiver,
+            "Only the receiver can escalate a pause"
+        );
+        require!(!stream.is_cancelled, "Stream is cancelled");
+        require!(stream.is_paused, "Stream is not paused");
+        require!(
+            !self.escalated_pauses.contains(&id),
+            "Pause already escalated"
+        );
+
+        let recourse = self.pause_recourses.get(&id);
+        let grace_period = recourse
+            .as_ref()
+            .map_or(PAUSE_GRACE_PERIOD, |recourse| recourse.grace_period.into());
+        require!(
+            current_time() >= stream.paused_time + grace_period,
+            "Grace period has not passed"
+        );
+
+        self.escalated_pauses.insert(&id);
+        log_event!("Pause escalated: {}", id);
+        self.internal_notify(&stream, StreamEvent::PauseEscalated);
+
+        match recourse.and_then(|recourse| recourse.action) {
+            Some(PauseAction::Resume) => {
+                self.internal_resume(stream);
+                PromiseOrValue::Value(true)
+            }
+            Some(PauseAction::Cancel) => self.internal_cancel_with_pair(stream, &None),
+            None => PromiseOrValue::Value(true),
+        }
+    }
+
+    pub fn get_pause_recourse(&self, stream_id: U64) -> Option<PauseRecourse> {
+        self.pause_recourses.get(&stream_id.0)
+    }
+
+    pub fn is_pause_escalated(&self, stream_id: U64) -> bool {
+        self.escalated_pauses.contains(&stream_id.0)
+    }
+
+    // pauses left on the stream, None if it has no quota
+    pub fn get_remaining_pauses(&self, stream_id: U64) -> Option<u32> {
+        self.pause_quotas
+            .get(&stream_id.0)
+            .map(|quota| quota.max - quota.used)
+    }
+
+    // The end time moved by every second the stream has been paused, a running
+    // pause up to now. A pause doesn't extend the stream, this is when the
+    // receiver would have been paid in full had it been extended.
+    pub fn get_projected_end_time(&self, stream_id: U64) -> U64 {
+        let stream = self.internal_get_stream(stream_id.0);
+        let mut paused_seconds = self.pause_totals.get(&stream.id).unwrap_or(0);
+        if stream.is_paused {
+            // same as `resume`, a pause stops counting at the end time
+            paused_seconds += current_time().min(stream.end_time) - stream.paused_time;
+        }
+        U64(stream.end_time + paused_seconds)
+    }
+}
+
+impl Contract {
+    /// Adds the seconds of an ended pause to the stream's total.
+    pub(crate) fn internal_add_pause_time(&mut self, stream_id: u64, seconds: u64) {
+        if seconds > 0 {
+            let total = self.pause_totals.get(&stream_id).unwrap_or(0);
+            self.pause_totals.insert(&stream_id, &(total + seconds));
+        }
+    }
+
+    /// Counts a pause of the stream against its quota, if it has one.
+    pub(crate) fn internal_use_pause(&mut self, stream_id: u64) {
+        if let Some(mut quota) = self.pause_quotas.get(&stream_id) {
+            require!(quota.used < quota.max, "Pause quota exhausted");
+            quota.used += 1;
+            self.pause_quotas.insert(&stream_id, &quota);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn projected_end_time() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, false, None);
+        assert_eq!(contract.get_projected_end_time(stream_id), U64(20));
+
+        // paused from 12 to 15
+        set_context(accounts(0), 0, 12);
+        contract.pause(stream_id, None);
+        set_context(accounts(0), 0, 15);
+        contract.resume(stream_id);
+        assert_eq!(contract.get_projected_end_time(stream_id), U64(23));
+
+        // a running pause counts up to now, and stops at the end time
+        set_context(accounts(0), 0, 17);
+        contract.pause(stream_id, None);
+        set_context(accounts(0), 0, 18);
+        assert_eq!(contract.get_projected_end_time(stream_id), U64(24));
+        set_context(accounts(0), 0, 30);
+        assert_eq!(contract.get_projected_end_time(stream_id), U64(26));
+    }
+
+    #[test]
+    #[should_panic(expected = "Pause quota exhausted")]
+    fn pause_quota_exhausted() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, false, None);
+        contract.set_pause_quota(stream_id, Some(1));
+        assert_eq!(contract.get_remaining_pauses(stream_id), Some(1));
+
+        set_context(accounts(0), 0, 12);
+        contract.pause(stream_id, None);
+        assert_eq!(contract.get_remaining_pauses(stream_id), Some(0));
+        contract.resume(stream_id);
+
+        set_context(accounts(0), 0, 14);
+        contract.pause(stream_id, None);
+    }
+
+    #[test]
+    fn escalate_long_pause() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, false, None);
+        contract.set_pause_recourse(stream_id, U64(100), Some(PauseAction::Resume));
+
+        set_context(accounts(0), 0, 12);
+        contract.pause(stream_id, None);
+
+        // after the grace period the receiver gets the stream resumed
+        set_context(accounts(1), 0, 112);
+        contract.escalate_pause(stream_id);
+        let stream = contract.get_stream(stream_id);
+        assert!(!stream.is_paused);
+        assert!(!contract.is_pause_escalated(stream_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "Grace period has not passed")]
+    fn escalate_within_grace_period() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, false, None);
+
+        set_context(accounts(0), 0, 12);
+        contract.pause(stream_id, None);
+
+        set_context(accounts(1), 0, PAUSE_GRACE_PERIOD);
+        contract.escalate_pause(stream_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot update: stream already started")]
+    fn pause_quota_fixed_after_start() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, false, None);
+        contract.set_pause_quota(stream_id, Some(1));
+
+        set_context(accounts(0), 0, 12);
+        contract.set_pause_quota(stream_id, None);
+    }
+}
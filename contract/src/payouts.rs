@@ -0,0 +1,241 @@
+use crate::*;
+
+/// Withdrawals credited to an account on a payout schedule and not transferred yet.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingPayout {
+    amount: Balance,
+    last_payout: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Withdrawals to the caller are credited and only transferred once every
+    // `interval` seconds per token. `None` goes back to direct transfers, what is
+    // pending can then be paid out with `withdraw_payout`. The storage is paid
+    // from the attached deposit and the rest, or the storage freed, is refunded.
+    #[payable]
+    pub fn set_payout_schedule(&mut self, interval: Option<U64>) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        match interval {
+            Some(interval) => {
+                require!(interval.0 > 0, "Interval cannot be zero");
+                self.payout_intervals
+                    .insert(&account_id, &compact_timestamp(interval.0));
+                log_event!("Payout schedule set: {} {}", account_id, interval.0);
+            }
+            None => {
+                self.payout_intervals.remove(&account_id);
+                log_event!("Payout schedule removed: {}", account_id);
+            }
+        }
+
+        let final_storage = env::storage_usage();
+        let refund = if final_storage > initial_storage {
+            let cost = Balance::from(final_storage - initial_storage) * env::storage_byte_cost();
+            require!(
+                env::attached_deposit() >= cost,
+                "The amount provided is not enough for the schedule storage"
+            );
+            env::attached_deposit() - cost
+        } else {
+            env::attached_deposit()
+                + Balance::from(initial_storage - final_storage) * env::storage_byte_cost()
+        };
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+    }
+
+    // transfers what is pending for the caller in `token` (None for NEAR) if it is due
+    pub fn withdraw_payout(&mut self, token: Option<AccountId>) -> PromiseOrValue<bool> {
+        let account_id = env::predecessor_account_id();
+        require!(
+            self.internal_payout_due(&account_id, &token),
+            "Payout is not due yet"
+        );
+        self.internal_pay_out(account_id, token)
+            .expect("Nothing to pay out")
+            .into()
+    }
+
+    // withdrawals credited to the account and not paid out yet, token None for NEAR
+    pub fn get_pending_payout(&self, account_id: AccountId, token: Option<AccountId>) -> U128 {
+        U128(
+            self.pending_payouts
+                .get(&(account_id, token))
+                .map_or(0, |pending| pending.amount),
+        )
+    }
+
+    // credits the amount back if the transfer of the payout failed
+    #[private]
+    pub fn internal_resolve_payout(
+        &mut self,
+        account_id: AccountId,
+        token: AccountId,
+        amount: U128,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !res {
+            let key = (account_id, Some(token));
+            let mut pending = self.pending_payouts.get(&key).unwrap_or_default();
+            pending.amount += amount.0;
+            self.pending_payouts.insert(&key, &pending);
+        }
+        res
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_has_payout_schedule(&self, account_id: &AccountId) -> bool {
+        self.payout_intervals.get(account_id).is_some()
+    }
+
+    /// Credits a withdrawal to the account's pending payout and transfers
+    /// everything pending in the token if the payout is due.
+    pub(crate) fn internal_credit_payout(
+        &mut self,
+        account_id: AccountId,
+        token: Option<AccountId>,
+        amount: Balance,
+    ) -> PromiseOrValue<bool> {
+        let key = (account_id, token);
+        let mut pending = self.pending_payouts.get(&key).unwrap_or_default();
+        pending.amount += amount;
+        self.pending_payouts.insert(&key, &pending);
+        log_event!("Payout credited: {} {}", key.0, amount);
+
+        let (account_id, token) = key;
+        if !self.internal_payout_due(&account_id, &token) {
+            return PromiseOrValue::Value(true);
+        }
+        match self.internal_pay_out(account_id, token) {
+            Some(promise) => promise.into(),
+            None => PromiseOrValue::Value(true),
+        }
+    }
+
+    fn internal_payout_due(&self, account_id: &AccountId, token: &Option<AccountId>) -> bool {
+        let interval = match self.payout_intervals.get(account_id) {
+            Some(interval) => interval,
+            None => return true,
+        };
+        let last_payout = self
+            .pending_payouts
+            .get(&(account_id.clone(), token.clone()))
+            .map_or(0, |pending| pending.last_payout);
+        current_time() >= u64::from(last_payout) + u64::from(interval)
+    }
+
+    // transfers everything pending, None if there is nothing to transfer
+    fn internal_pay_out(
+        &mut self,
+        account_id: AccountId,
+        token: Option<AccountId>,
+    ) -> Option<Promise> {
+        let key = (account_id, token);
+        let mut pending = self.pending_payouts.get(&key)?;
+        if pending.amount == 0 {
+            return None;
+        }
+
+        let amount = pending.amount;
+        pending.amount = 0;
+        pending.last_payout = compact_timestamp(current_time());
+        self.pending_payouts.insert(&key, &pending);
+
+        let (account_id, token) = key;
+        log_event!("Payout: {} {}", account_id, amount);
+        Some(match token {
+            None => self.internal_native_payout(
+                Promise::new(account_id.clone()).transfer(amount),
+                &account_id,
+                amount,
+            ),
+            Some(token) => ext_ft_transfer::ext(token.clone())
+                .with_attached_deposit(1)
+                .ft_transfer(account_id.clone(), amount.into(), None)
+                .then(
+                    Self::ext(env::current_account_id()).internal_resolve_payout(
+                        account_id,
+                        token,
+                        amount.into(),
+                    ),
+                ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn accumulate_until_due() {
+        let mut contract = Contract::new();
+
+        // alice -> bob, bob is paid every 5 seconds
+        set_context(accounts(0), 10 * NEAR, 0);
+        contract.create_stream(accounts(1), U128(NEAR), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), NEAR, 0);
+        contract.set_payout_schedule(Some(U64(5)));
+
+        // the first withdrawals are only credited
+        set_context(accounts(1), 0, 2);
+        contract.withdraw(U64(1), None);
+        set_context(accounts(1), 0, 4);
+        contract.withdraw(U64(1), None);
+        assert_eq!(
+            contract.get_pending_payout(accounts(1), None),
+            U128(3 * NEAR)
+        );
+
+        // the withdrawal at 6 pays out all 5 NEAR
+        set_context(accounts(1), 0, 6);
+        contract.withdraw(U64(1), None);
+        assert_eq!(contract.get_pending_payout(accounts(1), None), U128(0));
+        assert_eq!(contract.internal_get_stream(1).balance, 5 * NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "Payout is not due yet")]
+    fn payout_not_due() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10 * NEAR, 0);
+        contract.create_stream(accounts(1), U128(NEAR), U64(1), U64(11), true, false, None);
+        set_context(accounts(1), NEAR, 0);
+        contract.set_payout_schedule(Some(U64(5)));
+
+        set_context(accounts(1), 0, 2);
+        contract.withdraw(U64(1), None);
+        contract.withdraw_payout(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the schedule storage")]
+    fn payout_schedule_needs_storage_deposit() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(1), 0, 0);
+        contract.set_payout_schedule(Some(U64(5)));
+    }
+}
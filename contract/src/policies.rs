@@ -0,0 +1,486 @@
+use crate::*;
+
+/// How a stream treats its final second and rounds its escalated rates. The
+/// default ends the stream at its end time and rounds down.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccrualPolicy {
+    pub end_inclusive: bool, // still running at its end time, ended after it
+    pub round_up: bool,      // escalated rates are rounded up
+}
+
+/// Calendar boundaries (UTC) at which a stream vests instead of every second.
+/// Weeks start on Monday.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum AccrualPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Sets the accrual policy of the stream, before it starts. A rounding that
+    // raises the total must be covered by the attached deposit.
+    #[payable]
+    pub fn set_accrual_policy(&mut self, stream_id: U64, end_inclusive: bool, round_up: bool) {
+        // convert to native u64
+        let id: u64 = stream_id.0;
+        let current_timestamp: u64 = current_time();
+
+        let mut stream = self.internal_get_stream(id);
+
+        // same conditions as `update`
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(stream.can_update, "Stream cannot be updated");
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.start_time > current_timestamp,
+            "Cannot update: stream already started"
+        );
+        require!(
+            env::attached_deposit() == 0 || stream.is_native,
+            "Deposit is only for native streams"
+        );
+
+        let policy = AccrualPolicy {
+            end_inclusive,
+            round_up,
+        };
+        if policy == AccrualPolicy::default() {
+            self.accrual_policies.remove(&id);
+        } else {
+            self.accrual_policies.insert(&id, &policy);
+        }
+
+        // calculate the balance is enough
+        let stream_amount =
+            self.internal_streamed_amount(&stream, stream.start_time, stream.end_time);
+        if stream_amount > stream.balance {
+            require!(
+                env::attached_deposit() >= stream_amount - stream.balance,
+                "The amount provided is not enough for the stream"
+            );
+        }
+        stream.balance += env::attached_deposit();
+        self.internal_save_stream_state(&stream);
+
+        log_event!("Accrual policy set: {} {} {}", id, end_inclusive, round_up);
+    }
+
+    pub fn get_accrual_policy(&self, stream_id: U64) -> AccrualPolicy {
+        self.accrual_policies.get(&stream_id.0).unwrap_or_default()
+    }
+
+    // Makes the stream vest at the start of each day, week or month instead of
+    // continuously, before it starts. `None` vests every second again.
+    pub fn set_accrual_period(&mut self, stream_id: U64, period: Option<AccrualPeriod>) {
+        let id: u64 = stream_id.0;
+        let stream = self.internal_get_stream(id);
+
+        // same conditions as `update`
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(stream.can_update, "Stream cannot be updated");
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.start_time > current_time(),
+            "Cannot update: stream already started"
+        );
+
+        match period {
+            Some(period) => {
+                self.accrual_periods.insert(&id, &period);
+                log_event!("Accrual period set: {}", id);
+            }
+            None => {
+                self.accrual_periods.remove(&id);
+                log_event!("Accrual period removed: {}", id);
+            }
+        }
+    }
+
+    pub fn get_accrual_period(&self, stream_id: U64) -> Option<AccrualPeriod> {
+        self.accrual_periods.get(&stream_id.0)
+    }
+
+    pub fn get_cliff_time(&self, stream_id: U64) -> Option<U64> {
+        self.cliff_times.get(&stream_id.0).map(U64)
+    }
+}
+
+impl Contract {
+    /// Whether the stream has ended at `timestamp` under its accrual policy.
+    pub(crate) fn internal_has_ended(&self, stream: &Stream, timestamp: Timestamp) -> bool {
+        let policy = self.accrual_policies.get(&stream.id).unwrap_or_default();
+        if policy.end_inclusive {
+            timestamp > stream.end_time
+        } else {
+            timestamp >= stream.end_time
+        }
+    }
+
+    /// Whether the sender can take back what is left of the stream at
+    /// `timestamp`: after the end second, or as the accrual policy says once
+    /// the sender set one.
+    pub(crate) fn internal_can_reclaim(&self, stream: &Stream, timestamp: Timestamp) -> bool {
+        if self.accrual_policies.get(&stream.id).is_some() {
+            self.internal_has_ended(stream, timestamp)
+        } else {
+            timestamp > stream.end_time
+        }
+    }
+
+    /// The rate after an escalation of `bps`, rounded as the policy says.
+    pub(crate) fn internal_escalated_rate(
+        &self,
+        stream_id: u64,
+        rate: Balance,
+        bps: u32,
+    ) -> Balance {
+        let policy = self.accrual_policies.get(&stream_id).unwrap_or_default();
+        let scaled = rate * u128::from(10_000 + bps);
+        if policy.round_up {
+            scaled.div_ceil(10_000)
+        } else {
+            scaled / 10_000
+        }
+    }
+
+    /// The time up to which the stream has vested at `timestamp`, its start
+    /// before the cliff, the last boundary of its accrual period before the
+    /// end, `timestamp` otherwise.
+    pub(crate) fn internal_vested_time(&self, stream: &Stream, timestamp: Timestamp) -> Timestamp {
+        vested_time(
+            stream,
+            self.cliff_times.get(&stream.id),
+            self.accrual_periods.get(&stream.id),
+            timestamp,
+        )
+    }
+
+    /// Whether the stream has a cliff after `timestamp`.
+    pub(crate) fn internal_before_cliff(&self, stream_id: u64, timestamp: Timestamp) -> bool {
+        self.cliff_times
+            .get(&stream_id)
+            .is_some_and(|cliff_time| timestamp < cliff_time)
+    }
+
+    /// Keeps the cliff of a new stream, if any.
+    pub(crate) fn internal_set_cliff(&mut self, stream: &Stream, cliff_time: Option<U64>) {
+        if let Some(cliff_time) = cliff_time {
+            self.cliff_times.insert(&stream.id, &cliff_time.0);
+            self.internal_check_cliff(stream);
+        }
+    }
+
+    /// Checks the cliff of the stream is still between its start and end times.
+    pub(crate) fn internal_check_cliff(&self, stream: &Stream) {
+        if let Some(cliff_time) = self.cliff_times.get(&stream.id) {
+            require!(
+                stream.start_time <= cliff_time && cliff_time <= stream.end_time,
+                "Cliff time must be between the start and end times"
+            );
+        }
+    }
+}
+
+/// `internal_vested_time` of a stream with the given cliff and accrual period,
+/// also used to simulate streams that do not exist.
+pub(crate) fn vested_time(
+    stream: &Stream,
+    cliff_time: Option<Timestamp>,
+    accrual_period: Option<AccrualPeriod>,
+    timestamp: Timestamp,
+) -> Timestamp {
+    if timestamp >= stream.end_time {
+        return timestamp;
+    }
+    if cliff_time.is_some_and(|cliff_time| timestamp < cliff_time) {
+        return stream.start_time;
+    }
+    match accrual_period {
+        Some(AccrualPeriod::Day) => timestamp - timestamp % 86_400,
+        // 1970-01-01 was a Thursday, three days after a Monday
+        Some(AccrualPeriod::Week) => timestamp - (timestamp + 259_200) % 604_800,
+        Some(AccrualPeriod::Month) => month_start(calendar_month(timestamp)),
+        None => timestamp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn inclusive_end() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, true, None);
+        contract.set_accrual_policy(stream_id, true, false);
+
+        // the stream can still be cancelled at its end time
+        set_context(accounts(0), 0, 20);
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert!(!contract.internal_has_ended(&stream, 20));
+        assert!(contract.internal_has_ended(&stream, 21));
+        contract.cancel(stream_id, None);
+        assert!(contract.get_stream(stream_id).is_cancelled);
+    }
+
+    #[test]
+    fn reclaim_after_end_by_default() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, true, None);
+
+        // without a policy the sender waits until after the end second
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert!(!contract.internal_can_reclaim(&stream, 20));
+        assert!(contract.internal_can_reclaim(&stream, 21));
+
+        // a policy set by the sender with an exclusive end allows it at the end second
+        set_context(accounts(0), 0, 0);
+        contract.set_accrual_policy(stream_id, false, true);
+        assert!(contract.internal_can_reclaim(&stream, 20));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot withdraw before the stream has ended")]
+    fn no_reclaim_at_end_by_default() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, true, None);
+
+        set_context(accounts(0), 0, 20);
+        contract.withdraw(stream_id, None);
+    }
+
+    #[test]
+    fn round_up_escalation() {
+        let mut contract = Contract::new();
+
+        // 3 per second from 10 to 20, then 1% more from 15
+        set_context(accounts(0), 30, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(3), U64(10), U64(20), true, true, None);
+        set_context(accounts(0), 0, 0);
+        contract.set_rate_escalation(stream_id, 100, U64(5));
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(contract.internal_streamed_amount(&stream, 10, 20), 30);
+
+        set_context(accounts(0), 5, 0);
+        contract.set_accrual_policy(stream_id, false, true);
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(contract.internal_streamed_amount(&stream, 10, 20), 35);
+        assert_eq!(stream.balance, 35);
+    }
+
+    #[test]
+    fn monthly_vesting() {
+        let mut contract = Contract::new();
+
+        // 1 per second from 2024-01-15 to 2024-03-15, vesting monthly
+        set_context(accounts(0), 5_184_000, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(1),
+            U64(1_705_276_800),
+            U64(1_710_460_800),
+            true,
+            true,
+            None,
+        );
+        contract.set_accrual_period(stream_id, Some(AccrualPeriod::Month));
+
+        // nothing vests before 2024-02-01
+        let stream = contract.internal_get_stream(stream_id.0);
+        assert_eq!(
+            contract.internal_vested_time(&stream, 1_706_659_200),
+            1_704_067_200
+        );
+
+        // 2024-02-20 only pays up to 2024-02-01
+        set_context(accounts(1), 0, 1_708_387_200);
+        contract.withdraw(stream_id, None);
+        let stream = contract.get_stream(stream_id);
+        assert_eq!(stream.withdraw_time, 1_706_745_600);
+        assert_eq!(stream.balance, 5_184_000 - 1_468_800);
+
+        // the rest vests at the end
+        set_context(accounts(1), 0, 1_710_460_800);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.get_stream(stream_id).balance, 0);
+    }
+
+    #[test]
+    fn cancel_pays_vested_days() {
+        let mut contract = Contract::new();
+
+        // 1 per second for 10 days from 2024-01-01, vesting daily
+        set_context(accounts(0), 864_000, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(1),
+            U64(1_704_067_200),
+            U64(1_704_931_200),
+            true,
+            true,
+            None,
+        );
+        contract.set_accrual_period(stream_id, Some(AccrualPeriod::Day));
+
+        // cancelled half way through the third day, the receiver gets 2 days
+        set_context(accounts(0), 0, 1_704_283_200);
+        contract.cancel(stream_id, None);
+        let statement = contract.receiver_statements.get(&(accounts(1), None));
+        assert_eq!(statement.unwrap().received, 172_800);
+    }
+
+    #[test]
+    fn cliff_unlocks_at_once() {
+        let mut contract = Contract::new();
+
+        // 1 per second from 10 to 30, cliff at 20
+        set_context(accounts(0), 20, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(1),
+            U64(10),
+            U64(30),
+            true,
+            true,
+            Some(U64(20)),
+        );
+        assert_eq!(contract.get_cliff_time(stream_id), Some(U64(20)));
+
+        // paused before the cliff, nothing is withdrawable yet
+        set_context(accounts(0), 0, 15);
+        contract.pause(stream_id, None);
+        let paused = contract.get_paused_streams(accounts(1), None, None);
+        assert_eq!(paused[0].accrued, U128(0));
+        set_context(accounts(0), 0, 17);
+        contract.resume(stream_id);
+
+        // the 8 seconds streamed unlock at the cliff
+        set_context(accounts(1), 0, 20);
+        contract.withdraw(stream_id, None);
+        assert_eq!(contract.get_stream(stream_id).balance, 12);
+
+        // a cancel before the cliff pays the receiver nothing
+        set_context(accounts(0), 20, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(1),
+            U64(10),
+            U64(30),
+            true,
+            true,
+            Some(U64(20)),
+        );
+        set_context(accounts(0), 0, 19);
+        let preview = contract.preview_cancel(stream_id);
+        assert_eq!(preview.receiver_amount, U128(0));
+        assert_eq!(preview.sender_amount, U128(20));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cliff time has not been reached")]
+    fn withdraw_before_cliff() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 20, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(1),
+            U64(10),
+            U64(30),
+            true,
+            true,
+            Some(U64(20)),
+        );
+
+        set_context(accounts(1), 0, 19);
+        contract.withdraw(stream_id, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cliff time must be between the start and end times")]
+    fn update_past_cliff() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 20, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(1),
+            U64(10),
+            U64(30),
+            true,
+            true,
+            Some(U64(20)),
+        );
+        contract.update(stream_id, Some(U64(25)), Some(U64(45)), None);
+    }
+
+    #[test]
+    fn calendar_boundaries() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 1_799_999_990, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(1),
+            U64(10),
+            U64(1_800_000_000),
+            true,
+            true,
+            None,
+        );
+        let stream = contract.internal_get_stream(stream_id.0);
+
+        // Thursday 2024-01-04 12:00 UTC
+        let timestamp = 1_704_369_600;
+        contract.set_accrual_period(stream_id, Some(AccrualPeriod::Day));
+        assert_eq!(
+            contract.internal_vested_time(&stream, timestamp),
+            1_704_326_400
+        );
+        contract.set_accrual_period(stream_id, Some(AccrualPeriod::Week));
+        assert_eq!(
+            contract.internal_vested_time(&stream, timestamp),
+            1_704_067_200
+        );
+        contract.set_accrual_period(stream_id, Some(AccrualPeriod::Month));
+        assert_eq!(
+            contract.internal_vested_time(&stream, timestamp),
+            1_704_067_200
+        );
+        contract.set_accrual_period(stream_id, None);
+        assert_eq!(contract.internal_vested_time(&stream, timestamp), timestamp);
+    }
+}
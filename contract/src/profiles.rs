@@ -0,0 +1,247 @@
+use crate::*;
+
+pub const MAX_PROFILE_NAME_LEN: usize = 32;
+pub const MAX_PROFILE_OPERATORS: usize = 10;
+
+/// Part of a sender's streams, like `hr` or `grants`, run by its own operators
+/// from a NEAR budget the sender deposited.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Profile {
+    operators: Vec<AccountId>,
+    balance: Balance, // budget left for new streams
+}
+
+#[near_bindgen]
+impl Contract {
+    // Creates or replaces the operators of the caller's profile `name`, the
+    // attached deposit is added to its budget
+    #[payable]
+    pub fn set_profile(&mut self, name: String, operators: Vec<AccountId>) {
+        require!(
+            !name.is_empty() && name.len() <= MAX_PROFILE_NAME_LEN,
+            "Invalid profile name"
+        );
+        require!(
+            operators.len() <= MAX_PROFILE_OPERATORS,
+            "Too many operators"
+        );
+
+        let key = (env::predecessor_account_id(), name);
+        let balance = self.profiles.get(&key).map_or(0, |profile| profile.balance);
+        self.profiles.insert(
+            &key,
+            &Profile {
+                operators,
+                balance: balance + env::attached_deposit(),
+            },
+        );
+        log_event!("Profile set: {} {}", key.0, key.1);
+    }
+
+    // Transfers `amount` of the budget of the caller's profile back to it
+    pub fn withdraw_profile_budget(&mut self, name: String, amount: U128) -> Promise {
+        let key = (env::predecessor_account_id(), name);
+        let mut profile = self.profiles.get(&key).expect("Profile does not exist");
+        require!(amount.0 <= profile.balance, "Not enough budget");
+
+        profile.balance -= amount.0;
+        self.profiles.insert(&key, &profile);
+        log_event!("Profile budget withdrawn: {} {} {}", key.0, key.1, amount.0);
+        Promise::new(key.0).transfer(amount.0)
+    }
+
+    // Creates a native stream from `owner` funded by the budget of its profile,
+    // callable by the owner and the profile's operators. The operators can
+    // then pause, resume and cancel it like the sender.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_profile_stream(
+        &mut self,
+        owner: AccountId,
+        profile: String,
+        receiver: AccountId,
+        stream_rate: U128,
+        start: U64,
+        end: U64,
+        can_cancel: bool,
+        can_update: bool,
+    ) -> U64 {
+        let key = (owner, profile);
+        let mut budget = self.profiles.get(&key).expect("Profile does not exist");
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == key.0 || budget.operators.contains(&caller),
+            "Not an operator of the profile"
+        );
+
+        // same checks as `create_stream`
+        let rate = stream_rate.0;
+        let current_timestamp: u64 = current_time();
+        require!(
+            start.0 >= current_timestamp,
+            "Start time cannot be in the past"
+        );
+        require!(end.0 >= start.0, "Start time cannot be in the past");
+        require!(receiver != key.0, "Sender and receiver cannot be the same");
+        self.internal_check_sender(&receiver, &key.0);
+        require!(rate > 0, "Rate cannot be zero");
+        require!(rate < MAX_RATE, "Rate is too high");
+        let amount = u128::from(end.0 - start.0)
+            .checked_mul(rate)
+            .expect("Stream amount is too large");
+
+        require!(amount <= budget.balance, "Not enough budget");
+        budget.balance -= amount;
+        self.profiles.insert(&key, &budget);
+
+        let id = self.current_id;
+        self.internal_add_stream(&Stream {
+            id,
+            sender: key.0.clone(),
+            receiver,
+            rate,
+            is_paused: false,
+            is_cancelled: false,
+            balance: amount,
+            created: current_timestamp,
+            start_time: start.0,
+            end_time: end.0,
+            withdraw_time: start.0,
+            paused_time: 0,
+            contract_id: "near.testnet".parse().unwrap(), // ignored for native streams
+            can_cancel,
+            can_update,
+            is_native: true,
+        });
+        self.current_id += 1;
+
+        let mut stream_ids = self.profile_streams.get(&key).unwrap_or_else(|| {
+            Vector::new(StorageKey::ProfileStreamsInner {
+                profile_hash: env::sha256_array(format!("{}:{}", key.0, key.1).as_bytes()),
+            })
+        });
+        stream_ids.push(&id);
+        self.profile_streams.insert(&key, &stream_ids);
+        self.stream_profiles.insert(&id, &key);
+        log_event!("Profile stream created: {} {} {}", key.0, key.1, id);
+        U64(id)
+    }
+
+    pub fn get_profile(&self, owner: AccountId, name: String) -> Option<Profile> {
+        self.profiles.get(&(owner, name))
+    }
+
+    // streams created from the profile, oldest first
+    pub fn get_profile_streams(
+        &self,
+        owner: AccountId,
+        name: String,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<Stream> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        let stream_ids = match self.profile_streams.get(&(owner, name)) {
+            Some(stream_ids) => stream_ids,
+            None => return vec![],
+        };
+
+        stream_ids
+            .iter()
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(|id| self.internal_get_stream(id))
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Whether `account_id` operates the profile the stream of `owner` was created from.
+    pub(crate) fn internal_is_profile_operator(
+        &self,
+        stream_id: u64,
+        owner: &AccountId,
+        account_id: &AccountId,
+    ) -> bool {
+        let key = match self.stream_profiles.get(&stream_id) {
+            Some(key) if &key.0 == owner => key,
+            _ => return false,
+        };
+        self.profiles
+            .get(&key)
+            .is_some_and(|profile| profile.operators.contains(account_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn operator_streams_from_budget() {
+        let mut contract = Contract::new();
+
+        // alice's hr profile is run by charlie with a budget of 25
+        set_context_with_balance(accounts(0), 25);
+        contract.set_profile("hr".to_string(), vec![accounts(2)]);
+
+        set_context_with_balance(accounts(2), 0);
+        let stream_id = contract.create_profile_stream(
+            accounts(0),
+            "hr".to_string(),
+            accounts(1),
+            U128(2),
+            U64(0),
+            U64(10),
+            true,
+            false,
+        );
+        let stream = contract.get_stream(stream_id);
+        assert_eq!(stream.sender, accounts(0));
+        assert_eq!(stream.balance, 20);
+        let profile = contract.get_profile(accounts(0), "hr".to_string()).unwrap();
+        assert_eq!(profile.balance, 5);
+        assert_eq!(
+            contract
+                .get_profile_streams(accounts(0), "hr".to_string(), None, None)
+                .len(),
+            1
+        );
+
+        // charlie cancels it for alice
+        contract.cancel(stream_id, None);
+        assert!(contract.get_stream(stream_id).is_cancelled);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough budget")]
+    fn budget_exceeded() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10);
+        contract.set_profile("grants".to_string(), vec![accounts(2)]);
+
+        set_context_with_balance(accounts(2), 0);
+        contract.create_profile_stream(
+            accounts(0),
+            "grants".to_string(),
+            accounts(1),
+            U128(2),
+            U64(0),
+            U64(10),
+            true,
+            false,
+        );
+    }
+}
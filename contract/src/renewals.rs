@@ -0,0 +1,124 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    // Streams sent by the caller are reported as ending soon once a withdrawal
+    // happens less than `window` seconds before their end, `None` turns it off.
+    // The storage is paid from the attached deposit and the rest, or the
+    // storage freed, is refunded.
+    #[payable]
+    pub fn set_renewal_window(&mut self, window: Option<U64>) {
+        let sender = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        match window {
+            Some(window) => {
+                require!(window.0 > 0, "Window cannot be zero");
+                self.renewal_windows
+                    .insert(&sender, &compact_timestamp(window.0));
+                log_event!("Renewal window set: {} {}", sender, window.0);
+            }
+            None => {
+                self.renewal_windows.remove(&sender);
+                log_event!("Renewal window removed: {}", sender);
+            }
+        }
+
+        let final_storage = env::storage_usage();
+        let refund = if final_storage > initial_storage {
+            let cost = Balance::from(final_storage - initial_storage) * env::storage_byte_cost();
+            require!(
+                env::attached_deposit() >= cost,
+                "The amount provided is not enough for the window storage"
+            );
+            env::attached_deposit() - cost
+        } else {
+            env::attached_deposit()
+                + Balance::from(initial_storage - final_storage) * env::storage_byte_cost()
+        };
+        if refund > 0 {
+            Promise::new(sender).transfer(refund);
+        }
+    }
+}
+
+impl Contract {
+    /// Adds the stream to the streams ending soon on its first withdrawal in
+    /// the sender's renewal window and drops it once it has ended.
+    pub(crate) fn internal_track_ending_soon(&mut self, stream: &Stream) {
+        let current_timestamp = current_time();
+        if stream.is_cancelled || self.internal_has_ended(stream, current_timestamp) {
+            self.ending_soon.remove(&stream.id);
+            return;
+        }
+
+        let window = match self.renewal_windows.get(&stream.sender) {
+            Some(window) => u64::from(window),
+            None => return,
+        };
+        if current_timestamp + window >= stream.end_time && self.ending_soon.insert(&stream.id) {
+            log_event!("Stream ending soon: {} {}", stream.id, stream.end_time);
+            self.internal_notify(stream, StreamEvent::EndingSoon);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn report_ending_soon() {
+        let mut contract = Contract::new();
+
+        // alice wants to hear about her streams 3 seconds before they end
+        set_context(accounts(0), NEAR, 0);
+        contract.set_renewal_window(Some(U64(3)));
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+
+        set_context(accounts(1), 0, 5);
+        contract.withdraw(U64(1), None);
+        assert!(contract.get_renewal_reminders(None, None).is_empty());
+
+        // only the first withdrawal in the window is reported
+        set_context(accounts(1), 0, 8);
+        contract.withdraw(U64(1), None);
+        assert!(get_logs().contains(&"Stream ending soon: 1 11".to_string()));
+        assert!(get_logs().contains(&format!(
+            r#"EVENT_JSON:{{"data":[{{"message":"Stream ending soon: 1 11"}}],"event":"log","standard":"zebec","version":"{}"}}"#,
+            API_REVISION
+        )));
+        set_context(accounts(1), 0, 9);
+        contract.withdraw(U64(1), None);
+        assert!(!get_logs()
+            .iter()
+            .any(|log| log.starts_with("Stream ending soon")));
+        assert_eq!(contract.get_renewal_reminders(None, None), vec![U64(1)]);
+
+        // the stream is dropped once it has ended
+        set_context(accounts(1), 0, 12);
+        contract.withdraw(U64(1), None);
+        assert!(contract.get_renewal_reminders(None, None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the window storage")]
+    fn renewal_window_needs_storage_deposit() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 0, 0);
+        contract.set_renewal_window(Some(U64(3)));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::*;
+
+/// How a receiver's list of senders is applied when a stream to it is created.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SenderFilter {
+    Allow, // only the listed senders can open streams
+    Block, // everyone but the listed senders can open streams
+}
+
+#[near_bindgen]
+impl Contract {
+    // Filters who can open streams to the caller, `None` lets everyone again.
+    // The list of senders is kept when the filter changes.
+    pub fn set_sender_filter(&mut self, filter: Option<SenderFilter>) {
+        let receiver = env::predecessor_account_id();
+        match filter {
+            Some(filter) => {
+                self.sender_filters.insert(&receiver, &filter);
+                log_event!("Sender filter set: {}", receiver);
+            }
+            None => {
+                self.sender_filters.remove(&receiver);
+                log_event!("Sender filter removed: {}", receiver);
+            }
+        }
+    }
+
+    // Adds senders to the caller's list, the storage is paid from the attached
+    // deposit and the rest is refunded
+    #[payable]
+    pub fn add_filtered_senders(&mut self, senders: Vec<AccountId>) {
+        let receiver = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        for sender in senders {
+            self.filtered_senders.insert(&(receiver.clone(), sender));
+        }
+
+        let cost = Balance::from(env::storage_usage().saturating_sub(initial_storage))
+            * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() >= cost,
+            "The amount provided is not enough for the senders storage"
+        );
+        if env::attached_deposit() > cost {
+            Promise::new(receiver).transfer(env::attached_deposit() - cost);
+        }
+    }
+
+    // Removes senders from the caller's list and refunds the storage freed
+    pub fn remove_filtered_senders(&mut self, senders: Vec<AccountId>) {
+        let receiver = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+        for sender in senders {
+            self.filtered_senders.remove(&(receiver.clone(), sender));
+        }
+
+        let refund = Balance::from(initial_storage.saturating_sub(env::storage_usage()))
+            * env::storage_byte_cost();
+        if refund > 0 {
+            Promise::new(receiver).transfer(refund);
+        }
+    }
+}
+
+impl Contract {
+    /// Whether the receiver's sender filter lets `sender` open streams to it.
+    pub(crate) fn internal_is_sender_allowed(
+        &self,
+        receiver: &AccountId,
+        sender: &AccountId,
+    ) -> bool {
+        let is_listed = || {
+            self.filtered_senders
+                .contains(&(receiver.clone(), sender.clone()))
+        };
+        match self.sender_filters.get(receiver) {
+            None => true,
+            Some(SenderFilter::Allow) => is_listed(),
+            Some(SenderFilter::Block) => !is_listed(),
+        }
+    }
+
+    pub(crate) fn internal_check_sender(&self, receiver: &AccountId, sender: &AccountId) {
+        require!(
+            self.internal_is_sender_allowed(receiver, sender),
+            "The receiver does not accept streams from the sender"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn allow_listed_senders() {
+        let mut contract = Contract::new();
+
+        // bob only accepts streams from alice
+        set_context_with_balance(accounts(1), NEAR);
+        contract.set_sender_filter(Some(SenderFilter::Allow));
+        contract.add_filtered_senders(vec![accounts(0)]);
+        assert!(contract.is_sender_allowed(accounts(1), accounts(0)));
+        assert!(!contract.is_sender_allowed(accounts(1), accounts(2)));
+
+        set_context_with_balance(accounts(0), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+
+        // blocking alice instead
+        set_context_with_balance(accounts(1), 0);
+        contract.set_sender_filter(Some(SenderFilter::Block));
+        assert!(!contract.is_sender_allowed(accounts(1), accounts(0)));
+        assert!(contract.is_sender_allowed(accounts(1), accounts(2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "The receiver does not accept streams from the sender")]
+    fn blocked_sender_cannot_create() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(1), NEAR);
+        contract.set_sender_filter(Some(SenderFilter::Block));
+        contract.add_filtered_senders(vec![accounts(2)]);
+
+        set_context_with_balance(accounts(2), 10);
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), true, false, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the senders storage")]
+    fn filtered_senders_need_storage_deposit() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(1), 0);
+        contract.add_filtered_senders(vec![accounts(0), accounts(2)]);
+    }
+}
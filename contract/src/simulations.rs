@@ -0,0 +1,240 @@
+use crate::*;
+
+pub const MAX_SIMULATION_POINTS: usize = 100; // timestamps per `simulate_stream`
+
+// a stream that is not created, see `simulate_stream`
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SimulationParams {
+    pub stream_rate: U128,
+    pub start: U64,
+    pub end: U64,
+    pub cliff_time: Option<U64>,
+    pub accrual_period: Option<AccrualPeriod>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SimulationPointView {
+    pub timestamp: U64,
+    pub withdrawable: U128, // by the receiver if nothing was withdrawn before
+    pub balance: U128,      // left in the stream after that withdrawal
+}
+
+#[near_bindgen]
+impl Contract {
+    // What a stream created with `params` would let the receiver withdraw at
+    // each of `sample_points`, with the same accrual as the created stream
+    pub fn simulate_stream(
+        &self,
+        params: SimulationParams,
+        sample_points: Vec<U64>,
+    ) -> Vec<SimulationPointView> {
+        require!(
+            sample_points.len() <= MAX_SIMULATION_POINTS,
+            "Too many sample points"
+        );
+        // same conditions as `create_stream`
+        let (rate, start_time, end_time) = (params.stream_rate.0, params.start.0, params.end.0);
+        require!(
+            end_time >= start_time,
+            "End time cannot be before start time"
+        );
+        require!(rate > 0, "Rate cannot be zero");
+        require!(rate < MAX_RATE, "Rate is too high");
+        let cliff_time = params.cliff_time.map(|cliff_time| cliff_time.0);
+        if let Some(cliff_time) = cliff_time {
+            require!(
+                start_time <= cliff_time && cliff_time <= end_time,
+                "Cliff time must be between the start and end times"
+            );
+        }
+        let balance = rate
+            .checked_mul(u128::from(end_time - start_time))
+            .expect("Stream amount is too large");
+
+        // only the times matter to the vesting
+        let stream = Stream {
+            id: 0,
+            sender: env::current_account_id(),
+            receiver: env::current_account_id(),
+            rate,
+            is_paused: false,
+            is_cancelled: false,
+            balance,
+            created: current_time(),
+            start_time,
+            end_time,
+            withdraw_time: start_time,
+            paused_time: 0,
+            contract_id: env::current_account_id(),
+            can_cancel: false,
+            can_update: false,
+            is_native: true,
+        };
+
+        sample_points
+            .into_iter()
+            .map(|timestamp| {
+                let accrued_until =
+                    policies::vested_time(&stream, cliff_time, params.accrual_period, timestamp.0)
+                        .min(end_time)
+                        .max(start_time);
+                let withdrawable = rate * u128::from(accrued_until - start_time);
+                SimulationPointView {
+                    timestamp,
+                    withdrawable: U128(withdrawable),
+                    balance: U128(balance - withdrawable),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn simulate_stream() {
+        let contract = Contract::new();
+
+        // 2 per second from 10 to 20
+        let params = SimulationParams {
+            stream_rate: U128(2),
+            start: U64(10),
+            end: U64(20),
+            cliff_time: None,
+            accrual_period: None,
+        };
+        let points = contract.simulate_stream(params, vec![U64(5), U64(14), U64(25)]);
+        let withdrawable: Vec<u128> = points.iter().map(|point| point.withdrawable.0).collect();
+        assert_eq!(withdrawable, vec![0, 8, 20]);
+        assert_eq!(points[1].balance, U128(12));
+    }
+
+    #[test]
+    fn simulate_stream_cliff() {
+        let contract = Contract::new();
+
+        // 2 per second from 10 to 20 with a cliff at 15
+        let params = SimulationParams {
+            stream_rate: U128(2),
+            start: U64(10),
+            end: U64(20),
+            cliff_time: Some(U64(15)),
+            accrual_period: None,
+        };
+        let points = contract.simulate_stream(params, vec![U64(14), U64(15), U64(25)]);
+        let withdrawable: Vec<u128> = points.iter().map(|point| point.withdrawable.0).collect();
+        assert_eq!(withdrawable, vec![0, 10, 20]);
+        assert_eq!(points[1].balance, U128(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cliff time must be between the start and end times")]
+    fn simulate_stream_cliff_after_end() {
+        let contract = Contract::new();
+
+        let params = SimulationParams {
+            stream_rate: U128(2),
+            start: U64(10),
+            end: U64(20),
+            cliff_time: Some(U64(25)),
+            accrual_period: None,
+        };
+        contract.simulate_stream(params, vec![U64(15)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "End time cannot be before start time")]
+    fn simulate_stream_ending_before_start() {
+        let contract = Contract::new();
+
+        let params = SimulationParams {
+            stream_rate: U128(2),
+            start: U64(20),
+            end: U64(10),
+            cliff_time: None,
+            accrual_period: None,
+        };
+        contract.simulate_stream(params, vec![U64(15)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Stream amount is too large")]
+    fn simulate_stream_amount_overflow() {
+        let contract = Contract::new();
+
+        let params = SimulationParams {
+            stream_rate: U128(MAX_RATE - 1),
+            start: U64(0),
+            end: U64(u64::MAX),
+            cliff_time: None,
+            accrual_period: None,
+        };
+        contract.simulate_stream(params, vec![U64(15)]);
+    }
+
+    #[test]
+    fn simulate_stream_matches_withdraw() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(10), U64(20), true, false, None);
+        let params = SimulationParams {
+            stream_rate: U128(1),
+            start: U64(10),
+            end: U64(20),
+            cliff_time: None,
+            accrual_period: None,
+        };
+        let points = contract.simulate_stream(params, vec![U64(14)]);
+
+        set_context(accounts(1), 0, 14);
+        contract.withdraw(stream_id, None);
+        assert_eq!(
+            points[0].balance,
+            U128(contract.get_stream(stream_id).balance)
+        );
+    }
+
+    #[test]
+    fn simulate_stream_accrual_period() {
+        let mut contract = Contract::new();
+
+        // a day of 1 per second, vesting daily
+        set_context(accounts(0), 86_400, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(1), U64(1), U64(86_401), true, true, None);
+        contract.set_accrual_period(stream_id, Some(AccrualPeriod::Day));
+        let params = SimulationParams {
+            stream_rate: U128(1),
+            start: U64(1),
+            end: U64(86_401),
+            cliff_time: None,
+            accrual_period: Some(AccrualPeriod::Day),
+        };
+        let points = contract.simulate_stream(params, vec![U64(86_399), U64(86_400)]);
+        assert_eq!(points[0].withdrawable, U128(0));
+        assert_eq!(points[1].withdrawable, U128(86_399));
+
+        set_context(accounts(1), 0, 86_400);
+        contract.withdraw(stream_id, None);
+        assert_eq!(
+            points[1].balance,
+            U128(contract.get_stream(stream_id).balance)
+        );
+    }
+}
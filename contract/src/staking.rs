@@ -0,0 +1,392 @@
+use crate::*;
+use near_sdk::{serde_json, EpochHeight};
+
+// unstaked NEAR can be withdrawn from a staking pool after 4 epochs
+pub const NUM_EPOCHS_TO_UNLOCK: EpochHeight = 4;
+
+#[allow(dead_code)]
+#[ext_contract(ext_staking_pool)]
+trait StakingPool {
+    fn deposit_and_stake(&mut self);
+    fn ping(&mut self);
+    fn get_account_staked_balance(&self, account_id: AccountId) -> U128;
+    fn unstake(&mut self, amount: U128);
+    fn withdraw(&mut self, amount: U128);
+}
+
+/// NEAR a sender staked through the contract so that the rewards extend one
+/// of its native streams. Each pool funds at most one stream.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakedFunding {
+    pool_id: AccountId,
+    principal: Balance,
+    unstaked: Balance, // waiting to be withdrawn from the pool
+    unstaked_epoch: EpochHeight,
+    is_stopped: bool, // the principal is unstaked too
+    is_locked: bool,  // a call to the pool is in progress
+}
+
+#[near_bindgen]
+impl Contract {
+    // Stakes the attached NEAR with `pool_id` for the caller's stream, the
+    // rewards are swept into the stream with `sweep_rewards`
+    #[payable]
+    pub fn stake_for_stream(&mut self, stream_id: U64, pool_id: AccountId) -> Promise {
+        let stream = self.internal_get_stream(stream_id.0);
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "Only the sender can stake for the stream"
+        );
+        require!(
+            stream.is_native,
+            "Only native streams can be funded by staking"
+        );
+        require!(!stream.is_cancelled, "Stream is cancelled");
+        require!(env::attached_deposit() > 0, "Nothing to stake");
+
+        let funding = match self.staked_fundings.get(&stream_id.0) {
+            Some(funding) => {
+                require!(
+                    funding.pool_id == pool_id,
+                    "Stream is staked with another pool"
+                );
+                require!(!funding.is_stopped, "Staking is stopped");
+                funding
+            }
+            None => {
+                require!(
+                    self.staking_pools.insert(&pool_id),
+                    "Pool already funds a stream"
+                );
+                StakedFunding {
+                    pool_id,
+                    principal: 0,
+                    unstaked: 0,
+                    unstaked_epoch: 0,
+                    is_stopped: false,
+                    is_locked: false,
+                }
+            }
+        };
+        let pool_id = self.internal_lock_funding(stream_id.0, funding);
+
+        ext_staking_pool::ext(pool_id)
+            .with_attached_deposit(env::attached_deposit())
+            .deposit_and_stake()
+            .then(Self::ext(env::current_account_id()).internal_resolve_stake(
+                stream_id,
+                stream.sender,
+                U128(env::attached_deposit()),
+            ))
+    }
+
+    // Callable by anyone: withdraws the unstaked rewards into the stream once
+    // unlocked, otherwise unstakes the rewards earned since the last sweep
+    pub fn sweep_rewards(&mut self, stream_id: U64) -> Promise {
+        let funding = self
+            .staked_fundings
+            .get(&stream_id.0)
+            .expect("Stream is not funded by staking");
+
+        let unstaked = funding.unstaked;
+        if unstaked > 0 {
+            require!(
+                env::epoch_height() >= funding.unstaked_epoch + NUM_EPOCHS_TO_UNLOCK,
+                "Unstaked rewards are not unlocked yet"
+            );
+            let pool_id = self.internal_lock_funding(stream_id.0, funding);
+            return ext_staking_pool::ext(pool_id)
+                .withdraw(U128(unstaked))
+                .then(
+                    Self::ext(env::current_account_id())
+                        .internal_resolve_staking_withdraw(stream_id, U128(unstaked)),
+                );
+        }
+        require!(!funding.is_stopped, "Staking is stopped");
+        let pool_id = self.internal_lock_funding(stream_id.0, funding);
+        self.internal_query_staked_balance(stream_id, pool_id, false)
+    }
+
+    // Unstakes the principal with the rewards, the next sweep once unlocked
+    // returns the principal to the sender
+    pub fn stop_staking(&mut self, stream_id: U64) -> Promise {
+        let stream = self.internal_get_stream(stream_id.0);
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "Only the sender can stop staking"
+        );
+        let funding = self
+            .staked_fundings
+            .get(&stream_id.0)
+            .expect("Stream is not funded by staking");
+        require!(!funding.is_stopped, "Staking is stopped");
+        require!(
+            funding.unstaked == 0,
+            "Sweep the unstaked rewards before stopping"
+        );
+        let pool_id = self.internal_lock_funding(stream_id.0, funding);
+        self.internal_query_staked_balance(stream_id, pool_id, true)
+    }
+
+    pub fn get_staked_funding(&self, stream_id: U64) -> Option<StakedFunding> {
+        self.staked_fundings.get(&stream_id.0)
+    }
+
+    // refunds the sender if the deposit could not be staked
+    #[private]
+    pub fn internal_resolve_stake(
+        &mut self,
+        stream_id: U64,
+        sender: AccountId,
+        amount: U128,
+    ) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        let mut funding = self.staked_fundings.get(&stream_id.0).unwrap();
+        funding.is_locked = false;
+        if res {
+            funding.principal += amount.0;
+            self.staked_fundings.insert(&stream_id.0, &funding);
+            log_event!("Staked for stream: {} {}", stream_id.0, amount.0);
+        } else {
+            if funding.principal == 0 {
+                self.staked_fundings.remove(&stream_id.0);
+                self.staking_pools.remove(&funding.pool_id);
+            } else {
+                self.staked_fundings.insert(&stream_id.0, &funding);
+            }
+            Promise::new(sender).transfer(amount.0);
+        }
+        res
+    }
+
+    // unstakes what the pool holds above the principal, or everything when stopping
+    #[private]
+    pub fn internal_resolve_staked_balance(
+        &mut self,
+        stream_id: U64,
+        stop: bool,
+    ) -> PromiseOrValue<bool> {
+        let staked: Option<Balance> = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                .ok()
+                .map(|staked| staked.0),
+            _ => None,
+        };
+        let funding = self.staked_fundings.get(&stream_id.0).unwrap();
+        let amount = match staked {
+            Some(staked) if stop => staked,
+            Some(staked) => staked.saturating_sub(funding.principal),
+            None => 0,
+        };
+        if amount == 0 {
+            self.internal_unlock_funding(stream_id.0);
+            return PromiseOrValue::Value(false);
+        }
+
+        ext_staking_pool::ext(funding.pool_id)
+            .unstake(U128(amount))
+            .then(
+                Self::ext(env::current_account_id()).internal_resolve_unstake(
+                    stream_id,
+                    U128(amount),
+                    stop,
+                ),
+            )
+            .into()
+    }
+
+    #[private]
+    pub fn internal_resolve_unstake(&mut self, stream_id: U64, amount: U128, stop: bool) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        let mut funding = self.staked_fundings.get(&stream_id.0).unwrap();
+        funding.is_locked = false;
+        if res {
+            funding.unstaked = amount.0;
+            funding.unstaked_epoch = env::epoch_height();
+            funding.is_stopped = stop;
+            log_event!("Unstaked for stream: {} {}", stream_id.0, amount.0);
+        }
+        self.staked_fundings.insert(&stream_id.0, &funding);
+        res
+    }
+
+    // adds the withdrawn rewards to the stream, the principal and what the
+    // stream cannot take go back to the sender
+    #[private]
+    pub fn internal_resolve_staking_withdraw(&mut self, stream_id: U64, amount: U128) -> bool {
+        let res: bool = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !res {
+            self.internal_unlock_funding(stream_id.0);
+            return false;
+        }
+
+        let mut funding = self.staked_fundings.get(&stream_id.0).unwrap();
+        let (rewards, principal) = if funding.is_stopped {
+            let rewards = amount.0.saturating_sub(funding.principal);
+            (rewards, amount.0 - rewards)
+        } else {
+            (amount.0, 0)
+        };
+
+        let unused = self.internal_fund_with_rewards(stream_id.0, rewards);
+        if funding.is_stopped {
+            self.staked_fundings.remove(&stream_id.0);
+            self.staking_pools.remove(&funding.pool_id);
+        } else {
+            funding.unstaked = 0;
+            funding.is_locked = false;
+            self.staked_fundings.insert(&stream_id.0, &funding);
+        }
+
+        if principal + unused > 0 {
+            let sender = self.internal_get_stream(stream_id.0).sender;
+            Promise::new(sender).transfer(principal + unused);
+        }
+        true
+    }
+}
+
+impl Contract {
+    // one call to the pool at a time, so that the staked balance only moves
+    // with the principal and the rewards the contract knows of
+    fn internal_lock_funding(&mut self, stream_id: u64, mut funding: StakedFunding) -> AccountId {
+        require!(!funding.is_locked, "A staking call is in progress");
+        funding.is_locked = true;
+        self.staked_fundings.insert(&stream_id, &funding);
+        funding.pool_id
+    }
+
+    fn internal_unlock_funding(&mut self, stream_id: u64) {
+        let mut funding = self.staked_fundings.get(&stream_id).unwrap();
+        funding.is_locked = false;
+        self.staked_fundings.insert(&stream_id, &funding);
+    }
+
+    fn internal_query_staked_balance(
+        &self,
+        stream_id: U64,
+        pool_id: AccountId,
+        stop: bool,
+    ) -> Promise {
+        ext_staking_pool::ext(pool_id.clone())
+            .ping()
+            .then(
+                ext_staking_pool::ext(pool_id)
+                    .get_account_staked_balance(env::current_account_id()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .internal_resolve_staked_balance(stream_id, stop),
+            )
+    }
+
+    /// Adds whole seconds of `amount` to the stream and extends its end, like a
+    /// donation without matching. Returns what the stream cannot take.
+    pub(crate) fn internal_fund_with_rewards(
+        &mut self,
+        stream_id: u64,
+        amount: Balance,
+    ) -> Balance {
+        let mut stream = self.internal_get_stream(stream_id);
+        if stream.is_cancelled
+            || self.internal_has_ended(&stream, current_time())
+            || self.rate_escalations.get(&stream_id).is_some()
+            || self.rate_changes.get(&stream_id).is_some()
+        {
+            return amount;
+        }
+
+        let funded = amount / stream.rate * stream.rate;
+        let extension = funded / stream.rate;
+        if funded == 0 || extension > u32::MAX.into() {
+            return amount;
+        }
+
+        // move the stream in the end time index
+        self.stream_ends.remove(&(stream.end_time, stream_id));
+        stream.end_time += extension as u64;
+        self.stream_ends.insert(&(stream.end_time, stream_id), &());
+
+        stream.balance += funded;
+        self.internal_save_stream(&stream);
+        log_event!("Stream funded by rewards: {} {}", stream_id, funded);
+
+        amount - funded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn stake_with_one_pool() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(accounts(1), U128(NEAR), U64(0), U64(10), true, false, None);
+
+        // alice stakes 100 NEAR with the pool, the stake is counted once it succeeded
+        set_context_with_balance(accounts(0), 100 * NEAR);
+        contract.stake_for_stream(U64(1), accounts(3));
+        assert_eq!(get_created_receipts()[0].receiver_id, accounts(3));
+        let funding = contract.get_staked_funding(U64(1)).unwrap();
+        assert_eq!(funding.principal, 0);
+        assert!(funding.is_locked);
+        assert!(contract.staking_pools.contains(&accounts(3)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Pool already funds a stream")]
+    fn pool_funds_one_stream() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(accounts(1), U128(NEAR), U64(0), U64(10), true, false, None);
+        contract.create_stream(accounts(2), U128(NEAR), U64(0), U64(10), true, false, None);
+
+        set_context_with_balance(accounts(0), 100 * NEAR);
+        contract.stake_for_stream(U64(1), accounts(3));
+        contract.stake_for_stream(U64(2), accounts(3));
+    }
+
+    #[test]
+    fn fund_whole_seconds() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(accounts(1), U128(NEAR), U64(0), U64(10), true, false, None);
+
+        // 2.5 NEAR of rewards extend the stream by 2 seconds
+        let unused = contract.internal_fund_with_rewards(1, 5 * NEAR / 2);
+        assert_eq!(unused, NEAR / 2);
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.end_time, 12);
+        assert_eq!(stream.balance, 12 * NEAR);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::*;
+use near_sdk::IntoStorageKey;
+
+// only compiled with the `testing` feature, so sandbox tests can move the
+// contract's clock without waiting for blocks
+
+pub(crate) fn test_timestamp() -> Option<Timestamp> {
+    env::storage_read(&StorageKey::TestTimestamp.into_storage_key())
+        .map(|timestamp| Timestamp::try_from_slice(&timestamp).unwrap())
+}
+
+#[near_bindgen]
+impl Contract {
+    // Makes the contract see `timestamp` (seconds) as the current time, `None`
+    // goes back to the block timestamp
+    #[private]
+    pub fn set_test_timestamp(&mut self, timestamp: Option<U64>) {
+        let key = StorageKey::TestTimestamp.into_storage_key();
+        match timestamp {
+            Some(timestamp) => {
+                env::storage_write(&key, &timestamp.0.try_to_vec().unwrap());
+            }
+            None => {
+                env::storage_remove(&key);
+            }
+        }
+    }
+
+    pub fn get_test_timestamp(&self) -> Option<U64> {
+        test_timestamp().map(U64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    #[test]
+    fn override_timestamp() {
+        let mut contract = Contract::new();
+
+        // the contract calls itself
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(accounts(0));
+        builder.current_account_id(accounts(0));
+        builder.attached_deposit(10);
+        testing_env!(builder.build());
+        contract.create_stream(accounts(1), U128(1), U64(0), U64(10), false, false, None);
+
+        // bob withdraws at 4 seconds without the block time moving
+        contract.set_test_timestamp(Some(U64(4)));
+        assert_eq!(current_time(), 4);
+        builder.predecessor_account_id(accounts(1));
+        builder.attached_deposit(0);
+        testing_env!(builder.build());
+        contract.withdraw(U64(1), None);
+        assert_eq!(contract.internal_get_stream(1).balance, 6);
+
+        builder.predecessor_account_id(accounts(0));
+        testing_env!(builder.build());
+        contract.set_test_timestamp(None);
+        assert_eq!(current_time(), 0);
+    }
+}
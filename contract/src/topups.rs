@@ -0,0 +1,209 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    // Adds the attached NEAR to the caller's running native stream and extends
+    // its end time by the whole seconds it pays for, the rest is refunded
+    #[payable]
+    pub fn topup(&mut self, stream_id: U64) {
+        let sender = env::predecessor_account_id();
+        let unused = self.internal_topup(stream_id.0, &sender, env::attached_deposit(), None);
+        if unused > 0 {
+            Promise::new(sender).transfer(unused);
+        }
+    }
+}
+
+impl Contract {
+    /// Adds `amount` of `token` (None for NEAR) from the sender to the stream and
+    /// extends its end time by the whole seconds paid for. Returns the unused remainder.
+    pub(crate) fn internal_topup(
+        &mut self,
+        stream_id: u64,
+        sender: &AccountId,
+        amount: Balance,
+        token: Option<AccountId>,
+    ) -> Balance {
+        let current_timestamp: u64 = current_time();
+
+        let mut stream = self.internal_get_stream(stream_id);
+
+        require!(
+            sender == &stream.sender,
+            "Only the sender can top up the stream"
+        );
+        require!(
+            stream.is_native == token.is_none()
+                && (stream.is_native || Some(&stream.contract_id) == token.as_ref()),
+            "Token does not match the stream"
+        );
+        require!(!stream.is_cancelled, "Stream is cancelled");
+        require!(
+            !self.internal_has_ended(&stream, current_timestamp),
+            "Stream already ended"
+        );
+        // same as donations, the extension is paid at the current rate
+        require!(
+            self.rate_escalations.get(&stream_id).is_none(),
+            "Cannot top up a stream with rate escalation"
+        );
+        require!(
+            self.rate_changes.get(&stream_id).is_none(),
+            "Cannot top up a stream with a rate change"
+        );
+
+        let topped_up = amount / stream.rate * stream.rate;
+        require!(
+            topped_up > 0,
+            "Top-up is less than one second of the stream"
+        );
+        let extension = topped_up / stream.rate;
+        require!(extension <= u32::MAX.into(), "Top-up is too large");
+
+        // move the stream in the end time index
+        self.stream_ends.remove(&(stream.end_time, stream_id));
+        stream.end_time += extension as u64;
+        self.stream_ends.insert(&(stream.end_time, stream_id), &());
+        // renewed, it is reported again in the next renewal window
+        self.ending_soon.remove(&stream_id);
+
+        stream.balance += topped_up;
+        self.internal_save_stream(&stream);
+
+        log_event!(
+            "Stream topped up: {} {} {}",
+            stream_id,
+            topped_up,
+            stream.end_time
+        );
+        self.internal_notify(&stream, StreamEvent::ToppedUp);
+
+        amount - topped_up
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+    use std::collections::HashMap;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn topup_extends_end_time() {
+        let mut contract = Contract::new();
+
+        // 2 per second from 10 to 20
+        set_context(accounts(0), 20, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(2), U64(10), U64(20), true, false, None);
+
+        // 7 more pays for 3 seconds, 1 is refunded
+        set_context(accounts(0), 7, 15);
+        contract.topup(stream_id);
+        let stream = contract.get_stream(stream_id);
+        assert_eq!(stream.end_time, 23);
+        assert_eq!(stream.balance, 26);
+        assert_eq!(get_created_receipts().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the sender can top up the stream")]
+    fn topup_by_receiver() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 20, 0);
+        let stream_id =
+            contract.create_stream(accounts(1), U128(2), U64(10), U64(20), true, false, None);
+
+        set_context(accounts(1), 4, 15);
+        contract.topup(stream_id);
+    }
+
+    // alice -> bob, 2 of `token` per second from 10 to 20
+    fn create_ft_stream(contract: &mut Contract, token: &str) -> U64 {
+        set_context(token.parse().unwrap(), 0, 0);
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"2","start":"10","end":"20","can_update":false,"can_cancel":true}}"#,
+            accounts(1),
+        );
+        contract.ft_on_transfer(accounts(0), U128(20), msg);
+        U64(1)
+    }
+
+    fn topup_msg(stream_id: U64) -> String {
+        format!(r#"{{"method_name":"topup","stream_id":"{}"}}"#, stream_id.0)
+    }
+
+    #[test]
+    fn ft_topup() {
+        let mut contract = Contract::new();
+        let stream_id = create_ft_stream(&mut contract, "usdn.testnet");
+
+        // 7 more tokens pay for 3 seconds, 1 is returned to alice
+        set_context("usdn.testnet".parse().unwrap(), 0, 15);
+        let unused = contract.ft_on_transfer(accounts(0), U128(7), topup_msg(stream_id));
+        assert!(matches!(unused, PromiseOrValue::Value(U128(1))));
+        let stream = contract.get_stream(stream_id);
+        assert_eq!(stream.end_time, 23);
+        assert_eq!(stream.balance, 26);
+    }
+
+    #[test]
+    fn ft_topup_during_withdraw() {
+        let mut contract = Contract::new();
+        let stream_id = create_ft_stream(&mut contract, "usdn.testnet");
+
+        // bob withdraws 8 at 14, the stream is saved when the transfer resolves
+        set_context(accounts(1), 0, 14);
+        let mut stream = contract.internal_get_stream(stream_id.0);
+        let (amount, cap) = contract.internal_withdraw_accrued(&mut stream);
+        contract.withdraw(stream_id, None);
+
+        // alice tops up 6 in the meantime
+        set_context("usdn.testnet".parse().unwrap(), 0, 14);
+        contract.ft_on_transfer(accounts(0), U128(6), topup_msg(stream_id));
+
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(env::current_account_id())
+                .block_timestamp(14_000_000_000)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            HashMap::default(),
+            vec![PromiseResult::Successful(vec![])],
+        );
+        contract.internal_resolve_ft_withdraw(
+            stream,
+            U128(amount),
+            U128(amount),
+            accounts(1),
+            cap,
+            None,
+        );
+        let stream = contract.get_stream(stream_id);
+        assert_eq!(stream.balance, 18);
+        assert_eq!(stream.end_time, 23);
+        assert_eq!(stream.withdraw_time, 14);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token does not match the stream")]
+    fn ft_topup_with_other_token() {
+        let mut contract = Contract::new();
+        let stream_id = create_ft_stream(&mut contract, "usdn.testnet");
+
+        set_context("wrap.testnet".parse().unwrap(), 0, 15);
+        contract.ft_on_transfer(accounts(0), U128(6), topup_msg(stream_id));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::*;
+
+pub const PENDING_UPDATE_EXPIRY: u64 = 86_400; // 1 day
+
+/// Update of an ft stream waiting for the extra tokens it needs, see `update`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingUpdate {
+    start_time: u64,
+    end_time: u64,
+    rate: Balance,
+    amount: Balance, // tokens to deposit
+    expires_at: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn cancel_pending_update(&mut self, stream_id: U64) {
+        let stream = self.internal_get_stream(stream_id.0);
+        require!(
+            env::predecessor_account_id() == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(
+            self.pending_updates.remove(&stream_id.0).is_some(),
+            "No pending update"
+        );
+        log_event!("Pending update cancelled: {}", stream_id.0);
+    }
+
+    pub fn get_pending_update(&self, stream_id: U64) -> Option<PendingUpdate> {
+        self.pending_updates.get(&stream_id.0)
+    }
+}
+
+impl Contract {
+    /// Keeps the update of an ft stream that needs `amount` more tokens until
+    /// they are deposited, replacing any previous pending update.
+    pub(crate) fn internal_add_pending_update(&mut self, stream: &Stream, amount: Balance) {
+        let expires_at = current_time() + PENDING_UPDATE_EXPIRY;
+        self.pending_updates.insert(
+            &stream.id,
+            &PendingUpdate {
+                start_time: stream.start_time,
+                end_time: stream.end_time,
+                rate: stream.rate,
+                amount,
+                expires_at: compact_timestamp(expires_at),
+            },
+        );
+        log_event!("Pending update: {} {} {}", stream.id, amount, expires_at);
+    }
+
+    /// Applies the pending update of the stream with the tokens deposited by
+    /// `ft_transfer_call`, checking the stream can still be updated.
+    pub(crate) fn internal_apply_update(
+        &mut self,
+        stream_id: u64,
+        sender: AccountId,
+        token: AccountId,
+        amount: Balance,
+    ) {
+        let current_timestamp: u64 = current_time();
+        let pending = self
+            .pending_updates
+            .get(&stream_id)
+            .expect("No pending update");
+        let mut stream = self.internal_get_stream(stream_id);
+
+        require!(
+            sender == stream.sender,
+            "You are not authorized to update this stream"
+        );
+        require!(
+            token == stream.contract_id,
+            "Token does not match the stream"
+        );
+        require!(
+            amount == pending.amount,
+            "The amount provided doesn't match the pending update"
+        );
+        require!(
+            current_timestamp < u64::from(pending.expires_at),
+            "Pending update has expired"
+        );
+
+        // same conditions as `update`, they may have changed since
+        require!(!stream.is_cancelled, "Stream has already been cancelled");
+        require!(
+            stream.start_time > current_timestamp,
+            "Cannot update: stream already started"
+        );
+        require!(
+            pending.start_time >= current_timestamp,
+            "Start time cannot be in the past"
+        );
+
+        self.stream_ends.remove(&(stream.end_time, stream_id));
+        self.stream_ends.insert(&(pending.end_time, stream_id), &());
+
+        stream.start_time = pending.start_time;
+        stream.withdraw_time = pending.start_time;
+        stream.end_time = pending.end_time;
+        stream.rate = pending.rate;
+        stream.balance += amount;
+        self.internal_check_cliff(&stream);
+
+        if let Some(escalation) = self.rate_escalations.get(&stream_id) {
+            require!(
+                (stream.end_time - stream.start_time) / u64::from(escalation.period)
+                    <= MAX_ESCALATION_PERIODS,
+                "Too many escalation periods"
+            );
+        }
+        require!(
+            self.internal_streamed_amount(&stream, stream.start_time, stream.end_time)
+                <= stream.balance,
+            "The amount provided is not enough for the stream"
+        );
+
+        self.internal_save_stream(&stream);
+        self.pending_updates.remove(&stream_id);
+        log_event!("Pending update applied: {}", stream_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_context(predecessor: AccountId, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    // alice -> bob, `rate` USN per second from 10 to 20
+    fn create_ft_stream(contract: &mut Contract, rate: u128) {
+        set_context("usdn.testnet".parse().unwrap(), 0);
+        let msg = format!(
+            r#"{{"method_name":"create_stream","receiver":"{}","stream_rate":"{}","start":"10","end":"20","can_update":true,"can_cancel":true}}"#,
+            accounts(1),
+            rate,
+        );
+        contract.ft_on_transfer(accounts(0), U128(10 * rate), msg);
+    }
+
+    #[test]
+    fn apply_funded_update() {
+        let mut contract = Contract::new();
+        let usn = 10u128.pow(18);
+        create_ft_stream(&mut contract, usn);
+
+        // doubling the rate waits for 10 more USN
+        set_context(accounts(0), 1);
+        contract.update(U64(1), None, None, Some(U128(2 * usn)));
+        assert_eq!(contract.internal_get_stream(1).rate, usn);
+        assert!(contract.get_pending_update(U64(1)).is_some());
+
+        set_context("usdn.testnet".parse().unwrap(), 2);
+        let msg = r#"{"method_name":"apply_update","stream_id":"1"}"#.to_string();
+        contract.ft_on_transfer(accounts(0), U128(10 * usn), msg);
+
+        let stream = contract.internal_get_stream(1);
+        assert_eq!(stream.rate, 2 * usn);
+        assert_eq!(stream.balance, 20 * usn);
+        assert!(contract.get_pending_update(U64(1)).is_none());
+    }
+
+    #[test]
+    fn ft_update_refunds_deposit() {
+        let mut contract = Contract::new();
+        let usn = 10u128.pow(18);
+        create_ft_stream(&mut contract, usn);
+
+        // the NEAR attached to an ft update goes back to alice
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(accounts(0));
+        builder.attached_deposit(5);
+        builder.block_timestamp(1_000_000_000);
+        testing_env!(builder.build());
+        contract.update(U64(1), None, None, Some(U128(2 * usn)));
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, accounts(0));
+        assert!(matches!(
+            receipts[0].actions[0],
+            VmAction::Transfer { deposit: 5 }
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rate is too high")]
+    fn update_above_token_max_rate() {
+        let mut contract = Contract::new();
+        let usn = 10u128.pow(18);
+        create_ft_stream(&mut contract, usn);
+
+        // below MAX_RATE but above the 100 USN per second of the token
+        set_context(accounts(0), 1);
+        contract.update(U64(1), None, None, Some(U128(101 * usn)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Pending update has expired")]
+    fn apply_expired_update() {
+        let mut contract = Contract::new();
+        let usn = 10u128.pow(18);
+        create_ft_stream(&mut contract, usn);
+
+        set_context(accounts(0), 0);
+        contract.update(
+            U64(1),
+            Some(U64(PENDING_UPDATE_EXPIRY + 10)),
+            Some(U64(PENDING_UPDATE_EXPIRY + 30)),
+            None,
+        );
+
+        set_context("usdn.testnet".parse().unwrap(), PENDING_UPDATE_EXPIRY);
+        let msg = r#"{"method_name":"apply_update","stream_id":"1"}"#.to_string();
+        contract.ft_on_transfer(accounts(0), U128(10 * usn), msg);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::calls::ft_max_rate;
 use crate::*;
 use near_sdk::{near_bindgen, AccountId};
+use std::ops::Bound;
 
 // mainly for `ft_on_transfer`
 #[derive(Deserialize, Serialize, Debug)]
@@ -12,28 +14,333 @@ pub struct StreamView {
     pub end: U64,
     pub can_update: bool,
     pub can_cancel: bool,
+    pub cliff_time: Option<U64>,
+    pub nonce: Option<U64>, // see `create_stream_with_nonce`
+}
+
+// `ft_on_transfer` message to create a stream of the transferred amount
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AmountStreamView {
+    pub method_name: String,
+    pub receiver: AccountId,
+    pub start: U64,
+    pub end: U64,
+    pub can_update: bool,
+    pub can_cancel: bool,
+    pub cliff_time: Option<U64>,
+}
+
+// `ft_on_transfer` message to an existing stream: "donate", "topup" or
+// "apply_update"
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreamMsgView {
+    pub method_name: String,
+    pub stream_id: U64,
+}
+
+// `ft_on_transfer` message to fund a matching pool for ft streams
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchingPoolView {
+    pub method_name: String,
+    pub pool_id: U64,
+}
+
+// one stream of `create_bundle`, `token` is None for native NEAR
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleStreamView {
+    pub token: Option<AccountId>,
+    pub stream_rate: U128,
+    pub start: U64,
+    pub end: U64,
+    pub can_update: bool,
+    pub can_cancel: bool,
+}
+
+// `ft_on_transfer` message to fund a stream of a pending bundle
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleFundingView {
+    pub method_name: String,
+    pub bundle_id: U64,
+}
+
+// `ft_on_transfer` message to deposit tokens for `create_ft_stream`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositView {
+    pub method_name: String,
+}
+
+// one payout to the receiver of a stream, see `get_payments`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentView {
+    pub stream_id: U64,
+    pub sender: AccountId,
+    pub receiver: AccountId,
+    pub contract_id: AccountId, // will be ignored for native stream
+    pub is_native: bool,
+    pub amount: U128,
+    pub timestamp: U64,
+}
+
+// totals of an account in one token and month, see `get_monthly_totals`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MonthlyTotalView {
+    pub token: Option<AccountId>, // None for native NEAR
+    pub streamed_in: U128,
+    pub streamed_out: U128,
+}
+
+// all-time payouts to a receiver in one token, see `get_receiver_statement`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReceiverStatementView {
+    pub received: U128,
+    pub withheld: U128,
+    pub payments: U64,
+}
+
+// how the deployed contract accrues streams, see `get_protocol_spec`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProtocolSpecView {
+    pub version: String,
+    pub time_unit: String,            // of timestamps, durations and rates
+    pub bps_divisor: U64,             // of withholding, escalation and matching ratios
+    pub protocol_fee_bps: u32,        // no fee is charged
+    pub rounding: String,             // of every division
+    pub accrues_at_start_time: bool,  // withdrawals need current time > start_time
+    pub end_time_inclusive: bool,     // default `get_accrual_policy`
+    pub escalation_round_up: bool,    // default `get_accrual_policy`
+    pub cliff: String,                // what vests at `get_cliff_time`
+    pub accrual_periods: Vec<String>, // boundaries of `get_accrual_period`
+    pub remainder: String,            // of streams created from an amount
+    pub max_native_rate: U128,        // exclusive, in yoctoNEAR per second
+    pub tokens: Vec<TokenSpecView>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenSpecView {
+    pub token_id: AccountId,
+    pub decimals: u8,
+    pub max_rate: U128, // exclusive, in the smallest unit per second
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VersionView {
+    pub version: String,
+    pub api_revision: u32, // also sent with every event and notification
+}
+
+// what a stream pays per period from now on, see `get_stream_flow`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreamFlowView {
+    pub per_second: U128,
+    pub per_hour: U128,
+    pub per_day: U128,
+    pub per_month: U128,  // 30 days
+    pub per_year: U128,   // 365 days
+    pub per_day_bps: U64, // of the stream's balance, rounded down
+    pub per_month_bps: U64,
+    pub remaining_seconds: U64,
+}
+
+// filters of `get_streams_by_filter`, status at the current time
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum StreamStatus {
+    Scheduled,
+    Active,
+    Paused,
+    Ended,
+    Cancelled,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum StreamRole {
+    Sender,
+    Receiver,
+}
+
+// a stream with everything attached to it, see `export_streams`
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreamExportView {
+    pub stream: Stream,
+    pub withdraw_cap: Option<WithdrawCap>,
+    pub bond: Option<Bond>,
+    pub rate_escalation: Option<RateEscalation>,
+    pub rate_change: Option<RateChange>,
+    pub withholding: Option<Withholding>,
+    pub matching_pool_id: Option<U64>,
+    pub paired_stream_id: Option<U64>,
+    pub is_netting: bool,
+    pub paused_seconds: Option<U64>, // before the last resume
+    pub remainder: Option<U128>, // paid with the last second, see `create_stream_with_amount`
+    pub cliff_time: Option<U64>,
+    pub accrual_policy: Option<AccrualPolicy>, // None for the default
+    pub accrual_period: Option<AccrualPeriod>,
+    pub pause_quota: Option<PauseQuota>,
+    pub pause_recourse: Option<PauseRecourse>,
+    pub child_stream: Option<ChildStream>,
+    pub parent_stream_id: Option<U64>,
+}
+
+// the stream ids of an account, see `export_accounts`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountExportView {
+    pub account_id: AccountId,
+    pub sender_stream_ids: Vec<U64>,
+    pub receiver_stream_ids: Vec<U64>,
+}
+
+// what a sender still owes in one token, see `get_outstanding_obligations`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ObligationView {
+    pub token: Option<AccountId>, // None for native NEAR
+    pub owed: U128,               // streamed but not withdrawn yet
+    pub scheduled: U128,          // still to be streamed until the end, paused streams included
+    pub streams: u64,
+}
+
+// what `cancel` would pay out now, see `preview_cancel`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CancelPreview {
+    pub receiver_amount: U128,  // paid to the receiver, after withholding
+    pub withheld: U128,         // kept for the withholding account or the child stream
+    pub fee: U128,              // no protocol fee is charged
+    pub sender_amount: U128,    // refunded, ft streams with `ft_claim_sender`
+    pub bond_to_receiver: U128, // a bond posted by the sender goes to the sender otherwise
+}
+
+// what the sender would reclaim from an ended stream now, see `preview_sender_withdraw`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SenderWithdrawPreview {
+    pub sender_amount: U128, // paid to the sender by `withdraw`, 0 if already withdrawn
+    pub receiver_amount: U128, // streamed but not withdrawn, stays for the receiver
+}
+
+// a paused stream with the amount the receiver can still withdraw
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PausedStreamView {
+    pub stream: Stream,
+    pub accrued: U128, // vested before the pause and not withdrawn
 }
 
 #[near_bindgen]
 impl Contract {
     pub fn get_stream(&self, stream_id: U64) -> Stream {
         let id: u64 = stream_id.into();
-        self.streams.get(&id).unwrap()
+        self.internal_get_stream(id)
     }
 
     pub fn get_streams(&self, from_index: Option<U128>, limit: Option<U64>) -> Vec<Stream> {
         let start = u128::from(from_index.unwrap_or(U128(0)));
 
-        self.streams
-            .keys()
+        self.stream_ids
+            .iter()
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(|id| self.internal_get_stream(id))
+            .collect()
+    }
+
+    // `limit` streams from index `from` in creation order, streams created with
+    // a nonce included, for migrating the state off-chain. The format only
+    // changes by adding fields.
+    pub fn export_streams(&self, from: U64, limit: U64) -> Vec<StreamExportView> {
+        self.stream_ids
+            .iter()
+            .skip(from.0 as usize)
+            .take(limit.0 as usize)
+            .map(|id| StreamExportView {
+                stream: self.internal_get_stream(id),
+                withdraw_cap: self.withdraw_caps.get(&id),
+                bond: self.bonds.get(&id),
+                rate_escalation: self.rate_escalations.get(&id),
+                rate_change: self.rate_changes.get(&id),
+                withholding: self.withholdings.get(&id),
+                matching_pool_id: self.stream_matching_pools.get(&id).map(U64),
+                paired_stream_id: self.stream_pairs.get(&id).map(U64),
+                is_netting: self.netting_streams.contains(&id),
+                paused_seconds: self.pause_totals.get(&id).map(U64),
+                remainder: self.stream_remainders.get(&id).map(U128),
+                cliff_time: self.cliff_times.get(&id).map(U64),
+                accrual_policy: self.accrual_policies.get(&id),
+                accrual_period: self.accrual_periods.get(&id),
+                pause_quota: self.pause_quotas.get(&id),
+                pause_recourse: self.pause_recourses.get(&id),
+                child_stream: self.child_streams.get(&id),
+                parent_stream_id: self.parent_streams.get(&id).map(U64),
+            })
+            .collect()
+    }
+
+    // every account that has sent or received a stream, with its stream ids
+    pub fn export_accounts(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<AccountExportView> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        let stream_ids = |ids: Option<UnorderedSet<u64>>| {
+            ids.map_or(vec![], |ids| ids.iter().map(U64).collect())
+        };
+
+        self.accounts
+            .iter()
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(|account_id| AccountExportView {
+                sender_stream_ids: stream_ids(self.sender_streams.get(&account_id)),
+                receiver_stream_ids: stream_ids(self.receiver_streams.get(&account_id)),
+                account_id,
+            })
+            .collect()
+    }
+
+    // every account that has sent or received a stream, in registration order
+    pub fn get_registered_accounts(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<AccountId> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.accounts
+            .iter()
             // skip to start
             .skip(start as usize)
             // take the first `limit` elements in the vec
             .take(limit.unwrap_or(U64(50)).0 as usize)
-            .map(|id| self.streams.get(&id).unwrap())
             .collect()
     }
 
+    pub fn get_registered_accounts_count(&self) -> U64 {
+        U64(self.accounts.len())
+    }
+
     pub fn get_streams_by_user(
         &self,
         user_id: AccountId,
@@ -42,16 +349,542 @@ impl Contract {
     ) -> Vec<Stream> {
         let start = u128::from(from_index.unwrap_or(U128(0)));
 
-        self.streams
-            .keys()
+        let stream_ids = match self.sender_streams.get(&user_id) {
+            Some(stream_ids) => stream_ids,
+            None => return vec![],
+        };
+
+        stream_ids
+            .iter()
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(|id| self.internal_get_stream(id))
+            .collect()
+    }
+
+    // streams of the account matching every filter that is set, as sender then as
+    // receiver. `token` is a token contract or "NEAR" for native streams.
+    pub fn get_streams_by_filter(
+        &self,
+        account_id: AccountId,
+        status: Option<StreamStatus>,
+        token: Option<String>,
+        role: Option<StreamRole>,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<Stream> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        let current_timestamp: u64 = current_time();
+
+        let sender_ids = match role {
+            Some(StreamRole::Receiver) => None,
+            _ => self.sender_streams.get(&account_id),
+        };
+        let receiver_ids = match role {
+            Some(StreamRole::Sender) => None,
+            _ => self.receiver_streams.get(&account_id),
+        };
+
+        sender_ids
+            .iter()
+            .chain(receiver_ids.iter())
+            .flat_map(|stream_ids| stream_ids.iter())
+            .map(|id| self.internal_get_stream(id))
+            .filter(|stream| match &token {
+                Some(token) if token == "NEAR" => stream.is_native,
+                Some(token) => !stream.is_native && stream.contract_id.as_str() == token,
+                None => true,
+            })
+            .filter(|stream| {
+                status.is_none()
+                    || status.as_ref()
+                        == Some(&stream_status(
+                            stream,
+                            current_timestamp,
+                            self.internal_has_ended(stream, current_timestamp),
+                        ))
+            })
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .collect()
+    }
+
+    // streams of the account (as sender or receiver) ending in the next `within`
+    // seconds, soonest first. Cancelled streams are not listed.
+    pub fn get_streams_ending_soon(
+        &self,
+        account_id: AccountId,
+        within: U64,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<Stream> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        let current_timestamp: u64 = current_time();
+
+        self.stream_ends
+            .range((
+                Bound::Excluded((current_timestamp, u64::MAX)),
+                Bound::Included((current_timestamp + within.0, u64::MAX)),
+            ))
+            .map(|((_, id), _)| self.internal_get_stream(id))
+            .filter(|stream| stream.sender == account_id || stream.receiver == account_id)
             // skip to start
             .skip(start as usize)
             // take the first `limit` elements in the vec
             .take(limit.unwrap_or(U64(50)).0 as usize)
-            .map(|id| self.streams.get(&id).unwrap())
-            .filter(|stream| stream.sender == user_id)
             .collect()
     }
+
+    // paused streams of the account, first as sender then as receiver
+    pub fn get_paused_streams(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<PausedStreamView> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        let current_timestamp: u64 = current_time();
+
+        let sender_ids = self.sender_streams.get(&account_id);
+        let receiver_ids = self.receiver_streams.get(&account_id);
+
+        sender_ids
+            .iter()
+            .chain(receiver_ids.iter())
+            .flat_map(|stream_ids| stream_ids.iter())
+            .map(|id| self.internal_get_stream(id))
+            .filter(|stream| stream.is_paused && !stream.is_cancelled)
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(|stream| {
+                // only what has vested, nothing before the cliff
+                let vested_time = self
+                    .internal_vested_time(&stream, current_timestamp)
+                    .max(stream.withdraw_time);
+                PausedStreamView {
+                    accrued: U128(self.internal_streamed_amount(
+                        &stream,
+                        stream.withdraw_time,
+                        stream.paused_time.min(vested_time),
+                    )),
+                    stream,
+                }
+            })
+            .collect()
+    }
+
+    // undelivered amounts of the sender's streams that are not cancelled, per token
+    pub fn get_outstanding_obligations(&self, sender: AccountId) -> Vec<ObligationView> {
+        let current_timestamp: u64 = current_time();
+        let mut obligations: Vec<ObligationView> = vec![];
+
+        let stream_ids = match self.sender_streams.get(&sender) {
+            Some(stream_ids) => stream_ids,
+            None => return obligations,
+        };
+
+        for stream in stream_ids.iter().map(|id| self.internal_get_stream(id)) {
+            if stream.is_cancelled || stream.withdraw_time >= stream.end_time {
+                continue;
+            }
+
+            let vested_time = self.internal_vested_time(&stream, current_timestamp);
+            let accrued_until = if stream.is_paused {
+                stream
+                    .paused_time
+                    .min(vested_time.max(stream.withdraw_time))
+            } else {
+                vested_time.clamp(stream.withdraw_time, stream.end_time)
+            };
+            let owed = self.internal_streamed_amount(&stream, stream.withdraw_time, accrued_until);
+            let scheduled = self.internal_streamed_amount(
+                &stream,
+                current_timestamp.clamp(stream.start_time, stream.end_time),
+                stream.end_time,
+            );
+            if owed == 0 && scheduled == 0 {
+                continue;
+            }
+
+            let token = if stream.is_native {
+                None
+            } else {
+                Some(stream.contract_id.clone())
+            };
+            let obligation = match obligations.iter_mut().position(|o| o.token == token) {
+                Some(i) => &mut obligations[i],
+                None => {
+                    obligations.push(ObligationView {
+                        token,
+                        owed: U128(0),
+                        scheduled: U128(0),
+                        streams: 0,
+                    });
+                    obligations.last_mut().unwrap()
+                }
+            };
+            obligation.owed.0 += owed;
+            obligation.scheduled.0 += scheduled;
+            obligation.streams += 1;
+        }
+        obligations
+    }
+
+    // amounts paid out to (streamed_in) and by (streamed_out) the account's streams
+    // in the calendar month, per token with any activity
+    pub fn get_monthly_totals(
+        &self,
+        account_id: AccountId,
+        year: u32,
+        month: u8,
+    ) -> Vec<MonthlyTotalView> {
+        require!((1..=12).contains(&month), "Invalid month");
+        let month = year * 12 + u32::from(month) - 1;
+
+        let ft_tokens = FT_TOKENS.iter().map(|(id, _)| Some(id.parse().unwrap()));
+        std::iter::once(None)
+            .chain(ft_tokens)
+            .filter_map(|token: Option<AccountId>| {
+                let total = self
+                    .monthly_totals
+                    .get(&(account_id.clone(), token.clone(), month))?;
+                Some(MonthlyTotalView {
+                    token,
+                    streamed_in: U128(total.streamed_in),
+                    streamed_out: U128(total.streamed_out),
+                })
+            })
+            .collect()
+    }
+
+    // amounts received (net of withholding) and withheld by the receiver's
+    // streams in `token` (None for NEAR) since they were created
+    pub fn get_receiver_statement(
+        &self,
+        receiver: AccountId,
+        token: Option<AccountId>,
+    ) -> ReceiverStatementView {
+        let statement = self
+            .receiver_statements
+            .get(&(receiver, token))
+            .unwrap_or_default();
+        ReceiverStatementView {
+            received: U128(statement.received),
+            withheld: U128(statement.withheld),
+            payments: U64(statement.payments),
+        }
+    }
+
+    // Accrual constants and semantics of this version: a stream accrues
+    // `rate * (min(now, end_time) - start_time)` for whole seconds
+    pub fn get_protocol_spec(&self) -> ProtocolSpecView {
+        let policy = AccrualPolicy::default();
+        ProtocolSpecView {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            time_unit: "second".to_string(),
+            bps_divisor: U64(10_000),
+            protocol_fee_bps: 0,
+            rounding: "down".to_string(),
+            accrues_at_start_time: false,
+            end_time_inclusive: policy.end_inclusive,
+            escalation_round_up: policy.round_up,
+            cliff: "nothing before cliff_time, then everything since start_time".to_string(),
+            accrual_periods: vec![
+                "day: at 00:00 UTC".to_string(),
+                "week: on Monday 00:00 UTC".to_string(),
+                "month: on the 1st 00:00 UTC".to_string(),
+            ],
+            remainder: "with the last second before end_time".to_string(),
+            max_native_rate: U128(MAX_RATE),
+            tokens: FT_TOKENS
+                .iter()
+                .map(|(token_id, decimals)| TokenSpecView {
+                    token_id: token_id.parse().unwrap(),
+                    decimals: *decimals,
+                    max_rate: U128(ft_max_rate(*decimals)),
+                })
+                .collect(),
+        }
+    }
+
+    // id of the stream `sender` creates with `create_stream_with_nonce`
+    pub fn get_nonce_stream_id(&self, sender: AccountId, nonce: U64) -> U64 {
+        U64(nonce_stream_id(&sender, nonce.0))
+    }
+
+    // Crate version and API revision of the deployed contract, the revision
+    // only increases so that clients can compare it across deployments
+    pub fn get_version(&self) -> VersionView {
+        VersionView {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            api_revision: API_REVISION,
+        }
+    }
+
+    // Amounts the stream pays over the next second, hour, day, month and year
+    // from now (or its start) until its end, with the contract's own accrual
+    // including rate changes and escalations. The bps are of its balance.
+    pub fn get_stream_flow(&self, stream_id: U64) -> StreamFlowView {
+        let stream = self.internal_get_stream(stream_id.0);
+        let from = current_time().max(stream.start_time).min(stream.end_time);
+        let flow = |seconds: u64| {
+            let to = (from + seconds).min(stream.end_time);
+            self.internal_streamed_amount(&stream, from, to)
+        };
+        let bps = |amount: Balance| {
+            let bps = amount
+                .saturating_mul(10_000)
+                .checked_div(stream.balance)
+                .unwrap_or(0);
+            U64(bps.min(u64::MAX.into()) as u64)
+        };
+
+        let per_day = flow(86_400);
+        let per_month = flow(30 * 86_400);
+        StreamFlowView {
+            per_second: U128(flow(1)),
+            per_hour: U128(flow(3_600)),
+            per_day: U128(per_day),
+            per_month: U128(per_month),
+            per_year: U128(flow(365 * 86_400)),
+            per_day_bps: bps(per_day),
+            per_month_bps: bps(per_month),
+            remaining_seconds: U64(stream.end_time.saturating_sub(from)),
+        }
+    }
+
+    pub fn get_matching_pool(&self, pool_id: U64) -> Option<MatchingPool> {
+        self.matching_pools.get(pool_id.0)
+    }
+
+    pub fn get_subscription(&self, account_id: AccountId) -> Option<Subscription> {
+        self.subscriptions.get(&account_id)
+    }
+
+    pub fn get_backup(&self, stream_id: U64) -> Option<Backup> {
+        self.backups.get(&stream_id.0)
+    }
+
+    pub fn get_payout_schedule(&self, account_id: AccountId) -> Option<U64> {
+        self.payout_intervals
+            .get(&account_id)
+            .map(|interval| U64(interval.into()))
+    }
+
+    pub fn get_sender_filter(&self, receiver: AccountId) -> Option<SenderFilter> {
+        self.sender_filters.get(&receiver)
+    }
+
+    pub fn is_sender_allowed(&self, receiver: AccountId, sender: AccountId) -> bool {
+        self.internal_is_sender_allowed(&receiver, &sender)
+    }
+
+    pub fn get_renewal_window(&self, sender: AccountId) -> Option<U64> {
+        self.renewal_windows
+            .get(&sender)
+            .map(|window| U64(window.into()))
+    }
+
+    // streams reported as ending soon that have not ended yet
+    pub fn get_renewal_reminders(&self, from_index: Option<U128>, limit: Option<U64>) -> Vec<U64> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.ending_soon
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(U64)
+            .collect()
+    }
+
+    pub fn get_withdraw_callback(&self, stream_id: U64) -> bool {
+        self.withdraw_callbacks.contains(&stream_id.0)
+    }
+
+    // whether `nonce` can no longer be used by the account on the stream: a
+    // `withdraw` with it or a later nonce was executed, so a nonce that was
+    // never submitted is reported as used too once it has been superseded
+    pub fn is_withdrawal_nonce_used(
+        &self,
+        stream_id: U64,
+        account_id: AccountId,
+        nonce: U64,
+    ) -> bool {
+        self.withdraw_nonces
+            .get(&(stream_id.0, account_id))
+            .is_some_and(|last| nonce.0 <= last)
+    }
+
+    pub fn get_bundle(&self, bundle_id: U64) -> Option<Bundle> {
+        self.bundles.get(bundle_id.0)
+    }
+
+    pub fn get_paired_stream(&self, stream_id: U64) -> Option<U64> {
+        self.stream_pairs.get(&stream_id.0).map(U64)
+    }
+
+    pub fn get_rate_escalation(&self, stream_id: U64) -> Option<RateEscalation> {
+        self.rate_escalations.get(&stream_id.0)
+    }
+
+    pub fn get_withholding(&self, stream_id: U64) -> Option<Withholding> {
+        self.withholdings.get(&stream_id.0)
+    }
+
+    // amount withheld for the account and not claimed yet, token None for NEAR
+    pub fn get_withheld_balance(&self, account_id: AccountId, token: Option<AccountId>) -> U128 {
+        U128(
+            self.withheld_balances
+                .get(&(account_id, token))
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn get_rate_change(&self, stream_id: U64) -> Option<RateChange> {
+        self.rate_changes.get(&stream_id.0)
+    }
+
+    // reason given for the last pause or cancel of the stream, if any
+    pub fn get_stop_reason(&self, stream_id: U64) -> Option<String> {
+        self.stop_reasons.get(&stream_id.0)
+    }
+
+    pub fn get_bond(&self, stream_id: U64) -> Option<Bond> {
+        self.bonds.get(&stream_id.0)
+    }
+
+    // payouts to the receivers of the account's streams (as sender or receiver)
+    // with `from_time <= timestamp < to_time`, oldest first
+    pub fn get_payments(
+        &self,
+        account_id: AccountId,
+        from_time: U64,
+        to_time: U64,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<PaymentView> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        let payment_ids = match self.account_payments.get(&account_id) {
+            Some(payment_ids) => payment_ids,
+            None => return vec![],
+        };
+        let payment_at = |index: u64| self.payments.get(payment_ids.get(index).unwrap()).unwrap();
+
+        // payments are appended in time order, find the first one in the range
+        let (mut low, mut high) = (0, payment_ids.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if u64::from(payment_at(mid).timestamp) < from_time.0 {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        (low..payment_ids.len())
+            .map(payment_at)
+            .take_while(|payment| u64::from(payment.timestamp) < to_time.0)
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(|payment| {
+                let stream = self.internal_get_stream(payment.stream_id);
+                PaymentView {
+                    stream_id: U64(payment.stream_id),
+                    sender: stream.sender,
+                    receiver: payment.receiver,
+                    contract_id: stream.contract_id,
+                    is_native: stream.is_native,
+                    amount: U128(payment.amount),
+                    timestamp: U64(payment.timestamp.into()),
+                }
+            })
+            .collect()
+    }
+
+    // what cancelling the stream now would pay, a paired stream is cancelled with
+    // its own amounts
+    pub fn preview_cancel(&self, stream_id: U64) -> CancelPreview {
+        let current_timestamp: u64 = current_time();
+        let stream = self.internal_get_stream(stream_id.0);
+
+        // same conditions as `cancel`
+        require!(
+            stream.can_cancel || current_timestamp < stream.start_time,
+            "Stream cannot be cancelled"
+        );
+        require!(
+            !self.internal_has_ended(&stream, current_timestamp),
+            "Stream already ended"
+        );
+        require!(!stream.is_cancelled, "already cancelled!");
+
+        let (receiver_amount, sender_amount) =
+            self.internal_cancel_amounts(&stream, current_timestamp);
+        let withheld = self.internal_withheld_amount(&stream, receiver_amount);
+        let bond_to_receiver = self
+            .bonds
+            .get(&stream.id)
+            .filter(|bond| Self::internal_bond_forfeited(&stream, bond, current_timestamp))
+            .map_or(0, |bond| bond.amount);
+
+        CancelPreview {
+            receiver_amount: U128(receiver_amount - withheld),
+            withheld: U128(withheld),
+            fee: U128(0),
+            sender_amount: U128(sender_amount),
+            bond_to_receiver: U128(bond_to_receiver),
+        }
+    }
+
+    // what the sender would reclaim by withdrawing from the ended stream now,
+    // the time a paused stream was paused for does not count as streamed
+    pub fn preview_sender_withdraw(&self, stream_id: U64) -> SenderWithdrawPreview {
+        let current_timestamp: u64 = current_time();
+        let stream = self.internal_get_stream(stream_id.0);
+
+        // same conditions as the sender's `withdraw`
+        require!(
+            !stream.is_cancelled,
+            "Stream is cancelled by sender already!"
+        );
+        require!(
+            self.internal_can_reclaim(&stream, current_timestamp),
+            "Cannot withdraw before the stream has ended"
+        );
+
+        let (receiver_amount, sender_amount) = self.internal_sender_reclaim(&stream);
+        SenderWithdrawPreview {
+            sender_amount: U128(sender_amount),
+            receiver_amount: U128(receiver_amount),
+        }
+    }
+}
+
+pub(crate) fn stream_status(
+    stream: &Stream,
+    current_timestamp: u64,
+    has_ended: bool,
+) -> StreamStatus {
+    if stream.is_cancelled {
+        StreamStatus::Cancelled
+    } else if has_ended {
+        StreamStatus::Ended
+    } else if stream.is_paused {
+        StreamStatus::Paused
+    } else if current_timestamp < stream.start_time {
+        StreamStatus::Scheduled
+    } else {
+        StreamStatus::Active
+    }
 }
 
 #[cfg(test)]
@@ -67,7 +900,7 @@ mod tests {
     fn initializes() {
         let contract = Contract::new();
         assert_eq!(contract.current_id, 1);
-        assert_eq!(contract.streams.len(), 0);
+        assert_eq!(contract.stream_ids.len(), 0);
     }
     fn set_context_with_balance(predecessor: AccountId, amount: Balance) {
         let mut builder = VMContextBuilder::new();
@@ -89,10 +922,18 @@ mod tests {
 
         set_context_with_balance(sender.clone(), 172800 * NEAR);
 
-        contract.create_stream(receiver.clone(), rate, start_time, end_time, false, false);
+        contract.create_stream(
+            receiver.clone(),
+            rate,
+            start_time,
+            end_time,
+            false,
+            false,
+            None,
+        );
         assert_eq!(contract.current_id, 2);
         let params_key = 1;
-        let stream = contract.streams.get(&params_key).unwrap();
+        let stream = contract.internal_get_stream(params_key);
         require!(!stream.is_paused);
         assert_eq!(stream.id, 1);
         assert_eq!(stream.sender, sender.clone());
@@ -110,4 +951,687 @@ mod tests {
         let res_stream = contract.get_stream(near_sdk::json_types::U64(stream.id));
         println!("{}", res_stream.id);
     }
+
+    #[test]
+    fn test_get_streams_by_user() {
+        let start = env::block_timestamp();
+        let start_time: U64 = U64::from(start);
+        let end_time: U64 = U64::from(start + 10);
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob, charlie -> bob, alice -> charlie
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(accounts(1), rate, start_time, end_time, false, false, None);
+        set_context_with_balance(accounts(2), 10 * NEAR);
+        contract.create_stream(accounts(1), rate, start_time, end_time, false, false, None);
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(accounts(2), rate, start_time, end_time, false, false, None);
+
+        assert_eq!(contract.get_streams(None, None).len(), 3);
+        assert_eq!(contract.get_streams(Some(U128(1)), Some(U64(1)))[0].id, 2);
+
+        let streams = contract.get_streams_by_user(accounts(0), None, None);
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].id, 1);
+        assert_eq!(streams[1].id, 3);
+
+        let streams = contract.get_streams_by_user(accounts(0), Some(U128(1)), None);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].id, 3);
+
+        assert!(contract
+            .get_streams_by_user(accounts(3), None, None)
+            .is_empty());
+        assert_eq!(
+            contract
+                .receiver_streams
+                .get(&accounts(1))
+                .unwrap()
+                .to_vec(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_get_payments() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob for 10 seconds, cancellable
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            true,
+            false,
+            None,
+        );
+
+        // bob withdraws twice, then alice cancels
+        set_context_with_balance_timestamp(accounts(1), 0, start + 2);
+        contract.withdraw(U64(1), None);
+        set_context_with_balance_timestamp(accounts(1), 0, start + 5);
+        contract.withdraw(U64(1), None);
+        set_context_with_balance_timestamp(accounts(0), 0, start + 7);
+        contract.cancel(U64(1), None);
+
+        let payments = contract.get_payments(accounts(1), U64(0), U64(100), None, None);
+        let amounts: Vec<u128> = payments.iter().map(|p| p.amount.0).collect();
+        assert_eq!(amounts, vec![2 * NEAR, 3 * NEAR, 2 * NEAR]);
+        assert_eq!(payments[2].timestamp, U64(start + 7));
+        assert_eq!(payments[0].sender, accounts(0));
+        assert!(payments[0].is_native);
+
+        // the sender sees the same payments
+        let payments = contract.get_payments(accounts(0), U64(0), U64(100), None, None);
+        assert_eq!(payments.len(), 3);
+
+        // time range and pagination
+        let payments =
+            contract.get_payments(accounts(1), U64(start + 3), U64(start + 7), None, None);
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].amount, U128(3 * NEAR));
+
+        let payments = contract.get_payments(
+            accounts(1),
+            U64(start + 2),
+            U64(100),
+            Some(U128(1)),
+            Some(U64(1)),
+        );
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].timestamp, U64(start + 5));
+
+        let payments = contract.get_payments(accounts(2), U64(0), U64(100), None, None);
+        assert!(payments.is_empty());
+    }
+
+    #[test]
+    fn test_get_streams_ending_soon() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob ending at 10, 20 and 30, charlie -> bob ending at 10
+        for end in [30, 10, 20] {
+            set_context_with_balance(accounts(0), end * NEAR);
+            contract.create_stream(
+                accounts(1),
+                rate,
+                U64(start),
+                U64(start + end as u64),
+                true,
+                true,
+                None,
+            );
+        }
+        set_context_with_balance(accounts(2), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+
+        let ids = |streams: Vec<Stream>| streams.iter().map(|s| s.id).collect::<Vec<u64>>();
+
+        set_context_with_balance_timestamp(accounts(0), 0, start + 5);
+        let streams = contract.get_streams_ending_soon(accounts(0), U64(15), None, None);
+        assert_eq!(ids(streams), vec![2, 3]);
+        let streams = contract.get_streams_ending_soon(accounts(1), U64(5), None, None);
+        assert_eq!(ids(streams), vec![2, 4]);
+
+        // cancelled streams are dropped, updated ones are moved
+        contract.cancel(U64(2), None);
+        let streams = contract.get_streams_ending_soon(accounts(0), U64(100), None, Some(U64(1)));
+        assert_eq!(ids(streams), vec![3]);
+
+        // ended streams are not listed
+        set_context_with_balance_timestamp(accounts(0), 0, start + 20);
+        let streams = contract.get_streams_ending_soon(accounts(0), U64(100), None, None);
+        assert_eq!(ids(streams), vec![1]);
+    }
+
+    #[test]
+    fn test_export() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob, alice -> charlie, bob -> charlie
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+        contract.create_stream(
+            accounts(2),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+        set_context_with_balance(accounts(1), 10 * NEAR);
+        contract.create_stream(
+            accounts(2),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+
+        let streams = contract.export_streams(U64(0), U64(2));
+        let ids: Vec<u64> = streams.iter().map(|s| s.stream.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(streams[0].bond.is_none());
+        assert!(streams[0].remainder.is_none());
+        assert_eq!(contract.export_streams(U64(2), U64(100)).len(), 1);
+
+        let accounts_export = contract.export_accounts(None, None);
+        let account_ids: Vec<AccountId> = accounts_export
+            .iter()
+            .map(|a| a.account_id.clone())
+            .collect();
+        assert_eq!(account_ids, vec![accounts(0), accounts(1), accounts(2)]);
+        assert_eq!(accounts_export[1].sender_stream_ids, vec![U64(3)]);
+        assert_eq!(accounts_export[1].receiver_stream_ids, vec![U64(1)]);
+        assert_eq!(contract.export_accounts(Some(U128(2)), None).len(), 1);
+
+        assert_eq!(contract.get_registered_accounts_count(), U64(3));
+        assert_eq!(
+            contract.get_registered_accounts(Some(U128(1)), Some(U64(1))),
+            vec![accounts(1)]
+        );
+    }
+
+    #[test]
+    fn test_get_streams_by_filter() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob now and later, bob -> alice now
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            true,
+            false,
+            None,
+        );
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start + 5),
+            U64(start + 15),
+            true,
+            false,
+            None,
+        );
+        set_context_with_balance(accounts(1), 10 * NEAR);
+        contract.create_stream(
+            accounts(0),
+            rate,
+            U64(start),
+            U64(start + 10),
+            true,
+            false,
+            None,
+        );
+
+        set_context_with_balance_timestamp(accounts(1), 0, start + 2);
+        let ids = |streams: Vec<Stream>| streams.iter().map(|s| s.id).collect::<Vec<u64>>();
+
+        let streams = contract.get_streams_by_filter(
+            accounts(1),
+            Some(StreamStatus::Active),
+            Some("NEAR".to_string()),
+            Some(StreamRole::Receiver),
+            None,
+            None,
+        );
+        assert_eq!(ids(streams), vec![1]);
+
+        let streams = contract.get_streams_by_filter(
+            accounts(1),
+            Some(StreamStatus::Scheduled),
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(ids(streams), vec![2]);
+
+        let streams = contract.get_streams_by_filter(accounts(1), None, None, None, None, None);
+        assert_eq!(ids(streams), vec![3, 1, 2]);
+
+        let streams = contract.get_streams_by_filter(
+            accounts(1),
+            None,
+            Some("usdn.testnet".to_string()),
+            None,
+            None,
+            None,
+        );
+        assert!(streams.is_empty());
+    }
+
+    #[test]
+    fn test_get_paused_streams() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob twice, bob -> charlie
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+        set_context_with_balance(accounts(1), 10 * NEAR);
+        contract.create_stream(
+            accounts(2),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+
+        // bob withdraws from the 2nd stream, then both of alice's streams are paused
+        set_context_with_balance_timestamp(accounts(1), 0, start + 2);
+        contract.withdraw(U64(2), None);
+        set_context_with_balance_timestamp(accounts(0), 0, start + 4);
+        contract.pause(U64(1), None);
+        contract.pause(U64(2), None);
+        set_context_with_balance_timestamp(accounts(1), 0, start + 5);
+        contract.pause(U64(3), None);
+
+        let paused = contract.get_paused_streams(accounts(1), None, None);
+        let ids: Vec<u64> = paused.iter().map(|p| p.stream.id).collect();
+        assert_eq!(ids, vec![3, 1, 2]);
+        assert_eq!(paused[0].accrued, U128(5 * NEAR));
+        assert_eq!(paused[1].accrued, U128(4 * NEAR));
+        assert_eq!(paused[2].accrued, U128(2 * NEAR));
+        assert_eq!(paused[2].stream.paused_time, start + 4);
+
+        // resumed streams are not listed
+        set_context_with_balance_timestamp(accounts(0), 0, start + 6);
+        contract.resume(U64(1));
+        let paused = contract.get_paused_streams(accounts(0), None, None);
+        assert_eq!(paused.len(), 1);
+        assert_eq!(paused[0].stream.id, 2);
+
+        assert!(contract
+            .get_paused_streams(accounts(3), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_outstanding_obligations() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob twice, one is cancelled
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            true,
+            false,
+            None,
+        );
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            true,
+            false,
+            None,
+        );
+        contract.cancel(U64(2), None);
+
+        // 4 seconds streamed, bob withdrew 3 of them
+        set_context_with_balance_timestamp(accounts(1), 0, start + 3);
+        contract.withdraw(U64(1), None);
+        set_context_with_balance_timestamp(accounts(1), 0, start + 4);
+
+        let obligations = contract.get_outstanding_obligations(accounts(0));
+        assert_eq!(obligations.len(), 1);
+        assert_eq!(obligations[0].token, None);
+        assert_eq!(obligations[0].owed, U128(NEAR));
+        assert_eq!(obligations[0].scheduled, U128(6 * NEAR));
+        assert_eq!(obligations[0].streams, 1);
+
+        assert!(contract.get_outstanding_obligations(accounts(1)).is_empty());
+    }
+
+    #[test]
+    fn test_preview_cancel() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob, a quarter of the payouts withheld for charlie
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start + 1),
+            U64(start + 11),
+            true,
+            true,
+            None,
+        );
+        set_context_with_balance(accounts(0), 0);
+        contract.set_withholding(U64(1), Some(accounts(2)), 2_500);
+
+        set_context_with_balance_timestamp(accounts(0), 0, start + 5);
+        let preview = contract.preview_cancel(U64(1));
+        assert_eq!(preview.receiver_amount, U128(3 * NEAR));
+        assert_eq!(preview.withheld, U128(NEAR));
+        assert_eq!(preview.fee, U128(0));
+        assert_eq!(preview.sender_amount, U128(6 * NEAR));
+        assert_eq!(preview.bond_to_receiver, U128(0));
+
+        // the cancel pays the previewed amounts
+        contract.cancel(U64(1), None);
+        let statement = contract.get_receiver_statement(accounts(1), None);
+        assert_eq!(statement.received, preview.receiver_amount);
+        assert_eq!(statement.withheld, preview.withheld);
+    }
+
+    #[test]
+    fn test_preview_sender_withdraw() {
+        let start = env::block_timestamp();
+        let rate = U128::from(NEAR);
+
+        let mut contract = Contract::new();
+
+        // alice -> bob for 10 seconds, paused from 4 to 7
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            rate,
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+        set_context_with_balance_timestamp(accounts(0), 0, start + 4);
+        contract.pause(U64(1), None);
+        set_context_with_balance_timestamp(accounts(0), 0, start + 7);
+        contract.resume(U64(1));
+
+        // bob is owed the 7 seconds streamed, alice the 3 paused
+        set_context_with_balance_timestamp(accounts(0), 0, start + 12);
+        let preview = contract.preview_sender_withdraw(U64(1));
+        assert_eq!(preview.sender_amount, U128(3 * NEAR));
+        assert_eq!(preview.receiver_amount, U128(7 * NEAR));
+
+        contract.withdraw(U64(1), None);
+        assert_eq!(contract.get_stream(U64(1)).balance, 7 * NEAR);
+        let preview = contract.preview_sender_withdraw(U64(1));
+        assert_eq!(preview.sender_amount, U128(0));
+    }
+
+    #[test]
+    fn test_get_monthly_totals() {
+        let start = env::block_timestamp();
+        let day = 86_400;
+
+        let mut contract = Contract::new();
+
+        // alice -> bob for 10 seconds, bob -> charlie for 40 days
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            U128(NEAR),
+            U64(start),
+            U64(start + 10),
+            false,
+            false,
+            None,
+        );
+        set_context_with_balance(accounts(1), 40 * day as u128 * 1_000);
+        contract.create_stream(
+            accounts(2),
+            U128(1_000),
+            U64(start),
+            U64(start + 40 * day),
+            false,
+            false,
+            None,
+        );
+
+        // withdraw in January and February 1970
+        set_context_with_balance_timestamp(accounts(1), 0, start + 4);
+        contract.withdraw(U64(1), None);
+        set_context_with_balance_timestamp(accounts(2), 0, start + 3);
+        contract.withdraw(U64(2), None);
+        set_context_with_balance_timestamp(accounts(2), 0, start + 32 * day);
+        contract.withdraw(U64(2), None);
+
+        let totals = contract.get_monthly_totals(accounts(1), 1970, 1);
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].token, None);
+        assert_eq!(totals[0].streamed_in, U128(4 * NEAR));
+        assert_eq!(totals[0].streamed_out, U128(3_000));
+
+        let totals = contract.get_monthly_totals(accounts(2), 1970, 2);
+        assert_eq!(totals[0].streamed_in, U128((32 * day as u128 - 3) * 1_000));
+        assert_eq!(totals[0].streamed_out, U128(0));
+
+        assert!(contract.get_monthly_totals(accounts(0), 1970, 2).is_empty());
+    }
+
+    #[test]
+    fn test_get_stream_flow() {
+        let mut contract = Contract::new();
+
+        // 1 NEAR per second for 10 days
+        set_context_with_balance(accounts(0), 864_000 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            U128(NEAR),
+            U64(0),
+            U64(864_000),
+            false,
+            false,
+            None,
+        );
+
+        let flow = contract.get_stream_flow(U64(1));
+        assert_eq!(flow.per_second, U128(NEAR));
+        assert_eq!(flow.per_hour, U128(3_600 * NEAR));
+        assert_eq!(flow.per_day, U128(86_400 * NEAR));
+        assert_eq!(flow.per_day_bps, U64(1_000));
+        assert_eq!(flow.per_month, U128(864_000 * NEAR));
+        assert_eq!(flow.per_month_bps, U64(10_000));
+        assert_eq!(flow.remaining_seconds, U64(864_000));
+    }
+
+    #[test]
+    fn test_get_protocol_spec() {
+        let contract = Contract::new();
+
+        let spec = contract.get_protocol_spec();
+        assert_eq!(spec.time_unit, "second");
+        assert_eq!(spec.bps_divisor, U64(10_000));
+        assert_eq!(spec.max_native_rate, U128(MAX_RATE));
+        assert_eq!(
+            spec.end_time_inclusive,
+            contract.get_accrual_policy(U64(0)).end_inclusive
+        );
+        assert_eq!(spec.accrual_periods.len(), 3);
+        assert_eq!(spec.tokens.len(), FT_TOKENS.len());
+        assert_eq!(spec.tokens[0].max_rate, U128(100 * 10u128.pow(18)));
+    }
+
+    #[test]
+    fn test_get_version() {
+        let contract = Contract::new();
+
+        let version = contract.get_version();
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(version.api_revision, API_REVISION);
+    }
+
+    #[test]
+    fn test_get_receiver_statement() {
+        let start = env::block_timestamp();
+        let mut contract = Contract::new();
+
+        // alice -> bob, 10% withheld for charlie
+        set_context_with_balance(accounts(0), 10 * NEAR);
+        contract.create_stream(
+            accounts(1),
+            U128(NEAR),
+            U64(start + 1),
+            U64(start + 11),
+            false,
+            true,
+            None,
+        );
+        contract.set_withholding(U64(1), Some(accounts(2)), 1_000);
+
+        set_context_with_balance_timestamp(accounts(1), 0, start + 5);
+        contract.withdraw(U64(1), None);
+        set_context_with_balance_timestamp(accounts(1), 0, start + 11);
+        contract.withdraw(U64(1), None);
+
+        let statement = contract.get_receiver_statement(accounts(1), None);
+        assert_eq!(statement.received, U128(9 * NEAR));
+        assert_eq!(statement.withheld, U128(NEAR));
+        assert_eq!(statement.payments, U64(2));
+
+        let statement = contract.get_receiver_statement(accounts(1), Some(accounts(3)));
+        assert_eq!(statement.payments, U64(0));
+    }
+
+    #[test]
+    fn test_export_streams_round_trip() {
+        let mut contract = Contract::new();
+
+        // alice -> bob from 10 to 20 with a cliff at 12
+        set_context_with_balance_timestamp(accounts(0), 10 * NEAR, 0);
+        let stream_id = contract.create_stream(
+            accounts(1),
+            U128(NEAR),
+            U64(10),
+            U64(20),
+            true,
+            true,
+            Some(U64(12)),
+        );
+        contract.set_accrual_policy(stream_id, true, false);
+        contract.set_accrual_period(stream_id, Some(AccrualPeriod::Day));
+        contract.set_pause_quota(stream_id, Some(2));
+
+        // bob streams half of it on to charlie
+        set_context_with_balance_timestamp(accounts(1), 0, 0);
+        let child_id = contract.create_child_stream(stream_id, accounts(2), 5_000);
+
+        let streams = contract.export_streams(U64(0), U64(2));
+        assert_eq!(streams[0].cliff_time, Some(U64(12)));
+        assert!(streams[0].accrual_policy.unwrap().end_inclusive);
+        assert!(streams[0].accrual_period == Some(AccrualPeriod::Day));
+        assert!(streams[0].pause_quota.is_some());
+        assert!(streams[0].child_stream.is_some());
+        assert_eq!(streams[1].parent_stream_id, Some(stream_id));
+        assert_eq!(streams[1].stream.id, child_id.0);
+
+        // the exported json reads back to the same export
+        let json = near_sdk::serde_json::to_string(&streams).unwrap();
+        let imported: Vec<StreamExportView> = near_sdk::serde_json::from_str(&json).unwrap();
+        assert_eq!(near_sdk::serde_json::to_string(&imported).unwrap(), json);
+    }
+
+    #[test]
+    fn test_export_nonce_streams() {
+        let mut contract = Contract::new();
+
+        set_context_with_balance_timestamp(accounts(0), 10 * NEAR, 0);
+        contract.create_stream(
+            accounts(1),
+            U128(NEAR),
+            U64(10),
+            U64(20),
+            false,
+            false,
+            None,
+        );
+        let nonce_id = contract.create_stream_with_nonce(
+            accounts(2),
+            U128(NEAR),
+            U64(10),
+            U64(20),
+            false,
+            false,
+            None,
+            U64(7),
+        );
+
+        let streams = contract.export_streams(U64(0), U64(10));
+        let ids: Vec<u64> = streams.iter().map(|s| s.stream.id).collect();
+        assert_eq!(ids, vec![1, nonce_id.0]);
+        assert_eq!(
+            contract.export_streams(U64(1), U64(10))[0].stream.id,
+            nonce_id.0
+        );
+    }
+
+    fn set_context_with_balance_timestamp(predecessor: AccountId, amount: Balance, ts: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(ts * 1e9 as u64);
+        testing_env!(builder.build());
+    }
 }
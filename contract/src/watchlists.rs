@@ -0,0 +1,193 @@
+use crate::views::{stream_status, StreamStatus};
+use crate::*;
+
+pub const MAX_WATCHLIST_LEN: u64 = 100;
+
+// one entry of `get_watchlist_feed`
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WatchedStreamView {
+    pub stream_id: U64,
+    pub status: StreamStatus,
+    pub balance: U128,
+    pub accrued: U128, // streamed to the receiver and not withdrawn yet
+    pub stop_reason: Option<String>,
+}
+
+#[near_bindgen]
+impl Contract {
+    // Adds streams to the caller's watchlist, any stream can be watched. The
+    // storage is paid from the attached deposit and the rest is refunded.
+    #[payable]
+    pub fn watch_streams(&mut self, stream_ids: Vec<U64>) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+
+        let mut watchlist = self.watchlists.get(&account_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::WatchlistsInner {
+                account_hash: env::sha256_array(account_id.as_bytes()),
+            })
+        });
+        for stream_id in stream_ids {
+            require!(self.streams.contains_key(&stream_id.0), "Invalid stream id");
+            watchlist.insert(&stream_id.0);
+        }
+        require!(
+            watchlist.len() <= MAX_WATCHLIST_LEN,
+            "Too many streams in the watchlist"
+        );
+        self.watchlists.insert(&account_id, &watchlist);
+        log_event!("Watchlist updated: {} {}", account_id, watchlist.len());
+
+        let cost = Balance::from(env::storage_usage().saturating_sub(initial_storage))
+            * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() >= cost,
+            "The amount provided is not enough for the watchlist storage"
+        );
+        if env::attached_deposit() > cost {
+            Promise::new(account_id).transfer(env::attached_deposit() - cost);
+        }
+    }
+
+    // Removes streams from the caller's watchlist and refunds the storage freed
+    pub fn unwatch_streams(&mut self, stream_ids: Vec<U64>) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage = env::storage_usage();
+
+        let mut watchlist = self
+            .watchlists
+            .get(&account_id)
+            .expect("No streams to unwatch");
+        for stream_id in stream_ids {
+            watchlist.remove(&stream_id.0);
+        }
+        if watchlist.is_empty() {
+            self.watchlists.remove(&account_id);
+        } else {
+            self.watchlists.insert(&account_id, &watchlist);
+        }
+        log_event!("Watchlist updated: {} {}", account_id, watchlist.len());
+
+        let refund = Balance::from(initial_storage.saturating_sub(env::storage_usage()))
+            * env::storage_byte_cost();
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+    }
+
+    pub fn get_watchlist(&self, account_id: AccountId) -> Vec<U64> {
+        self.watchlists
+            .get(&account_id)
+            .map_or(vec![], |watchlist| watchlist.iter().map(U64).collect())
+    }
+
+    // status of the streams in the account's watchlist at the current time
+    pub fn get_watchlist_feed(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+    ) -> Vec<WatchedStreamView> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        let current_timestamp: u64 = current_time();
+
+        self.watchlists
+            .get(&account_id)
+            .iter()
+            .flat_map(|watchlist| watchlist.iter())
+            // skip to start
+            .skip(start as usize)
+            // take the first `limit` elements in the vec
+            .take(limit.unwrap_or(U64(50)).0 as usize)
+            .map(|id| {
+                let stream = self.internal_get_stream(id);
+                let has_ended = self.internal_has_ended(&stream, current_timestamp);
+                let accrued = if stream.is_cancelled {
+                    0
+                } else {
+                    let vested_time = self
+                        .internal_vested_time(&stream, current_timestamp)
+                        .min(stream.end_time)
+                        .max(stream.withdraw_time);
+                    let accrued_until = if stream.is_paused {
+                        stream.paused_time.min(vested_time)
+                    } else {
+                        vested_time
+                    };
+                    self.internal_streamed_amount(&stream, stream.withdraw_time, accrued_until)
+                };
+                WatchedStreamView {
+                    stream_id: U64(id),
+                    status: stream_status(&stream, current_timestamp, has_ended),
+                    balance: U128(stream.balance),
+                    accrued: U128(accrued),
+                    stop_reason: self.stop_reasons.get(&id),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const NEAR: u128 = 1000000000000000000000000;
+
+    fn set_context(predecessor: AccountId, amount: Balance, timestamp: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(predecessor);
+        builder.attached_deposit(amount);
+        builder.block_timestamp(timestamp * 1e9 as u64);
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn watchlist_feed() {
+        let mut contract = Contract::new();
+
+        // alice -> bob twice, the second is paused at 4
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+        set_context(accounts(0), NEAR, 4);
+        contract.pause(U64(2), Some("audit".to_string()));
+
+        // dave, an auditor, watches both and pays for the storage
+        set_context(accounts(3), NEAR, 6);
+        contract.watch_streams(vec![U64(1), U64(2)]);
+        assert_eq!(get_created_receipts().len(), 1);
+        assert_eq!(contract.get_watchlist(accounts(3)).len(), 2);
+
+        let feed = contract.get_watchlist_feed(accounts(3), None, None);
+        assert_eq!(feed.len(), 2);
+        let paused = feed.iter().find(|entry| entry.stream_id == U64(2)).unwrap();
+        assert_eq!(paused.status, StreamStatus::Paused);
+        assert_eq!(paused.accrued, U128(3));
+        assert_eq!(paused.stop_reason, Some("audit".to_string()));
+        let active = feed.iter().find(|entry| entry.stream_id == U64(1)).unwrap();
+        assert_eq!(active.status, StreamStatus::Active);
+        assert_eq!(active.accrued, U128(5));
+
+        // unwatching refunds the storage
+        set_context(accounts(3), 0, 6);
+        contract.unwatch_streams(vec![U64(1), U64(2)]);
+        assert!(contract.get_watchlist(accounts(3)).is_empty());
+        assert_eq!(get_created_receipts().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount provided is not enough for the watchlist storage")]
+    fn watchlist_storage_not_paid() {
+        let mut contract = Contract::new();
+
+        set_context(accounts(0), 10, 0);
+        contract.create_stream(accounts(1), U128(1), U64(1), U64(11), true, false, None);
+
+        set_context(accounts(3), 0, 0);
+        contract.watch_streams(vec![U64(1)]);
+    }
+}